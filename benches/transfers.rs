@@ -0,0 +1,365 @@
+//! Transfer timings against `MockJtag` behind a model of the USB link
+//!
+//! Run with `cargo bench --bench transfers`. Replies become readable at the
+//! TCK rate after their command is written, and every read call costs a
+//! USB round trip. Absolute numbers only say something about the model,
+//! compare the rows of a case with each other.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ftdi_playground::{ftdi, FtdiProbe, JtagAdapter, JtagError, MockJtag, MockTap, MpsseIo};
+
+/// TCK the adapter is set to, which also paces the replies
+const TCK_HZ: u32 = 30_000_000;
+
+/// Cost of a read call that returns data, one high-speed USB microframe
+const ROUND_TRIP: Duration = Duration::from_micros(125);
+
+/// `System` counting its allocations
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Part of a reply, readable from `start` on at the TCK rate
+#[derive(Debug)]
+struct Reply {
+    start: Instant,
+    data: VecDeque<u8>,
+    /// Bytes of `data` already read
+    taken: usize,
+}
+
+impl Reply {
+    /// Number of bytes readable now
+    fn ready(&self, now: Instant) -> usize {
+        let elapsed = now.saturating_duration_since(self.start);
+        let clocked = elapsed.as_nanos() * u128::from(TCK_HZ) / 8 / 1_000_000_000;
+        (clocked as usize)
+            .saturating_sub(self.taken)
+            .min(self.data.len())
+    }
+}
+
+/// USB traffic so far
+#[derive(Clone, Copy, Debug, Default)]
+struct Stats {
+    reads: usize,
+    bytes_read: usize,
+    bytes_written: usize,
+}
+
+/// `MockJtag` whose replies trickle in at the TCK rate
+#[derive(Debug)]
+struct Usb {
+    mock: MockJtag,
+    replies: VecDeque<Reply>,
+    /// Extra delay before a reply starts, e.g. a slow target
+    delay: Duration,
+    /// Whether replies trickle in at the TCK rate or are readable at once
+    paced: bool,
+    round_trip: Duration,
+    stats: Rc<Cell<Stats>>,
+}
+
+impl Usb {
+    fn new(taps: Vec<MockTap>) -> Self {
+        Self {
+            mock: MockJtag::new(taps),
+            replies: VecDeque::new(),
+            delay: Duration::from_secs(0),
+            paced: true,
+            round_trip: ROUND_TRIP,
+            stats: Rc::new(Cell::new(Stats::default())),
+        }
+    }
+
+    /// Start every reply `delay` after its command, like a slow target
+    fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Deliver replies at once and make read calls free, leaving only the
+    /// CPU time of the host side
+    fn instant(mut self) -> Self {
+        self.paced = false;
+        self.round_trip = Duration::from_secs(0);
+        self
+    }
+
+    fn count(&self, f: impl FnOnce(&mut Stats)) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+}
+
+impl Read for Usb {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let now = Instant::now();
+        let mut n = 0;
+        while let Some(reply) = self.replies.front_mut() {
+            let ready = if self.paced {
+                reply.ready(now)
+            } else {
+                reply.data.len()
+            };
+            let ready = ready.min(buf.len() - n);
+            for (dst, src) in buf[n..n + ready].iter_mut().zip(reply.data.drain(..ready)) {
+                *dst = src;
+            }
+            reply.taken += ready;
+            n += ready;
+            if !reply.data.is_empty() {
+                break;
+            }
+            self.replies.pop_front();
+        }
+        if n > 0 {
+            thread::sleep(self.round_trip);
+            self.count(|stats| {
+                stats.reads += 1;
+                stats.bytes_read += n;
+            });
+        }
+        Ok(n)
+    }
+}
+
+impl Write for Usb {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.mock.write(buf)?;
+        self.count(|stats| stats.bytes_written += n);
+
+        let mut data = Vec::new();
+        self.mock.read_to_end(&mut data)?;
+        if !data.is_empty() {
+            // A reply is clocked after the ones queued before it
+            let mut start = Instant::now() + self.delay;
+            if let Some(last) = self.replies.back() {
+                let queued = Duration::from_nanos(
+                    (last.taken + last.data.len()) as u64 * 8 * 1_000_000_000 / u64::from(TCK_HZ),
+                );
+                start = start.max(last.start + queued);
+            }
+            self.replies.push_back(Reply {
+                start,
+                data: data.into(),
+                taken: 0,
+            });
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.mock.flush()
+    }
+}
+
+impl MpsseIo for Usb {
+    fn usb_reset(&mut self) -> Result<(), JtagError> {
+        self.mock.usb_reset()
+    }
+
+    fn set_latency_timer(&mut self, ms: u8) -> Result<(), JtagError> {
+        self.mock.set_latency_timer(ms)
+    }
+
+    fn enter_mpsse(&mut self, direction: u8) -> Result<(), JtagError> {
+        self.mock.enter_mpsse(direction)
+    }
+
+    fn enter_bitbang(&mut self, direction: u8) -> Result<(), JtagError> {
+        self.mock.enter_bitbang(direction)
+    }
+
+    fn reset_bitmode(&mut self) -> Result<(), JtagError> {
+        self.mock.reset_bitmode()
+    }
+
+    fn purge(&mut self) -> Result<(), JtagError> {
+        self.replies.clear();
+        self.mock.purge()
+    }
+
+    fn chip_type(&self) -> ftdi::ChipType {
+        self.mock.chip_type()
+    }
+}
+
+/// Adapter attached to `usb` at `TCK_HZ`, with its USB traffic counters
+fn attach(usb: Usb) -> Result<(JtagAdapter<Usb>, Rc<Cell<Stats>>), JtagError> {
+    let stats = usb.stats.clone();
+    let mut adapter = JtagAdapter::from_io(usb);
+    adapter.set_read_timeout(Duration::from_secs(1));
+    adapter.attach()?;
+    adapter.set_clock(TCK_HZ)?;
+    adapter.reset()?;
+    adapter.flush()?;
+    Ok((adapter, stats))
+}
+
+/// `attach` for an `FtdiProbe`
+fn attach_probe(usb: Usb) -> Result<(FtdiProbe<Usb>, Rc<Cell<Stats>>), JtagError> {
+    let stats = usb.stats.clone();
+    let mut probe = FtdiProbe::from_io(usb);
+    probe.set_read_timeout(Duration::from_secs(1));
+    probe.attach()?;
+    probe.set_clock(TCK_HZ)?;
+    probe.reset()?;
+    probe.flush()?;
+    Ok((probe, stats))
+}
+
+/// A RISC-V DTM with `dtmcs` and `dmi`
+fn dtm() -> Result<MockTap, JtagError> {
+    MockTap::new(Some(0x1000563d), 5)?
+        .with_register(0x10, 32)?
+        .with_register(0x11, 41)
+}
+
+/// CPU time used by the calling thread so far, on Linux
+fn cpu_time() -> Option<Duration> {
+    let schedstat = fs::read_to_string("/proc/thread-self/schedstat").ok()?;
+    let nanos = schedstat.split_whitespace().next()?.parse().ok()?;
+    Some(Duration::from_nanos(nanos))
+}
+
+/// Time `runs` calls of `f`, printing the time, allocations and USB traffic per call
+fn measure<F>(name: &str, runs: usize, stats: &Cell<Stats>, mut f: F) -> Result<(), JtagError>
+where
+    F: FnMut() -> Result<(), JtagError>,
+{
+    let before = stats.get();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let t0 = Instant::now();
+    for _ in 0..runs {
+        f()?;
+    }
+    let elapsed = t0.elapsed() / runs as u32;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    let after = stats.get();
+    println!(
+        "{:<40} {:>10.1?} {:>6} allocs {:>6} reads {:>8} B in {:>8} B out",
+        name,
+        elapsed,
+        allocations / runs,
+        (after.reads - before.reads) / runs,
+        (after.bytes_read - before.bytes_read) / runs,
+        (after.bytes_written - before.bytes_written) / runs,
+    );
+    Ok(())
+}
+
+/// Large captures and 32-bit reads with and without a minimum read size
+fn min_read_size() -> Result<(), JtagError> {
+    println!("minimum read size");
+    let data = vec![0x5a; 256 * 1024];
+    for &size in &[0, 4096, 64 * 1024] {
+        let (mut adapter, stats) = attach(Usb::new(vec![dtm()?]))?;
+        adapter.set_min_read_size(size);
+        let name = format!("transfer_dr 256 KiB, min {}", size);
+        measure(&name, 4, &stats, || {
+            adapter.transfer_dr(&data, data.len() * 8).map(drop)
+        })?;
+        let name = format!("transfer_dr 32 bits, min {}", size);
+        measure(&name, 200, &stats, || {
+            adapter.transfer_dr(&[0; 4], 32).map(drop)
+        })?;
+    }
+    Ok(())
+}
+
+/// 32-bit reads on a single TAP through the fast path and the general one
+fn single_tap_reads() -> Result<(), JtagError> {
+    println!("single-TAP 32-bit reads, host side only");
+    let (mut probe, stats) = attach_probe(Usb::new(vec![dtm()?]).instant())?;
+    probe.select_target(0x1000563d)?;
+    measure("read_register32", 10_000, &stats, || {
+        probe.read_register32(0x10).map(drop)
+    })?;
+    measure("read_register, 32 bits", 10_000, &stats, || {
+        probe.read_register(0x10, 32).map(drop)
+    })?;
+    Ok(())
+}
+
+/// Streams of 32-bit writes with and without reading back TDO
+fn void_writes() -> Result<(), JtagError> {
+    println!("1000 dtmcs writes");
+    let (mut probe, stats) = attach_probe(Usb::new(vec![dtm()?]))?;
+    probe.select_target(0x1000563d)?;
+    measure("exchange_register32", 10, &stats, || {
+        for i in 0..1000 {
+            probe.exchange_register32(0x10, i)?;
+        }
+        probe.flush()
+    })?;
+    measure("write_register32_void", 10, &stats, || {
+        for i in 0..1000 {
+            probe.write_register32_void(0x10, i)?;
+        }
+        probe.flush()
+    })?;
+    Ok(())
+}
+
+/// CPU used while waiting for replies that start late
+fn slow_replies() -> Result<(), JtagError> {
+    println!("CPU while waiting for a 32-bit reply");
+    for &ms in &[1, 10, 50] {
+        let delay = Duration::from_millis(ms);
+        let (mut adapter, _) = attach(Usb::new(vec![dtm()?]).with_delay(delay))?;
+        let runs = 20;
+        let cpu = cpu_time();
+        let t0 = Instant::now();
+        for _ in 0..runs {
+            adapter.transfer_dr(&[0; 4], 32)?;
+        }
+        let wall = t0.elapsed() / runs;
+        match (cpu, cpu_time()) {
+            (Some(before), Some(after)) => {
+                let cpu = (after - before) / runs;
+                println!(
+                    "reply after {:>2} ms {:>24.1?} wall {:>10.1?} CPU ({:.1}%)",
+                    ms,
+                    wall,
+                    cpu,
+                    cpu.as_secs_f64() * 100.0 / wall.as_secs_f64()
+                );
+            }
+            _ => println!("reply after {:>2} ms {:>24.1?} wall, no CPU time", ms, wall),
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), JtagError> {
+    min_read_size()?;
+    single_tap_reads()?;
+    void_writes()?;
+    slow_replies()?;
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use ftdi_playground::{Dtmcs, FtdiProbe};
+
+fn main() {
+    env_logger::init();
+
+    println!("Opening probe...");
+
+    let mut probe = match FtdiProbe::open(0x0403, 0x6010) {
+        Ok(probe) => probe,
+        Err(e) => {
+            println!("Cannot find/open device: {:?}", e);
+            return;
+        }
+    };
+    probe.set_attach_ir(Some((vec![0x10], 5)));
+    probe.attach().unwrap();
+    probe.idle(42).unwrap();
+    probe.select_target(0x1000563d).unwrap();
+
+    probe.set_idle_cycles(8);
+
+    let r = probe.read_register32(0x01).unwrap();
+    println!("idcode: {:08x}", r);
+
+    let r = probe.read_register32(0x10).unwrap();
+    println!("dtmcs: {}", Dtmcs::from_raw(r));
+    let r = probe.read_register32(0x11).unwrap();
+    println!("dmi: {:08x}", r);
+    let reset = Dtmcs::dmireset() | Dtmcs::dmihardreset();
+    probe.write_register32_void(0x10, reset).unwrap();
+    let r = probe.read_register32(0x10).unwrap();
+    println!("dtmcs: {}", Dtmcs::from_raw(r));
+}
@@ -0,0 +1,119 @@
+//! `FtdiProbe` for use inside a tokio runtime
+
+use std::io;
+use std::panic;
+use std::sync::{Arc, Mutex};
+
+use crate::error::JtagError;
+use crate::ftdi;
+use crate::jtag::JtagChainItem;
+use crate::mpsse::MpsseIo;
+use crate::probe::FtdiProbe;
+
+/// Async front end of an `FtdiProbe`
+///
+/// Every operation runs the blocking probe code on tokio's blocking thread
+/// pool, so the USB transfers and their busy-waits never stall the runtime.
+/// The commands are built exactly as on the sync path. Clones share the
+/// probe and their operations run one at a time.
+#[derive(Debug)]
+pub struct AsyncFtdiProbe<D: MpsseIo = ftdi::Device> {
+    probe: Arc<Mutex<FtdiProbe<D>>>,
+}
+
+impl<D: MpsseIo> Clone for AsyncFtdiProbe<D> {
+    fn clone(&self) -> Self {
+        Self {
+            probe: self.probe.clone(),
+        }
+    }
+}
+
+impl<D: MpsseIo + Send + 'static> AsyncFtdiProbe<D> {
+    pub fn new(probe: FtdiProbe<D>) -> Self {
+        Self {
+            probe: Arc::new(Mutex::new(probe)),
+        }
+    }
+
+    /// Run `f` on the probe from the blocking thread pool
+    ///
+    /// For the operations without an async wrapper of their own.
+    pub async fn with<T, F>(&self, f: F) -> Result<T, JtagError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut FtdiProbe<D>) -> Result<T, JtagError> + Send + 'static,
+    {
+        let probe = self.probe.clone();
+        match tokio::task::spawn_blocking(move || f(&mut probe.lock().unwrap())).await {
+            Ok(r) => r,
+            Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+            Err(e) => Err(io::Error::new(io::ErrorKind::Other, e).into()),
+        }
+    }
+
+    pub async fn attach(&self) -> Result<(), JtagError> {
+        self.with(|probe| probe.attach()).await
+    }
+
+    pub async fn set_clock(&self, hz: u32) -> Result<u32, JtagError> {
+        self.with(move |probe| probe.set_clock(hz)).await
+    }
+
+    pub async fn flush(&self) -> Result<(), JtagError> {
+        self.with(|probe| probe.flush()).await
+    }
+
+    pub async fn reset(&self) -> Result<(), JtagError> {
+        self.with(|probe| probe.reset()).await
+    }
+
+    pub async fn scan_chain(&self) -> Result<Vec<JtagChainItem>, JtagError> {
+        self.with(|probe| probe.scan_chain()).await
+    }
+
+    pub async fn select_target(&self, idcode: u32) -> Result<(), JtagError> {
+        self.with(move |probe| probe.select_target(idcode)).await
+    }
+
+    pub async fn select_target_by_index(&self, index: usize) -> Result<(), JtagError> {
+        self.with(move |probe| probe.select_target_by_index(index))
+            .await
+    }
+
+    pub async fn read_register(&self, address: u32, len: usize) -> Result<Vec<u8>, JtagError> {
+        self.with(move |probe| probe.read_register(address, len))
+            .await
+    }
+
+    pub async fn write_register(
+        &self,
+        address: u32,
+        data: Vec<u8>,
+        len: usize,
+    ) -> Result<Vec<u8>, JtagError> {
+        self.with(move |probe| probe.write_register(address, &data, len))
+            .await
+    }
+
+    pub async fn read_register32(&self, address: u32) -> Result<u32, JtagError> {
+        self.with(move |probe| probe.read_register32(address)).await
+    }
+
+    pub async fn exchange_register32(&self, address: u32, value: u32) -> Result<u32, JtagError> {
+        self.with(move |probe| probe.exchange_register32(address, value))
+            .await
+    }
+
+    pub async fn write_register32_void(&self, address: u32, value: u32) -> Result<(), JtagError> {
+        self.with(move |probe| probe.write_register32_void(address, value))
+            .await
+    }
+
+    /// Get the sync probe back, `None` while clones of this handle exist
+    pub fn into_inner(self) -> Option<FtdiProbe<D>> {
+        Arc::try_unwrap(self.probe)
+            .ok()
+            .map(|probe| probe.into_inner().unwrap())
+    }
+}
@@ -0,0 +1,277 @@
+//! MPSSE emulation for chips driven in synchronous bitbang mode
+//!
+//! Chips without an MPSSE engine, such as the FT232R, can still run JTAG by
+//! writing every TCK edge as a pin byte. In synchronous bitbang mode the chip
+//! answers each written byte with the pin levels sampled just before applying
+//! it. `Bitbang` turns the MPSSE commands the adapter queues into such pin
+//! bytes and rebuilds the replies MPSSE would have sent, so everything above
+//! the transport works unchanged.
+//!
+//! The pins keep their MPSSE roles: TCK on bit 0, TDI on 1, TDO on 2 and TMS
+//! on 3, the default wiring of OpenOCD's ft232r driver. TCK runs as fast as
+//! the pin bytes go out, clock commands are ignored, and there is no ACBUS.
+
+use std::collections::VecDeque;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::JtagError;
+use crate::mpsse::MpsseIo;
+
+const TCK: u8 = 1 << 0;
+const TDI: u8 = 1 << 1;
+const TDO: u8 = 1 << 2;
+const TMS: u8 = 1 << 3;
+
+/// Pin bytes written before reading their echo back
+///
+/// Small enough for the FT232R receive buffer, so the chip never stalls
+/// waiting for the host to read.
+const CHUNK: usize = 64;
+
+/// Where a bit of an emulated reply comes from
+#[derive(Clone, Copy, Debug)]
+enum Sample {
+    /// TDO as read back for the pin byte at this index
+    Tdo(usize),
+    /// A known level, TDI while loopback is on
+    Level(bool),
+}
+
+/// An emulated reply byte
+#[derive(Clone, Debug)]
+enum Reply {
+    /// Bits shifted in from the top, as bit and TMS shifts return them
+    Bits(Vec<Sample>),
+    /// All pins as read back for the pin byte at this index
+    Pins(usize),
+    Byte(u8),
+}
+
+#[derive(Debug)]
+pub(crate) struct Bitbang {
+    output: u8,
+    direction: u8,
+    loopback: bool,
+    /// Start of a command still missing some of its bytes
+    input: Vec<u8>,
+    /// Emulated replies not read yet
+    replies: VecDeque<u8>,
+}
+
+impl Bitbang {
+    pub fn new(direction: u8) -> Self {
+        Self {
+            output: TMS,
+            direction,
+            loopback: false,
+            input: Vec::new(),
+            replies: VecDeque::new(),
+        }
+    }
+
+    /// Take emulated reply bytes, like `Read::read` on an MPSSE device
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let n = buf.len().min(self.replies.len());
+        for (dst, src) in buf.iter_mut().zip(self.replies.drain(..n)) {
+            *dst = src;
+        }
+        n
+    }
+
+    /// Drop partial commands and unread replies
+    pub fn purge(&mut self) {
+        self.input.clear();
+        self.replies.clear();
+    }
+
+    /// Execute MPSSE `commands` on a device in synchronous bitbang mode
+    pub fn transfer<D: MpsseIo>(
+        &mut self,
+        device: &mut D,
+        commands: &[u8],
+        timeout: Duration,
+    ) -> Result<(), JtagError> {
+        self.input.extend_from_slice(commands);
+
+        let mut pins = Vec::new();
+        let mut echo = Vec::new();
+        let mut replies = Vec::new();
+        while let Some(len) = command_len(&self.input) {
+            let command: Vec<u8> = self.input.drain(..len).collect();
+            let direction = self.direction;
+            self.translate(&command, &mut pins, &mut replies);
+
+            // The pin directions are part of the bit mode
+            if self.direction != direction {
+                exchange(device, &pins[echo.len()..], &mut echo, timeout)?;
+                device.enter_bitbang(self.direction)?;
+            }
+        }
+        exchange(device, &pins[echo.len()..], &mut echo, timeout)?;
+
+        for reply in replies {
+            let byte = match reply {
+                Reply::Bits(samples) => samples.iter().fold(0, |byte, sample| {
+                    let bit = match *sample {
+                        Sample::Tdo(i) => echo[i] & TDO != 0,
+                        Sample::Level(level) => level,
+                    };
+                    byte >> 1 | (bit as u8) << 7
+                }),
+                Reply::Pins(i) => echo[i],
+                Reply::Byte(byte) => byte,
+            };
+            self.replies.push_back(byte);
+        }
+        Ok(())
+    }
+
+    /// One TCK cycle, returning where TDO is sampled
+    ///
+    /// TDO is read back with the rising edge byte, so it's the level the
+    /// TAP set up on the falling edge before it.
+    fn clock(&mut self, pins: &mut Vec<u8>, tms: bool, tdi: bool) -> Sample {
+        self.output &= !(TCK | TDI | TMS);
+        if tms {
+            self.output |= TMS;
+        }
+        if tdi {
+            self.output |= TDI;
+        }
+        pins.push(self.output);
+        pins.push(self.output | TCK);
+
+        if self.loopback {
+            Sample::Level(tdi)
+        } else {
+            Sample::Tdo(pins.len() - 1)
+        }
+    }
+
+    fn translate(&mut self, command: &[u8], pins: &mut Vec<u8>, replies: &mut Vec<Reply>) {
+        let op = command[0];
+        let read = op & 0x20 != 0;
+        let tms = self.output & TMS != 0;
+        let start = pins.len();
+        let mut samples = Vec::new();
+        match op {
+            // TMS shift, TDI held at bit 7
+            op if op < 0x80 && op & 0x40 != 0 => {
+                let bits = command[1] as usize + 1;
+                let tdi = command[2] & 0x80 != 0;
+                for i in 0..bits {
+                    samples.push(self.clock(pins, command[2] >> i & 1 != 0, tdi));
+                }
+                if read {
+                    replies.push(Reply::Bits(samples));
+                }
+            }
+            op if op < 0x80 && op & 0x02 != 0 => {
+                let bits = command[1] as usize + 1;
+                let tdi = command.get(2).copied().unwrap_or(0);
+                for i in 0..bits {
+                    samples.push(self.clock(pins, tms, tdi >> i & 1 != 0));
+                }
+                if read {
+                    replies.push(Reply::Bits(samples));
+                }
+            }
+            op if op < 0x80 => {
+                let n = u16::from_le_bytes([command[1], command[2]]) as usize + 1;
+                for i in 0..n {
+                    let tdi = command.get(3 + i).copied().unwrap_or(0);
+                    let samples = (0..8)
+                        .map(|bit| self.clock(pins, tms, tdi >> bit & 1 != 0))
+                        .collect();
+                    if read {
+                        replies.push(Reply::Bits(samples));
+                    }
+                }
+            }
+            0x80 => {
+                self.output = command[1] & !TCK;
+                self.direction = command[2];
+                pins.push(self.output);
+            }
+            0x81 => {
+                pins.push(self.output);
+                replies.push(Reply::Pins(pins.len() - 1));
+            }
+            0x82 => {}
+            0x83 => replies.push(Reply::Byte(0)),
+            0x84 => self.loopback = true,
+            0x85 => self.loopback = false,
+            // Clocks without data
+            0x8e => {
+                for _ in 0..=command[1] {
+                    self.clock(pins, tms, false);
+                }
+            }
+            0x8f => {
+                let n = u16::from_le_bytes([command[1], command[2]]) as usize + 1;
+                for _ in 0..n * 8 {
+                    self.clock(pins, tms, false);
+                }
+            }
+            0x86 | 0x87 | 0x8a | 0x8b | 0x8c | 0x8d | 0x96 | 0x97 => {}
+            // Bad command, echoed like MPSSE does
+            op => replies.extend(vec![Reply::Byte(0xfa), Reply::Byte(op)]),
+        }
+        // Park TCK low between commands
+        if pins.len() > start && pins[pins.len() - 1] & TCK != 0 {
+            pins.push(self.output);
+        }
+    }
+}
+
+/// Length of the command at the start of `input`, `None` if it's incomplete
+fn command_len(input: &[u8]) -> Option<usize> {
+    let op = *input.first()?;
+    let len = match op {
+        op if op < 0x80 && op & 0x40 != 0 => 3,
+        // Bit shift, the data byte only comes with TDI output
+        op if op < 0x80 && op & 0x02 != 0 => 2 + (op & 0x10 != 0) as usize,
+        op if op < 0x80 => {
+            let n = u16::from_le_bytes([*input.get(1)?, *input.get(2)?]) as usize + 1;
+            3 + if op & 0x10 != 0 { n } else { 0 }
+        }
+        0x80 | 0x82 | 0x86 | 0x8f => 3,
+        0x8e => 2,
+        _ => 1,
+    };
+    if input.len() < len {
+        return None;
+    }
+    Some(len)
+}
+
+/// Write `pins` and append the bytes read back for them to `echo`
+fn exchange<D: MpsseIo>(
+    device: &mut D,
+    pins: &[u8],
+    echo: &mut Vec<u8>,
+    timeout: Duration,
+) -> Result<(), JtagError> {
+    for chunk in pins.chunks(CHUNK) {
+        device.write_all(chunk)?;
+
+        let expected = echo.len() + chunk.len();
+        let mut buf = [0; CHUNK];
+        let t0 = Instant::now();
+        while echo.len() < expected {
+            let n = device.read(&mut buf[..expected - echo.len()])?;
+            if n == 0 {
+                if t0.elapsed() > timeout {
+                    return Err(JtagError::Timeout {
+                        expected: chunk.len(),
+                        got: chunk.len() - (expected - echo.len()),
+                    });
+                }
+                thread::sleep(Duration::from_micros(50));
+            }
+            echo.extend_from_slice(&buf[..n]);
+        }
+    }
+    Ok(())
+}
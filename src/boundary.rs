@@ -0,0 +1,368 @@
+//! Boundary scan pin access (IEEE 1149.1 EXTEST and SAMPLE/PRELOAD)
+
+use bitvec::order::Lsb0;
+use bitvec::slice::BitSlice;
+use bitvec::vec::BitVec;
+
+use crate::error::JtagError;
+use crate::ftdi;
+use crate::jtag::JtagChainItem;
+use crate::mpsse::MpsseIo;
+use crate::probe::FtdiProbe;
+
+/// Drive and sample the pins of the selected target through its boundary
+/// register
+///
+/// The boundary register length and instruction opcodes come from the
+/// device's BSDL file (`BOUNDARY_LENGTH`, `INSTRUCTION_OPCODE`). Cell `n` of
+/// the BSDL is bit `n` of the patterns and samples, bit 0 being the cell
+/// closest to TDO. Which cells are inputs, outputs or control cells is up
+/// to the caller.
+#[derive(Debug)]
+pub struct BoundaryScan<D: MpsseIo = ftdi::Device> {
+    probe: FtdiProbe<D>,
+    bsr_len: usize,
+    extest: u32,
+    sample: Option<u32>,
+}
+
+impl<D: MpsseIo> BoundaryScan<D> {
+    /// The probe must be attached with the device selected as the target
+    pub fn new(probe: FtdiProbe<D>, bsr_len: usize, extest: u32) -> Result<Self, JtagError> {
+        if bsr_len == 0 {
+            return Err(JtagError::InvalidInput(
+                "boundary register length must not be zero",
+            ));
+        }
+        Ok(Self {
+            probe,
+            bsr_len,
+            extest,
+            sample: None,
+        })
+    }
+
+    /// Set the SAMPLE/PRELOAD opcode, needed by `sample` and `preload`
+    pub fn set_sample_opcode(&mut self, opcode: u32) {
+        self.sample = Some(opcode);
+    }
+
+    pub fn bsr_len(&self) -> usize {
+        self.bsr_len
+    }
+
+    pub fn into_probe(self) -> FtdiProbe<D> {
+        self.probe
+    }
+
+    /// Load EXTEST, apply `pattern` to the pins and return the cells
+    /// captured before it
+    ///
+    /// The capture happens in CAPTURE-DR, ahead of the shift, so input cells
+    /// reflect the pins as driven by the previous pattern. Load the safe
+    /// output state with `preload` before the first call, the pins switch
+    /// to the boundary register as soon as EXTEST is in IR.
+    pub fn extest(&mut self, pattern: &BitSlice<Lsb0, u8>) -> Result<BitVec<Lsb0, u8>, JtagError> {
+        self.check_pattern(pattern)?;
+        self.probe.exchange_register_bits(self.extest, pattern)
+    }
+
+    /// Load SAMPLE/PRELOAD and return the pins in normal operation
+    ///
+    /// The boundary register is shifted with zeros, which also preloads
+    /// them into the update latches.
+    pub fn sample(&mut self) -> Result<BitVec<Lsb0, u8>, JtagError> {
+        let zeros = BitVec::<Lsb0, u8>::repeat(false, self.bsr_len);
+        self.preload(&zeros)
+    }
+
+    /// Load SAMPLE/PRELOAD, latch `pattern` for a later EXTEST and return
+    /// the pins in normal operation
+    pub fn preload(&mut self, pattern: &BitSlice<Lsb0, u8>) -> Result<BitVec<Lsb0, u8>, JtagError> {
+        self.check_pattern(pattern)?;
+        let opcode = self
+            .sample
+            .ok_or(JtagError::InvalidInput("SAMPLE/PRELOAD opcode not set"))?;
+        self.probe.exchange_register_bits(opcode, pattern)
+    }
+
+    fn check_pattern(&self, pattern: &BitSlice<Lsb0, u8>) -> Result<(), JtagError> {
+        if pattern.len() != self.bsr_len {
+            return Err(JtagError::InvalidInput(
+                "pattern length differs from the boundary register",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A device taking part in `interconnect_test`, described by its BSDL file
+#[derive(Clone, Debug)]
+pub struct InterconnectDevice {
+    /// Position in the scanned chain, 0 being the TAP closest to TDO
+    pub tap: usize,
+    pub bsr_len: usize,
+    pub extest: u32,
+    pub sample: u32,
+    /// Cell values outside the nets under test, usually the BSDL safe values
+    pub safe: BitVec<Lsb0, u8>,
+}
+
+/// Cell `cell` of the boundary register of `devices[device]`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BoundaryCell {
+    pub device: usize,
+    pub cell: usize,
+}
+
+/// A board net between an output cell and the input cells reading it
+#[derive(Clone, Debug)]
+pub struct Net {
+    pub driver: BoundaryCell,
+    /// Control cell of a tristate driver and the level enabling it
+    pub enable: Option<(BoundaryCell, bool)>,
+    pub receivers: Vec<BoundaryCell>,
+}
+
+/// Devices and nets of an `interconnect_test`
+#[derive(Clone, Debug, Default)]
+pub struct InterconnectConfig {
+    pub devices: Vec<InterconnectDevice>,
+    pub nets: Vec<Net>,
+}
+
+/// How a receiver of a net misbehaved
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetFault {
+    /// The receiver never changed, an open net or one shorted to a supply
+    StuckAt(bool),
+    /// The receiver also followed another net
+    Short { net: usize },
+    /// Wrong levels that neither explains
+    Mismatch,
+}
+
+/// A receiver that didn't read what its net was driven to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NetFailure {
+    /// Index of the net in `InterconnectConfig::nets`
+    pub net: usize,
+    pub receiver: BoundaryCell,
+    pub fault: NetFault,
+}
+
+/// Outcome of an `interconnect_test`
+#[derive(Clone, Debug, Default)]
+pub struct InterconnectReport {
+    /// Failures by net, then by receiver
+    pub failures: Vec<NetFailure>,
+}
+
+impl InterconnectReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl<D: MpsseIo> FtdiProbe<D> {
+    /// Test the nets between boundary-scan devices with walking ones and zeros
+    ///
+    /// The devices go to EXTEST together, the other TAPs of the scanned
+    /// chain to BYPASS. Each net is driven high with all others low, then low
+    /// with all others high, and every receiver is checked against the
+    /// levels driven. SAMPLE/PRELOAD loads the first pattern before EXTEST
+    /// hands the pins over. The devices are left in EXTEST driving their
+    /// safe values.
+    pub fn interconnect_test(
+        &mut self,
+        config: &InterconnectConfig,
+    ) -> Result<InterconnectReport, JtagError> {
+        let chain = self.chain();
+        check_interconnect(config, &chain)?;
+        let nets = config.nets.len();
+        if nets == 0 {
+            return Ok(InterconnectReport::default());
+        }
+
+        // Walking one, then walking zero
+        let level = |step: usize, net: usize| (net == step % nets) == (step < nets);
+        let patterns: Vec<_> = (0..2 * nets)
+            .map(|step| chain_dr(config, &chain, Some(&|net| level(step, net))))
+            .collect();
+        let safe = chain_dr(config, &chain, None);
+
+        let ir = chain_ir(config, &chain, |device| device.sample);
+        self.shift_ir(ir.as_slice(), ir.len())?;
+        self.shift_dr(patterns[0].as_slice(), patterns[0].len())?;
+        let ir = chain_ir(config, &chain, |device| device.extest);
+        self.shift_ir(ir.as_slice(), ir.len())?;
+
+        // Each scan captures the pins driven by the previous one
+        let mut captures = Vec::with_capacity(patterns.len());
+        for next in patterns[1..].iter().chain(Some(&safe)) {
+            captures.push(self.transfer_dr_bits(next)?);
+        }
+
+        let offsets = dr_offsets(config, &chain);
+        let mut report = InterconnectReport::default();
+        for (index, net) in config.nets.iter().enumerate() {
+            for &receiver in &net.receivers {
+                let bit = offsets[receiver.device] + receiver.cell;
+                let read: Vec<bool> = captures.iter().map(|capture| capture[bit]).collect();
+                let wrong: Vec<usize> = (0..read.len())
+                    .filter(|&step| read[step] != level(step, index))
+                    .collect();
+                if wrong.is_empty() {
+                    continue;
+                }
+
+                let mut faults = vec![];
+                if read.iter().all(|&level| level == read[0]) {
+                    faults.push(NetFault::StuckAt(read[0]));
+                } else {
+                    let mut shorts: Vec<usize> = wrong
+                        .iter()
+                        .map(|step| step % nets)
+                        .filter(|&other| other != index)
+                        .collect();
+                    shorts.dedup();
+                    faults.extend(shorts.into_iter().map(|net| NetFault::Short { net }));
+                }
+                if faults.is_empty() {
+                    faults.push(NetFault::Mismatch);
+                }
+                for fault in faults {
+                    log::debug!("net {} at {:?}: {:?}", index, receiver, fault);
+                    report.failures.push(NetFailure {
+                        net: index,
+                        receiver,
+                        fault,
+                    });
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// Check that `config` fits the scanned `chain`
+fn check_interconnect(
+    config: &InterconnectConfig,
+    chain: &[JtagChainItem],
+) -> Result<(), JtagError> {
+    if chain.is_empty() {
+        return Err(JtagError::InvalidInput(
+            "scan the chain before an interconnect test",
+        ));
+    }
+    for (i, device) in config.devices.iter().enumerate() {
+        let tap = chain.get(device.tap).ok_or(JtagError::InvalidInput(
+            "interconnect device outside the scanned chain",
+        ))?;
+        if config.devices[..i]
+            .iter()
+            .any(|other| other.tap == device.tap)
+        {
+            return Err(JtagError::InvalidInput(
+                "two interconnect devices on the same TAP",
+            ));
+        }
+        if device.bsr_len == 0 || device.safe.len() != device.bsr_len {
+            return Err(JtagError::InvalidInput(
+                "safe values differ in length from the boundary register",
+            ));
+        }
+        if (u64::from(device.extest) | u64::from(device.sample)) >> tap.irlen != 0 {
+            return Err(JtagError::InvalidInput(
+                "EXTEST or SAMPLE opcode wider than the IR",
+            ));
+        }
+    }
+
+    let cells = config.nets.iter().flat_map(|net| {
+        let enable = net.enable.map(|(cell, _)| cell);
+        Some(net.driver)
+            .into_iter()
+            .chain(enable)
+            .chain(net.receivers.iter().copied())
+    });
+    for cell in cells {
+        match config.devices.get(cell.device) {
+            Some(device) if cell.cell < device.bsr_len => {}
+            _ => {
+                return Err(JtagError::InvalidInput(
+                    "net cell outside the boundary registers",
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Interconnect device on chain position `tap`, if any
+fn device_at(config: &InterconnectConfig, tap: usize) -> Option<(usize, &InterconnectDevice)> {
+    config
+        .devices
+        .iter()
+        .enumerate()
+        .find(|(_, device)| device.tap == tap)
+}
+
+/// IR for the whole chain, `opcode` for the devices and BYPASS elsewhere
+fn chain_ir<F>(config: &InterconnectConfig, chain: &[JtagChainItem], opcode: F) -> BitVec<Lsb0, u8>
+where
+    F: Fn(&InterconnectDevice) -> u32,
+{
+    let mut ir = BitVec::new();
+    for (tap, item) in chain.iter().enumerate() {
+        let value = device_at(config, tap).map_or(u32::MAX, |(_, device)| opcode(device));
+        ir.extend((0..item.irlen).map(|i| value >> i & 1 != 0));
+    }
+    ir
+}
+
+/// DR for the whole chain, with every net at `level(net)` or all cells safe
+fn chain_dr(
+    config: &InterconnectConfig,
+    chain: &[JtagChainItem],
+    level: Option<&dyn Fn(usize) -> bool>,
+) -> BitVec<Lsb0, u8> {
+    let mut registers: Vec<_> = config
+        .devices
+        .iter()
+        .map(|device| device.safe.clone())
+        .collect();
+    if let Some(level) = level {
+        for (index, net) in config.nets.iter().enumerate() {
+            registers[net.driver.device].set(net.driver.cell, level(index));
+            if let Some((cell, enabled)) = net.enable {
+                registers[cell.device].set(cell.cell, enabled);
+            }
+        }
+    }
+
+    let mut dr = BitVec::new();
+    for tap in 0..chain.len() {
+        match device_at(config, tap) {
+            Some((index, _)) => dr.extend_from_slice(&registers[index]),
+            None => dr.push(false),
+        }
+    }
+    dr
+}
+
+/// Position of each device's boundary register in the chain DR
+fn dr_offsets(config: &InterconnectConfig, chain: &[JtagChainItem]) -> Vec<usize> {
+    let mut offsets = vec![0; config.devices.len()];
+    let mut offset = 0;
+    for tap in 0..chain.len() {
+        match device_at(config, tap) {
+            Some((index, device)) => {
+                offsets[index] = offset;
+                offset += device.bsr_len;
+            }
+            None => offset += 1,
+        }
+    }
+    offsets
+}
@@ -0,0 +1,151 @@
+//! Adapter profiles loaded from TOML
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+
+use crate::error::JtagError;
+use crate::ftdi;
+use crate::jtag::{ClockEdge, GpioInit, ResetConfig, ResetPin};
+use crate::probe::FtdiProbe;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid config: {0}")]
+    Toml(#[from] toml::de::Error),
+}
+
+/// Wiring and USB identity of an adapter
+///
+/// ```toml
+/// name = "Olimex ARM-USB-TINY-H"
+/// vid = 0x15ba
+/// pid = 0x002a
+///
+/// [gpio]
+/// output = 0x0808
+/// direction = 0x0a1b
+///
+/// [reset.trst]
+/// pin = 8
+/// active_high = false
+/// open_drain = false
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Config {
+    pub name: String,
+    pub vid: u16,
+    pub pid: u16,
+    /// Channel of a multi-channel chip, 0 for A to 3 for D
+    #[serde(default)]
+    pub channel: u8,
+    pub serial: Option<String>,
+    #[serde(default)]
+    pub gpio: GpioInit,
+    /// TCK frequency programmed by `attach`, 1 MHz if not set
+    pub clock_hz: Option<u32>,
+    #[serde(default)]
+    pub clock_edge: ClockEdge,
+    #[serde(default)]
+    pub reset: ResetConfig,
+    /// GPIO wired to RTCK, GPIOL3 (7) if not set
+    pub rtck_pin: Option<u8>,
+}
+
+impl Config {
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Bare FT2232H board with JTAG on channel A and no reset lines
+    pub fn ft2232h() -> Self {
+        Self {
+            name: "FT2232H".to_string(),
+            vid: 0x0403,
+            pid: 0x6010,
+            channel: 0,
+            serial: None,
+            gpio: GpioInit::default(),
+            clock_hz: None,
+            clock_edge: ClockEdge::default(),
+            reset: ResetConfig::default(),
+            rtck_pin: None,
+        }
+    }
+
+    /// Olimex ARM-USB-TINY-H, wired as in OpenOCD's `olimex-arm-usb-tiny-h.cfg`
+    pub fn olimex_arm_usb_tiny_h() -> Self {
+        Self {
+            name: "Olimex ARM-USB-TINY-H".to_string(),
+            vid: 0x15ba,
+            pid: 0x002a,
+            channel: 0,
+            serial: None,
+            // TMS high, red LED on
+            gpio: GpioInit {
+                output: 0x0808,
+                direction: 0x0a1b,
+            },
+            clock_hz: None,
+            clock_edge: ClockEdge::default(),
+            reset: ResetConfig {
+                trst: Some(ResetPin {
+                    pin: 8,
+                    active_high: false,
+                    open_drain: false,
+                }),
+                srst: Some(ResetPin {
+                    pin: 9,
+                    active_high: false,
+                    open_drain: true,
+                }),
+            },
+            rtck_pin: None,
+        }
+    }
+
+    fn interface(&self) -> Result<ftdi::Interface, JtagError> {
+        match self.channel {
+            0 => Ok(ftdi::Interface::A),
+            1 => Ok(ftdi::Interface::B),
+            2 => Ok(ftdi::Interface::C),
+            3 => Ok(ftdi::Interface::D),
+            _ => Err(JtagError::InvalidInput("channel must be 0 to 3")),
+        }
+    }
+}
+
+impl FtdiProbe {
+    /// Open the adapter described by `config` and apply its wiring
+    ///
+    /// The probe still has to be attached, which writes the GPIO state and
+    /// programs the clock.
+    pub fn open_with_config(config: &Config) -> Result<Self, JtagError> {
+        let mut builder = ftdi::Builder::new();
+        builder.set_interface(config.interface()?)?;
+        let device = match &config.serial {
+            Some(serial) => builder.usb_open_serial(config.vid, config.pid, serial)?,
+            None => builder.usb_open(config.vid, config.pid)?,
+        };
+
+        let mut probe = FtdiProbe::from_device(device);
+        probe.set_gpio_init(config.gpio)?;
+        probe.set_reset_config(config.reset)?;
+        probe.set_clock_edge(config.clock_edge);
+        if let Some(pin) = config.rtck_pin {
+            probe.set_rtck_pin(pin)?;
+        }
+        if let Some(hz) = config.clock_hz {
+            probe.set_attach_clock(hz);
+        }
+        Ok(probe)
+    }
+}
@@ -0,0 +1,63 @@
+use std::io;
+use thiserror::Error;
+
+use crate::ftdi;
+
+#[derive(Debug, Error)]
+pub enum JtagError {
+    #[error("FTDI error: {0}")]
+    Ftdi(#[from] ftdi::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("timed out waiting for the device reply: expected {expected} bytes, got {got}")]
+    Timeout { expected: usize, got: usize },
+    #[error("no device with serial {serial:?}, found {available:?}")]
+    SerialNotFound {
+        serial: String,
+        available: Vec<String>,
+    },
+    #[error("the requested target is not in the chain")]
+    TargetNotFound,
+    #[error("{count} TAPs match the requested target, select it by chain position")]
+    AmbiguousTarget { count: usize },
+    #[error("no target selected")]
+    TargetNotSelected,
+    #[error("invalid JTAG chain: {0}")]
+    InvalidChain(String),
+    #[error("expected {expected} TAPs in the chain, found {found}")]
+    UnexpectedChain { expected: usize, found: usize },
+    #[error("register address {addr:#x} out of range, max {max:#x} for irlen {irlen}")]
+    RegisterAddressOutOfRange { addr: u32, max: u32, irlen: usize },
+    #[error("short read: expected {expected} bytes, got {got}")]
+    ShortRead { expected: usize, got: usize },
+    #[error("read more data than expected, the command stream was out of sync")]
+    Desync,
+    #[error("the MPSSE engine didn't answer the sync command")]
+    SyncFailed,
+    #[error("TCK of {hz} Hz out of range {min}..={max} Hz")]
+    ClockOutOfRange { hz: u32, min: u32, max: u32 },
+    #[error("pins read {actual:#06x}, expected {expected:#06x} (mask {mask:#06x})")]
+    PinMismatch {
+        expected: u16,
+        actual: u16,
+        mask: u16,
+    },
+    #[error("register {addr:#x} read back {actual:02x?}, wrote {expected:02x?}")]
+    VerifyMismatch {
+        addr: u32,
+        expected: Vec<u8>,
+        actual: Vec<u8>,
+    },
+    #[error("BYPASS bits after the target are not zero")]
+    BypassMismatch,
+    #[error("DMI operation at {addr:#x} failed")]
+    DmiFailed { addr: u32 },
+    #[error("DMI still busy at {addr:#x} after raising the idle cycles")]
+    DmiBusy { addr: u32 },
+    #[error("system bus error {sberror} at offset {offset:#x}")]
+    SystemBusFailed { offset: u32, sberror: u8 },
+    #[error("system bus still busy at offset {offset:#x}")]
+    SystemBusBusy { offset: u32 },
+    #[error("input value invalid: {0}")]
+    InvalidInput(&'static str),
+}
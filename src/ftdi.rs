@@ -6,11 +6,13 @@ use libftdi1_sys as ffi;
 use std::convert::TryInto;
 use std::io::{self, ErrorKind, Read, Write};
 
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
 use std::{mem, ptr};
 use thiserror::Error;
 
 /// The target interface
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Interface {
     A,
     B,
@@ -31,6 +33,55 @@ impl Into<ffi::ftdi_interface> for Interface {
     }
 }
 
+/// FTDI chip family, told apart by the bcdDevice field of the USB descriptor
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChipType {
+    Am,
+    Bm,
+    /// FT2232C/D, MPSSE with a fixed 12 MHz base clock
+    Ft2232D,
+    Ft232R,
+    Ft2232H,
+    Ft4232H,
+    Ft232H,
+    Ft230X,
+    Unknown(u16),
+}
+
+impl ChipType {
+    pub fn from_bcd_device(bcd_device: u16) -> Self {
+        match bcd_device {
+            0x0200 => ChipType::Am,
+            0x0400 => ChipType::Bm,
+            0x0500 => ChipType::Ft2232D,
+            0x0600 => ChipType::Ft232R,
+            0x0700 => ChipType::Ft2232H,
+            0x0800 => ChipType::Ft4232H,
+            0x0900 => ChipType::Ft232H,
+            0x1000 => ChipType::Ft230X,
+            other => ChipType::Unknown(other),
+        }
+    }
+
+    /// H-series chips run MPSSE from 60 MHz and support adaptive clocking
+    pub fn is_h_series(self) -> bool {
+        matches!(
+            self,
+            ChipType::Ft2232H | ChipType::Ft4232H | ChipType::Ft232H
+        )
+    }
+
+    /// Whether the chip has an MPSSE engine, assumed for unknown chips
+    ///
+    /// The others can only run JTAG in bitbang mode.
+    pub fn has_mpsse(self) -> bool {
+        !matches!(
+            self,
+            ChipType::Am | ChipType::Bm | ChipType::Ft232R | ChipType::Ft230X
+        )
+    }
+}
+
 pub enum BitMode {
     Reset,
     Bitbang,
@@ -83,8 +134,170 @@ impl Builder {
         }
     }
 
-    pub fn usb_open(mut self, vendor: u16, product: u16) -> Result<Device> {
+    pub fn usb_open(self, vendor: u16, product: u16) -> Result<Device> {
         let result = unsafe { ffi::ftdi_usb_open(self.context, vendor as i32, product as i32) };
+        self.into_device(result)
+    }
+
+    /// Open the device with the given serial number
+    pub fn usb_open_serial(self, vendor: u16, product: u16, serial: &str) -> Result<Device> {
+        let serial =
+            CString::new(serial).map_err(|_| Error::InvalidInput("serial contains a NUL byte"))?;
+        let result = unsafe {
+            ffi::ftdi_usb_open_desc(
+                self.context,
+                vendor as i32,
+                product as i32,
+                ptr::null(),
+                serial.as_ptr(),
+            )
+        };
+        self.into_device(result)
+    }
+
+    /// Open the `index`-th device (counting from 0) with the given IDs
+    pub fn usb_open_index(self, vendor: u16, product: u16, index: u32) -> Result<Device> {
+        let result = unsafe {
+            ffi::ftdi_usb_open_desc_index(
+                self.context,
+                vendor as i32,
+                product as i32,
+                ptr::null(),
+                ptr::null(),
+                index,
+            )
+        };
+        self.into_device(result)
+    }
+
+    /// Serial numbers of all devices with the given IDs
+    ///
+    /// Devices whose strings can't be read (e.g. no permissions) are skipped.
+    pub fn usb_serials(&self, vendor: u16, product: u16) -> Result<Vec<String>> {
+        let devices = self.usb_find_all(vendor, product)?;
+        Ok(devices.into_iter().filter_map(|d| d.serial).collect())
+    }
+
+    /// Enumerate devices with the given IDs without opening them
+    ///
+    /// Zero IDs match the default FTDI vendor and product IDs.
+    pub fn usb_find_all(&self, vendor: u16, product: u16) -> Result<Vec<DeviceInfo>> {
+        let mut list = ptr::null_mut();
+        let result = unsafe {
+            ffi::ftdi_usb_find_all(self.context, &mut list, vendor as i32, product as i32)
+        };
+        if result < 0 {
+            return Err(Error::EnumerationFailed);
+        }
+
+        let mut devices = Vec::new();
+        let mut node = list;
+        while !node.is_null() {
+            let dev = unsafe { (*node).dev };
+            devices.push(self.device_info(dev as *mut c_void));
+            node = unsafe { (*node).next };
+        }
+        unsafe { ffi::ftdi_list_free(&mut list) };
+
+        Ok(devices)
+    }
+
+    /// Enumerate every device with the given vendor ID, whatever its product ID
+    ///
+    /// Unlike `usb_find_all`, which only knows the default FTDI product IDs
+    /// when one of the IDs is zero, this walks the whole libusb device list,
+    /// so clone cables with their own VID/PID pairs are found too.
+    pub fn usb_find_vendor(&self, vendor: u16) -> Result<Vec<DeviceInfo>> {
+        let mut list = ptr::null_mut();
+        let count = unsafe { libusb_get_device_list((*self.context).usb_ctx as _, &mut list) };
+        if count < 0 {
+            return Err(Error::EnumerationFailed);
+        }
+
+        let mut devices = Vec::new();
+        for i in 0..count as usize {
+            let dev = unsafe { *list.add(i) };
+            let mut descriptor = LibusbDeviceDescriptor::default();
+            if unsafe { libusb_get_device_descriptor(dev, &mut descriptor) } < 0 {
+                continue;
+            }
+            if descriptor.id_vendor == vendor {
+                devices.push(self.device_info(dev));
+            }
+        }
+        unsafe { libusb_free_device_list(list, 1) };
+
+        Ok(devices)
+    }
+
+    fn device_info(&self, dev: *mut c_void) -> DeviceInfo {
+        let mut descriptor = LibusbDeviceDescriptor::default();
+        unsafe { libusb_get_device_descriptor(dev, &mut descriptor) };
+
+        let mut manufacturer = [0 as c_char; 128];
+        let mut product = [0 as c_char; 128];
+        let mut serial = [0 as c_char; 128];
+        // Opens the device without claiming any interface, so it's safe for devices in use
+        let result = unsafe {
+            ffi::ftdi_usb_get_strings(
+                self.context,
+                dev as _,
+                manufacturer.as_mut_ptr(),
+                manufacturer.len() as i32,
+                product.as_mut_ptr(),
+                product.len() as i32,
+                serial.as_mut_ptr(),
+                serial.len() as i32,
+            )
+        };
+        let string = |buf: &[c_char]| {
+            if result == 0 {
+                let s = unsafe { CStr::from_ptr(buf.as_ptr()) };
+                Some(s.to_string_lossy().into_owned())
+            } else {
+                None
+            }
+        };
+
+        DeviceInfo {
+            vid: descriptor.id_vendor,
+            pid: descriptor.id_product,
+            chip_type: ChipType::from_bcd_device(descriptor.bcd_device),
+            bus: unsafe { libusb_get_bus_number(dev) },
+            address: unsafe { libusb_get_device_address(dev) },
+            manufacturer: string(&manufacturer),
+            product: string(&product),
+            serial: string(&serial),
+        }
+    }
+
+    /// Open a device the caller found through its own libusb enumeration
+    ///
+    /// # Safety
+    ///
+    /// `dev` must point to a valid `libusb_device` the caller holds a
+    /// reference to for the duration of the call.
+    pub unsafe fn usb_open_device(mut self, dev: *mut c_void) -> Result<Device> {
+        let result = ffi::ftdi_usb_open_dev(self.context, dev as _);
+        match result {
+            0 => Ok(Device {
+                context: mem::replace(&mut self.context, ptr::null_mut()),
+            }),
+            -3 => Err(Error::AccessFailed), // unable to config device
+            -4 => Err(Error::AccessFailed), // unable to open device
+            -5 => Err(Error::ClaimFailed),  // unable to claim device
+            -6 => Err(Error::RequestFailed), // reset failed
+            -7 => Err(Error::RequestFailed), // set baudrate failed
+            -8 => unreachable!("uninitialized context"), // ftdi context invalid
+            -9 => Err(Error::EnumerationFailed), // libusb_get_device_descriptor() failed
+            -10 => Err(Error::EnumerationFailed), // libusb_get_config_descriptor() failed
+            -11 => Err(Error::ClaimFailed), // libusb_detach_kernel_driver() failed
+            -12 => Err(Error::EnumerationFailed), // libusb_get_configuration() failed
+            _ => Err(Error::unknown(self.context)),
+        }
+    }
+
+    fn into_device(mut self, result: i32) -> Result<Device> {
         match result {
             0 => Ok(Device {
                 context: mem::replace(&mut self.context, ptr::null_mut()),
@@ -120,6 +333,36 @@ pub struct Device {
 }
 
 impl Device {
+    /// USB vendor and product IDs of the opened device
+    pub fn usb_ids(&self) -> (u16, u16) {
+        let descriptor = self.descriptor();
+        (descriptor.id_vendor, descriptor.id_product)
+    }
+
+    /// Chip family of the opened device
+    pub fn chip_type(&self) -> ChipType {
+        ChipType::from_bcd_device(self.descriptor().bcd_device)
+    }
+
+    fn descriptor(&self) -> LibusbDeviceDescriptor {
+        let mut descriptor = LibusbDeviceDescriptor::default();
+        unsafe {
+            let dev = libusb_get_device((*self.context).usb_dev as *mut c_void);
+            libusb_get_device_descriptor(dev, &mut descriptor);
+        }
+        descriptor
+    }
+
+    /// Interface the device was opened on
+    pub fn interface(&self) -> Interface {
+        match unsafe { (*self.context).interface } {
+            1 => Interface::B,
+            2 => Interface::C,
+            3 => Interface::D,
+            _ => Interface::A,
+        }
+    }
+
     pub fn usb_reset(&mut self) -> Result<()> {
         let result = unsafe { ffi::ftdi_usb_reset(self.context) };
         match result {
@@ -246,6 +489,107 @@ impl Device {
             )),
         }
     }
+
+    /// Read the raw EEPROM contents, 128 or 256 bytes depending on the chip
+    pub fn read_eeprom(&mut self) -> Result<Vec<u8>> {
+        self.fetch_eeprom()?;
+        let size = match self.eeprom_value(ffi::ftdi_eeprom_value::CHIP_SIZE)? {
+            // Unknown for blank EEPROMs
+            size if size > 0 => size,
+            _ => EEPROM_MAX_SIZE,
+        };
+
+        let mut buf = vec![0; size as usize];
+        let result = unsafe { ffi::ftdi_get_eeprom_buf(self.context, buf.as_mut_ptr(), size) };
+        match result {
+            0 => Ok(buf),
+            _ => Err(Error::unknown(self.context)),
+        }
+    }
+
+    /// Read the EEPROM and decode the common fields
+    ///
+    /// Fails on a blank EEPROM or a checksum mismatch.
+    pub fn eeprom_info(&mut self) -> Result<EepromInfo> {
+        self.fetch_eeprom()?;
+        let result = unsafe { ffi::ftdi_eeprom_decode(self.context, 0) };
+        if result != 0 {
+            return Err(Error::unknown(self.context));
+        }
+
+        let mut manufacturer = [0 as c_char; 128];
+        let mut product = [0 as c_char; 128];
+        let mut serial = [0 as c_char; 128];
+        let result = unsafe {
+            ffi::ftdi_eeprom_get_strings(
+                self.context,
+                manufacturer.as_mut_ptr(),
+                manufacturer.len() as i32,
+                product.as_mut_ptr(),
+                product.len() as i32,
+                serial.as_mut_ptr(),
+                serial.len() as i32,
+            )
+        };
+        if result != 0 {
+            return Err(Error::unknown(self.context));
+        }
+        let string = |buf: &[c_char]| {
+            let s = unsafe { CStr::from_ptr(buf.as_ptr()) };
+            Some(s.to_string_lossy().into_owned()).filter(|s| !s.is_empty())
+        };
+
+        use ffi::ftdi_eeprom_value as value;
+        let mut cbus_functions = [0; 10];
+        let cbus = [
+            value::CBUS_FUNCTION_0,
+            value::CBUS_FUNCTION_1,
+            value::CBUS_FUNCTION_2,
+            value::CBUS_FUNCTION_3,
+            value::CBUS_FUNCTION_4,
+            value::CBUS_FUNCTION_5,
+            value::CBUS_FUNCTION_6,
+            value::CBUS_FUNCTION_7,
+            value::CBUS_FUNCTION_8,
+            value::CBUS_FUNCTION_9,
+        ];
+        for (function, &name) in cbus_functions.iter_mut().zip(cbus.iter()) {
+            *function = self.eeprom_value(name)? as u8;
+        }
+
+        Ok(EepromInfo {
+            vid: self.eeprom_value(value::VENDOR_ID)? as u16,
+            pid: self.eeprom_value(value::PRODUCT_ID)? as u16,
+            manufacturer: string(&manufacturer),
+            product: string(&product),
+            serial: string(&serial),
+            self_powered: self.eeprom_value(value::SELF_POWERED)? != 0,
+            max_power: self.eeprom_value(value::MAX_POWER)? as u16,
+            channel_a_type: self.eeprom_value(value::CHANNEL_A_TYPE)? as u8,
+            channel_b_type: self.eeprom_value(value::CHANNEL_B_TYPE)? as u8,
+            cbus_functions,
+        })
+    }
+
+    /// Load the EEPROM into libftdi's buffer
+    fn fetch_eeprom(&mut self) -> Result<()> {
+        let result = unsafe { ffi::ftdi_read_eeprom(self.context) };
+        match result {
+            0 => Ok(()),
+            -1 => Err(Error::RequestFailed),
+            -2 => Err(Error::Disconnected),
+            _ => Err(Error::unknown(self.context)),
+        }
+    }
+
+    fn eeprom_value(&mut self, name: ffi::ftdi_eeprom_value) -> Result<i32> {
+        let mut value = 0;
+        let result = unsafe { ffi::ftdi_get_eeprom_value(self.context, name, &mut value) };
+        match result {
+            0 => Ok(value),
+            _ => Err(Error::unknown(self.context)),
+        }
+    }
 }
 
 unsafe impl Send for Device {}
@@ -293,6 +637,127 @@ impl Write for Device {
     }
 }
 
+/// A connected FTDI device
+///
+/// Strings are `None` when they can't be read, e.g. without permissions.
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub vid: u16,
+    pub pid: u16,
+    pub chip_type: ChipType,
+    pub bus: u8,
+    pub address: u8,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial: Option<String>,
+}
+
+/// Largest EEPROM libftdi handles, the 93C66
+const EEPROM_MAX_SIZE: i32 = 256;
+
+/// Common fields of a decoded EEPROM
+///
+/// Channel types and CBUS functions are libftdi's raw codes. Channel types
+/// are 0 for UART, 1 for FIFO, 2 for opto-isolated, 4 for CPU FIFO and 8 for
+/// FT1284. CBUS function codes depend on the chip: on the FT232R they
+/// describe CBUS0-4, on the FT232H ACBUS0-9.
+#[derive(Clone, Debug)]
+pub struct EepromInfo {
+    pub vid: u16,
+    pub pid: u16,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial: Option<String>,
+    pub self_powered: bool,
+    /// Maximum bus current in mA
+    pub max_power: u16,
+    pub channel_a_type: u8,
+    pub channel_b_type: u8,
+    pub cbus_functions: [u8; 10],
+}
+
+/// List the connected devices with the default FTDI IDs
+///
+/// Nothing is claimed, so devices used by other programs are left alone.
+pub fn list_devices() -> Result<Vec<DeviceInfo>> {
+    Builder::new().usb_find_all(0, 0)
+}
+
+/// List the connected devices with the given vendor ID and any product ID
+///
+/// Like `list_devices`, but for clone and vendor cables outside the default
+/// FTDI IDs.
+pub fn list_vendor_devices(vid: u16) -> Result<Vec<DeviceInfo>> {
+    Builder::new().usb_find_vendor(vid)
+}
+
+// Not covered by the libftdi bindings, libusb is linked in through libftdi anyway
+#[repr(C)]
+#[derive(Default)]
+struct LibusbDeviceDescriptor {
+    length: u8,
+    descriptor_type: u8,
+    bcd_usb: u16,
+    device_class: u8,
+    device_sub_class: u8,
+    device_protocol: u8,
+    max_packet_size0: u8,
+    id_vendor: u16,
+    id_product: u16,
+    bcd_device: u16,
+    manufacturer: u8,
+    product: u8,
+    serial_number: u8,
+    num_configurations: u8,
+}
+
+extern "C" {
+    fn libusb_get_device_descriptor(dev: *mut c_void, desc: *mut LibusbDeviceDescriptor) -> c_int;
+    fn libusb_get_bus_number(dev: *mut c_void) -> u8;
+    fn libusb_get_device_address(dev: *mut c_void) -> u8;
+    fn libusb_get_device(handle: *mut c_void) -> *mut c_void;
+    fn libusb_get_device_list(ctx: *mut c_void, list: *mut *mut *mut c_void) -> isize;
+    fn libusb_free_device_list(list: *mut *mut c_void, unref_devices: c_int);
+}
+
+/// USB backend in use and what it supports
+#[derive(Clone, Debug)]
+pub struct BackendInfo {
+    pub name: &'static str,
+    pub version: String,
+    pub capabilities: Capabilities,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Capabilities {
+    /// H-series chips with the 60 MHz MPSSE clock
+    pub high_speed: bool,
+    /// RTCK adaptive clocking through MPSSE
+    pub adaptive_clocking: bool,
+    pub eeprom: bool,
+}
+
+pub fn backend_info() -> BackendInfo {
+    let info = unsafe { ffi::ftdi_get_library_version() };
+    let version = unsafe { CStr::from_ptr(info.version_str) }.to_string_lossy();
+    let snapshot = unsafe { CStr::from_ptr(info.snapshot_str) }.to_string_lossy();
+    let version = if snapshot.is_empty() || snapshot == "unknown" {
+        version.into_owned()
+    } else {
+        format!("{} ({})", version, snapshot)
+    };
+
+    BackendInfo {
+        name: "libftdi1",
+        version,
+        capabilities: Capabilities {
+            high_speed: true,
+            adaptive_clocking: true,
+            eeprom: true,
+        },
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("failed to enumerate devices to open the correct one")]
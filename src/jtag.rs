@@ -0,0 +1,2666 @@
+use bitvec::order::Lsb0;
+use bitvec::slice::BitSlice;
+use bitvec::vec::BitVec;
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::bitbang::Bitbang;
+use crate::error::JtagError;
+use crate::ftdi;
+use crate::mpsse::MpsseIo;
+use crate::tap::TapState;
+
+#[derive(Clone, Debug)]
+pub struct JtagChainItem {
+    /// IDCODE, or 0 for a TAP that selects BYPASS after reset
+    pub idcode: u32,
+    /// JEDEC manufacturer ID, IDCODE bits 1..11
+    pub manufacturer: u16,
+    /// IDCODE bits 12..27
+    pub part: u16,
+    /// IDCODE bits 28..31
+    pub version: u8,
+    pub irlen: usize,
+}
+
+impl JtagChainItem {
+    fn new(idcode: u32, irlen: usize) -> Self {
+        Self {
+            idcode,
+            manufacturer: ((idcode >> 1) & 0x7ff) as u16,
+            part: ((idcode >> 12) & 0xffff) as u16,
+            version: (idcode >> 28) as u8,
+            irlen,
+        }
+    }
+}
+
+impl fmt::Display for JtagChainItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.idcode == 0 {
+            return write!(f, "idcode=none irlen={}", self.irlen);
+        }
+        write!(
+            f,
+            "idcode=0x{:08x} mfr=0x{:03x} part=0x{:04x} ver={} irlen={}",
+            self.idcode, self.manufacturer, self.part, self.version, self.irlen
+        )
+    }
+}
+
+/// Position of the selected target in the chain
+#[derive(Clone, Debug)]
+pub struct ChainParams {
+    /// IR bits of the TAPs between the target and TDO
+    pub irpre: usize,
+    /// IR bits of the TAPs between TDI and the target
+    pub irpost: usize,
+    /// Number of TAPs between the target and TDO
+    pub drpre: usize,
+    /// Number of TAPs between TDI and the target
+    pub drpost: usize,
+    pub irlen: usize,
+}
+
+/// Instructions found on a TAP by `discover_instructions`
+#[derive(Clone, Debug)]
+pub struct TapInstructions {
+    /// All-ones IR selects a 1-bit DR
+    pub bypass: bool,
+    /// IR value selecting a 32-bit DR that reads back the IDCODE
+    pub idcode: Option<u32>,
+}
+
+/// Result of a DR transfer with its USB round trip time
+#[derive(Clone, Debug)]
+pub struct TimedTransfer {
+    pub tdo: Vec<u8>,
+    pub bits: usize,
+    pub elapsed: Duration,
+}
+
+/// Clock edges used to drive TDI/TMS and to sample TDO
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize, serde::Serialize))]
+pub enum ClockEdge {
+    /// Drive on the falling edge, sample on the rising edge (the JTAG standard)
+    #[default]
+    FallingOutRisingIn,
+    /// Drive on the rising edge, sample on the falling edge
+    RisingOutFallingIn,
+}
+
+impl ClockEdge {
+    /// Edge bits of the MPSSE shift opcodes
+    fn opcode_bits(self) -> u8 {
+        match self {
+            ClockEdge::FallingOutRisingIn => 0x01,
+            ClockEdge::RisingOutFallingIn => 0x04,
+        }
+    }
+}
+
+/// Lowest clock `transfer_dr` falls back to with `set_auto_clock_fallback`
+const MIN_FALLBACK_HZ: u32 = 100_000;
+
+/// Queued MPSSE commands are written out once the buffer reaches this size
+const FLUSH_THRESHOLD: usize = 64 * 1024;
+
+/// Smallest queue size `auto_tune` picks
+const MIN_TUNED_CHUNK: usize = 512;
+
+/// Smallest read size `auto_tune` picks, one full-speed USB packet
+const MIN_TUNED_READ: usize = 64;
+
+/// Loopback transfer sizes `auto_tune` times
+const TUNE_SIZES: [usize; 4] = [1, 512, 4096, 16 * 1024];
+
+/// GPIO wiring of a reset line
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize, serde::Serialize))]
+pub struct ResetPin {
+    /// GPIO number, 0-7 for ADBUS and 8-15 for ACBUS
+    pub pin: u8,
+    pub active_high: bool,
+    /// Drive the pin only while asserted and leave it floating otherwise
+    pub open_drain: bool,
+}
+
+/// Reset lines wired to the adapter GPIOs, `None` if not connected
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize, serde::Serialize))]
+pub struct ResetConfig {
+    pub trst: Option<ResetPin>,
+    pub srst: Option<ResetPin>,
+}
+
+/// GPIO levels and directions `attach` starts from
+///
+/// ADBUS (written with MPSSE command `0x80`) is in the low byte, ACBUS
+/// (`0x82`) in the high one. A set direction bit makes the pin an output.
+/// TCK, TDI and TMS (bits 0, 1 and 3) must be outputs and TDO (bit 2) an input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize, serde::Serialize))]
+pub struct GpioInit {
+    pub output: u16,
+    pub direction: u16,
+}
+
+impl Default for GpioInit {
+    /// TMS high, only the JTAG outputs driven. May not work with all probes.
+    fn default() -> Self {
+        GpioInit {
+            output: 0x0008,
+            direction: 0x000b,
+        }
+    }
+}
+
+/// Averages measured by `benchmark`
+#[derive(Clone, Debug)]
+pub struct BenchResult {
+    /// Bytes shifted per transfer
+    pub bytes: usize,
+    pub runs: usize,
+    /// Average time of a full transfer, including the USB round trip
+    pub transfer_time: Duration,
+    /// Effective throughput of the full transfers
+    pub kbit_per_sec: f64,
+    /// Average time of a one-byte transfer, dominated by USB latency
+    pub latency: Duration,
+}
+
+/// Measurements and settings of `auto_tune`
+#[derive(Clone, Debug)]
+pub struct TuneResult {
+    /// Average time of a one-byte transfer
+    pub latency: Duration,
+    /// Throughput fitted over loopback transfers of up to 16 KiB
+    pub kbit_per_sec: f64,
+    /// Queue size now set with `set_flush_threshold`
+    pub flush_threshold: usize,
+    /// Read size now set with `set_min_read_size`
+    pub min_read_size: usize,
+}
+
+/// Token bucket pacing transfers
+#[derive(Debug)]
+struct RateLimit {
+    per_sec: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimit {
+    fn new(per_sec: u32) -> Self {
+        Self {
+            per_sec: per_sec as f64,
+            tokens: 1.0,
+            last: Instant::now(),
+        }
+    }
+
+    /// Wait until the next transfer is allowed
+    fn acquire(&mut self) {
+        let elapsed = self.last.elapsed().as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_sec).min(1.0);
+        if self.tokens < 1.0 {
+            std::thread::sleep(Duration::from_secs_f64((1.0 - self.tokens) / self.per_sec));
+        }
+        self.tokens = 0.0;
+        self.last = Instant::now();
+    }
+}
+
+#[derive(Debug)]
+pub struct JtagAdapter<D: MpsseIo = ftdi::Device> {
+    device: D,
+    vid: u16,
+    pid: u16,
+    interface: ftdi::Interface,
+    chain: Vec<JtagChainItem>,
+    chain_params: Option<ChainParams>,
+    drain_limit: usize,
+    loopback: bool,
+    reset_clocks: usize,
+    min_read_size: usize,
+    /// Most bytes a single read returned, for `auto_tune`
+    largest_read: usize,
+    flush_threshold: usize,
+    verify_bypass: bool,
+    rate_limit: Option<RateLimit>,
+    attach_ir: Option<(Vec<u8>, usize)>,
+    tlr_dwell: usize,
+    /// Leave `reset` in the command queue instead of flushing it
+    queue_reset: bool,
+    /// Clock feeding the TCK divisor, 12 MHz while the divide-by-5 prescaler is on
+    base_clock: u32,
+    divisor: u16,
+    read_timeout: Duration,
+    max_devices: usize,
+    reset_config: ResetConfig,
+    /// GPIO levels and directions, ADBUS in the low byte and ACBUS in the high one
+    gpio_output: u16,
+    gpio_direction: u16,
+    gpio_init: GpioInit,
+    /// MPSSE commands not yet written to the device
+    commands: Vec<u8>,
+    /// Bytes read past the end of the last reply, the start of the next one
+    leftover: VecDeque<u8>,
+    /// TAP state after the queued commands, `None` until the next reset
+    state: Option<TapState>,
+    /// Where scans leave the TAP
+    park_state: TapState,
+    /// States passed by the last operation, `None` unless recording
+    state_path: Option<Vec<TapState>>,
+    adaptive_clock: bool,
+    /// GPIO sampled as RTCK, made an input while adaptive clocking is on
+    rtck_pin: u8,
+    /// Retry timed-out `transfer_dr`s at lower clocks
+    auto_clock_fallback: bool,
+    clock_edge: ClockEdge,
+    latency_timer: u8,
+    chip_type: ftdi::ChipType,
+    /// MPSSE emulation, set by `attach` on chips without an MPSSE engine
+    bitbang: Option<Bitbang>,
+    /// Whether `attach` ran since the last `close`
+    attached: bool,
+    tristate_on_close: bool,
+}
+
+impl JtagAdapter {
+    pub fn open(vid: u16, pid: u16) -> Result<Self, JtagError> {
+        Self::open_interface(vid, pid, ftdi::Interface::A)
+    }
+
+    /// Open the given channel of a multi-channel chip
+    ///
+    /// On an FT4232H only interfaces A and B have an MPSSE engine.
+    pub fn open_interface(
+        vid: u16,
+        pid: u16,
+        interface: ftdi::Interface,
+    ) -> Result<Self, JtagError> {
+        Self::open_with(vid, pid, interface, |builder| builder.usb_open(vid, pid))
+    }
+
+    /// Open the adapter with the given serial number
+    ///
+    /// Fails with `SerialNotFound` listing the serials present if no device matches.
+    pub fn open_by_serial(vid: u16, pid: u16, serial: &str) -> Result<Self, JtagError> {
+        let result = Self::open_with(vid, pid, ftdi::Interface::A, |builder| {
+            builder.usb_open_serial(vid, pid, serial)
+        });
+        match result {
+            Err(JtagError::Ftdi(ftdi::Error::DeviceNotFound)) => {
+                let available = ftdi::Builder::new().usb_serials(vid, pid)?;
+                Err(JtagError::SerialNotFound {
+                    serial: serial.to_string(),
+                    available,
+                })
+            }
+            result => result,
+        }
+    }
+
+    /// Open the `index`-th adapter (counting from 0) with the given IDs
+    pub fn open_by_index(vid: u16, pid: u16, index: usize) -> Result<Self, JtagError> {
+        let index = index
+            .try_into()
+            .map_err(|_| JtagError::InvalidInput("device index out of range"))?;
+        Self::open_with(vid, pid, ftdi::Interface::A, |builder| {
+            builder.usb_open_index(vid, pid, index)
+        })
+    }
+
+    fn open_with<F>(
+        vid: u16,
+        pid: u16,
+        interface: ftdi::Interface,
+        open: F,
+    ) -> Result<Self, JtagError>
+    where
+        F: FnOnce(ftdi::Builder) -> ftdi::Result<ftdi::Device>,
+    {
+        let mut builder = ftdi::Builder::new();
+        builder.set_interface(interface)?;
+        let device = open(builder)?;
+
+        let mut adapter = Self::from_device(device);
+        adapter.vid = vid;
+        adapter.pid = pid;
+        adapter.interface = interface;
+        Ok(adapter)
+    }
+
+    /// Wrap a device opened by the caller, `attach` still has to be called
+    pub fn from_device(device: ftdi::Device) -> Self {
+        let (vid, pid) = device.usb_ids();
+        let interface = device.interface();
+
+        let mut adapter = Self::from_io(device);
+        adapter.vid = vid;
+        adapter.pid = pid;
+        adapter.interface = interface;
+        adapter
+    }
+
+    /// Read the raw contents of the adapter's EEPROM
+    pub fn read_eeprom(&mut self) -> Result<Vec<u8>, JtagError> {
+        Ok(self.device.read_eeprom()?)
+    }
+
+    /// Read the adapter's EEPROM and decode IDs, strings and pin functions
+    pub fn eeprom_info(&mut self) -> Result<ftdi::EepromInfo, JtagError> {
+        Ok(self.device.eeprom_info()?)
+    }
+}
+
+impl<D: MpsseIo> JtagAdapter<D> {
+    /// Drive any MPSSE transport, e.g. a `MockJtag`
+    ///
+    /// USB IDs are reported as 0 and the interface as `Any`.
+    pub fn from_io(device: D) -> Self {
+        let chip_type = device.chip_type();
+        log::debug!("chip type: {:?}", chip_type);
+
+        Self {
+            device,
+            vid: 0,
+            pid: 0,
+            interface: ftdi::Interface::Any,
+            chain: Vec::new(),
+            chain_params: None,
+            drain_limit: 64 * 1024,
+            loopback: false,
+            reset_clocks: 5,
+            min_read_size: 0,
+            largest_read: 0,
+            flush_threshold: FLUSH_THRESHOLD,
+            verify_bypass: false,
+            rate_limit: None,
+            attach_ir: None,
+            tlr_dwell: 0,
+            queue_reset: false,
+            // MPSSE defaults: prescaler enabled, divisor 0
+            base_clock: 12_000_000,
+            divisor: 0,
+            read_timeout: Duration::from_millis(10),
+            max_devices: 32,
+            reset_config: ResetConfig::default(),
+            gpio_output: GpioInit::default().output,
+            gpio_direction: GpioInit::default().direction,
+            gpio_init: GpioInit::default(),
+            commands: Vec::new(),
+            leftover: VecDeque::new(),
+            state: None,
+            park_state: TapState::RunTestIdle,
+            state_path: None,
+            adaptive_clock: false,
+            rtck_pin: 7,
+            auto_clock_fallback: false,
+            clock_edge: ClockEdge::default(),
+            latency_timer: 1,
+            chip_type,
+            bitbang: None,
+            attached: false,
+            tristate_on_close: false,
+        }
+    }
+
+    /// Cap the number of DR transfers per second, 0 removes the limit
+    ///
+    /// Keeps background pollers from monopolizing a shared USB bus.
+    pub fn set_max_transfer_rate(&mut self, transfers_per_sec: u32) {
+        self.rate_limit = match transfers_per_sec {
+            0 => None,
+            rate => Some(RateLimit::new(rate)),
+        };
+    }
+
+    /// Check that the TAPs between TDI and the target shift out zeros
+    ///
+    /// A BYPASS register captures 0 on CAPTURE-DR, so the `drpost` bits of
+    /// every transfer must read back as zeros on a healthy chain.
+    pub fn set_verify_bypass(&mut self, enable: bool) {
+        self.verify_bypass = enable;
+    }
+
+    /// Request at least `bytes` per USB read while waiting for a reply
+    ///
+    /// Larger values let libftdi coalesce the bulk reads of long captures
+    /// into fewer calls. Small replies gain nothing from it. Bytes the device
+    /// returns beyond the expected reply are kept for the next read.
+    pub fn set_min_read_size(&mut self, bytes: usize) {
+        self.min_read_size = bytes;
+    }
+
+    pub fn min_read_size(&self) -> usize {
+        self.min_read_size
+    }
+
+    /// Write queued commands out once they reach `bytes`, 64 KiB by default
+    ///
+    /// Reads and `flush` write the queue out whatever its size.
+    pub fn set_flush_threshold(&mut self, bytes: usize) {
+        self.flush_threshold = bytes;
+    }
+
+    pub fn flush_threshold(&self) -> usize {
+        self.flush_threshold
+    }
+
+    /// TAPs found by the last scan, cached for `select_target`
+    pub fn chain(&self) -> &[JtagChainItem] {
+        &self.chain
+    }
+
+    /// Parameters of the selected target
+    pub fn chain_params(&self) -> Option<&ChainParams> {
+        self.chain_params.as_ref()
+    }
+
+    /// Set the maximum number of stale bytes discarded during `attach`
+    pub fn set_drain_limit(&mut self, bytes: usize) {
+        self.drain_limit = bytes;
+    }
+
+    pub fn attach(&mut self) -> Result<(), JtagError> {
+        // Anything queued before MPSSE mode is enabled would be garbage
+        self.commands.clear();
+        self.state = None;
+        self.chain.clear();
+        self.device.usb_reset()?;
+        self.device.set_latency_timer(self.latency_timer)?;
+        if self.chip_type.has_mpsse() {
+            self.bitbang = None;
+            self.device.enter_mpsse(0x0b)?;
+        } else {
+            log::info!(
+                "{:?} has no MPSSE engine, using bitbang mode",
+                self.chip_type
+            );
+            let direction = self.gpio_init.direction as u8;
+            self.bitbang = Some(Bitbang::new(direction));
+            self.device.enter_bitbang(direction)?;
+        }
+        self.purge()?;
+
+        self.sync()?;
+
+        self.gpio_output = self.gpio_init.output;
+        self.gpio_direction = self.gpio_init.direction;
+        if self.adaptive_clock {
+            self.gpio_direction &= !(1 << self.rtck_pin);
+        }
+        // Write the initial GPIO state with the reset lines released. The GPIO
+        // commands address the pins of the opened channel, so they're the same
+        // for every interface.
+        self.set_reset(Some(false), Some(false))?;
+
+        self.set_loopback(false)?;
+        // Entering MPSSE mode turns adaptive clocking off
+        if self.adaptive_clock {
+            self.queue(&[0x96])?;
+        }
+
+        if let Some((data, bits)) = self.attach_ir.clone() {
+            self.reset()?;
+            self.shift_ir(&data, bits)?;
+        }
+
+        self.attached = true;
+        Ok(())
+    }
+
+    /// Release the reset lines and take the chip out of MPSSE mode
+    ///
+    /// Runs on drop after `attach`, so a board isn't left held in reset.
+    /// Queued commands are sent first. The USB interface is released when
+    /// the device itself is dropped.
+    pub fn close(&mut self) -> Result<(), JtagError> {
+        if !self.attached {
+            return Ok(());
+        }
+        self.attached = false;
+
+        self.set_reset(Some(false), Some(false))?;
+        if self.tristate_on_close {
+            let config = self.reset_config;
+            for pin in config.trst.iter().chain(config.srst.iter()) {
+                self.gpio_direction &= !(1 << pin.pin);
+            }
+            self.write_gpio()?;
+        }
+        self.bitbang = None;
+        self.device.reset_bitmode()
+    }
+}
+
+impl<D: MpsseIo> Drop for JtagAdapter<D> {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            log::warn!("failed to restore the adapter state: {}", e);
+        }
+    }
+}
+
+impl<D: MpsseIo> JtagAdapter<D> {
+    /// Make `close` turn the reset lines into inputs instead of driving them released
+    pub fn set_tristate_on_close(&mut self, enable: bool) {
+        self.tristate_on_close = enable;
+    }
+
+    /// Program the TCK frequency, returning the frequency actually achieved
+    ///
+    /// The divisor quantizes the frequency to `base / (2 * (divisor + 1))`,
+    /// rounding down.
+    pub fn set_clock(&mut self, hz: u32) -> Result<u32, JtagError> {
+        // H-series chips run with the divide-by-5 prescaler disabled
+        let h_series = self.chip_type.is_h_series();
+        let base_clock = if h_series { 60_000_000 } else { 12_000_000 };
+        let max = base_clock / 2;
+        let divisor = match hz {
+            0 => None,
+            hz if hz > max => None,
+            hz => Some((max + hz - 1) / hz - 1).filter(|&divisor| divisor <= 0xffff),
+        };
+        let divisor = divisor.ok_or(JtagError::ClockOutOfRange {
+            hz,
+            min: max / 0x10000 + 1,
+            max,
+        })?;
+
+        // Older chips don't know the prescaler command and would echo it as bad
+        if h_series {
+            self.queue(&[0x8a])?;
+        }
+        self.queue(&[0x86, divisor as u8, (divisor >> 8) as u8])?;
+
+        self.base_clock = base_clock;
+        self.divisor = divisor as u16;
+        let actual = self.clock_hz();
+        log::debug!("TCK {} Hz (divisor {})", actual, divisor);
+        Ok(actual)
+    }
+
+    /// Program the fastest TCK not above `max_hz`, returning it
+    ///
+    /// For targets rated for a maximum TCK. Unlike `set_clock`, a `max_hz`
+    /// above what the chip can do selects its fastest clock instead of
+    /// failing. Only a `max_hz` below the slowest clock is an error.
+    pub fn set_clock_capped(&mut self, max_hz: u32) -> Result<u32, JtagError> {
+        let base_clock = if self.chip_type.is_h_series() {
+            60_000_000
+        } else {
+            12_000_000
+        };
+        self.set_clock(max_hz.min(base_clock / 2))
+    }
+
+    /// Retry a `transfer_dr` that timed out at halved clocks
+    ///
+    /// Goes down to 100 kHz, then gives up with the last timeout. Only a
+    /// timeout triggers a retry: a reply that arrives complete but corrupted
+    /// by a marginal clock, e.g. an IDCODE with bit 0 clear, is returned
+    /// as is.
+    ///
+    /// The whole DR scan is replayed, so registers with side effects on
+    /// read or write see it more than once. A RISC-V `dmi` write or a
+    /// read that pops a FIFO runs twice if its first reply timed out.
+    pub fn set_auto_clock_fallback(&mut self, enable: bool) {
+        self.auto_clock_fallback = enable;
+    }
+
+    /// TCK frequency the divisor is programmed for
+    ///
+    /// 6 MHz, the MPSSE default, until `set_clock` is called. With adaptive
+    /// clocking this is an upper bound.
+    pub fn clock_hz(&self) -> u32 {
+        self.base_clock / 2 / (u32::from(self.divisor) + 1)
+    }
+
+    /// Make every TCK edge wait for the target to echo it back on RTCK
+    ///
+    /// The RTCK pin, GPIOL3 (ADBUS7) unless set with `set_rtck_pin`, is
+    /// switched to an input. Only H-series chips support adaptive clocking.
+    /// The programmed TCK frequency becomes an upper bound, so read timeouts
+    /// may need raising for slow targets.
+    pub fn set_adaptive_clock(&mut self, enable: bool) -> Result<(), JtagError> {
+        if !self.chip_type.is_h_series() {
+            return Err(JtagError::InvalidInput(
+                "adaptive clocking needs an H-series chip",
+            ));
+        }
+        if enable {
+            check_rtck_pin(self.rtck_pin, &self.reset_config)?;
+            if self.rtck_pin != 7 {
+                log::warn!(
+                    "the MPSSE engine samples RTCK on GPIOL3, GPIO {} must be tied to it",
+                    self.rtck_pin
+                );
+            }
+            self.gpio_direction &= !(1 << self.rtck_pin);
+            self.write_gpio()?;
+        }
+        self.queue(&[if enable { 0x96 } else { 0x97 }])?;
+        self.adaptive_clock = enable;
+        Ok(())
+    }
+
+    pub fn is_adaptive_clock_enabled(&self) -> bool {
+        self.adaptive_clock
+    }
+
+    /// Set the GPIO wired to RTCK (4-15), GPIOL3 (7) by default
+    ///
+    /// The pin must not be one of the reset lines. With adaptive clocking
+    /// on, it's switched to an input right away and the previous pin stays
+    /// one.
+    pub fn set_rtck_pin(&mut self, pin: u8) -> Result<(), JtagError> {
+        check_rtck_pin(pin, &self.reset_config)?;
+        self.rtck_pin = pin;
+        if self.adaptive_clock {
+            self.gpio_direction &= !(1 << pin);
+            self.write_gpio()?;
+        }
+        Ok(())
+    }
+
+    /// Chip family detected from the USB descriptor when the device was wrapped
+    pub fn chip_type(&self) -> ftdi::ChipType {
+        self.chip_type
+    }
+
+    /// Set the GPIO state written by `attach`, replacing any `gpio_set` changes
+    ///
+    /// The reset lines configured with `set_reset_config` are still driven on top.
+    pub fn set_gpio_init(&mut self, init: GpioInit) -> Result<(), JtagError> {
+        if init.direction & 0x000f != 0x000b {
+            return Err(JtagError::InvalidInput(
+                "TCK, TDI and TMS must be outputs and TDO an input",
+            ));
+        }
+        self.gpio_init = init;
+        Ok(())
+    }
+
+    /// Set the USB latency timer in milliseconds, also used by later `attach` calls
+    ///
+    /// The chip sends a partially filled packet once the timer expires. Short
+    /// timers speed up small request/reply exchanges, longer ones batch large
+    /// reads into fewer packets and may be more stable on some USB stacks.
+    pub fn set_latency_timer(&mut self, ms: u8) -> Result<(), JtagError> {
+        if ms == 0 {
+            return Err(JtagError::InvalidInput("latency timer must be 1 to 255 ms"));
+        }
+        self.device.set_latency_timer(ms)?;
+        self.latency_timer = ms;
+        Ok(())
+    }
+
+    /// Select the clock edges for driving TDI/TMS and sampling TDO
+    ///
+    /// Sampling TDO on the falling edge can help with marginal signal integrity.
+    pub fn set_clock_edge(&mut self, edge: ClockEdge) {
+        self.clock_edge = edge;
+    }
+
+    /// Set the base timeout for a device reply
+    ///
+    /// Once the TCK frequency is known, each read also gets the time needed to
+    /// clock the expected bytes, so slow clocks don't time out long transfers.
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.read_timeout = timeout;
+    }
+
+    fn read_timeout_for(&self, size: usize) -> Duration {
+        let micros = size as u64 * 8 * 1_000_000 / u64::from(self.clock_hz());
+        self.read_timeout + Duration::from_micros(micros)
+    }
+
+    /// Set the GPIO wiring of the reset lines, released by `attach`
+    ///
+    /// GPIOs 0-3 carry TCK, TDI, TDO and TMS and can't be used.
+    pub fn set_reset_config(&mut self, config: ResetConfig) -> Result<(), JtagError> {
+        for pin in config.trst.iter().chain(config.srst.iter()) {
+            if pin.pin < 4 || pin.pin > 15 {
+                return Err(JtagError::InvalidInput("reset pin must be GPIO 4 to 15"));
+            }
+        }
+        if self.adaptive_clock {
+            check_rtck_pin(self.rtck_pin, &config)?;
+        }
+        self.reset_config = config;
+        Ok(())
+    }
+
+    /// Assert (`true`) or release (`false`) the reset lines, `None` leaves one as is
+    ///
+    /// Asserting nTRST without a TRST pin in the `ResetConfig` falls back to
+    /// the TMS `reset`. A missing nSRST is ignored.
+    pub fn set_reset(&mut self, trst: Option<bool>, srst: Option<bool>) -> Result<(), JtagError> {
+        match (trst, self.reset_config.trst) {
+            (Some(true), Some(pin)) => log::debug!("TAP reset through nTRST on GPIO {}", pin.pin),
+            (Some(true), None) => {
+                log::debug!("no TRST pin, TAP reset through TMS");
+                self.reset()?;
+            }
+            _ => {}
+        }
+        if srst == Some(true) && self.reset_config.srst.is_none() {
+            log::debug!("no SRST pin, ignoring nSRST");
+        }
+
+        let lines = [
+            (self.reset_config.trst, trst),
+            (self.reset_config.srst, srst),
+        ];
+        for &(pin, asserted) in lines.iter() {
+            if let (Some(pin), Some(asserted)) = (pin, asserted) {
+                let mask = 1 << pin.pin;
+                if asserted == pin.active_high {
+                    self.gpio_output |= mask;
+                } else {
+                    self.gpio_output &= !mask;
+                }
+                if pin.open_drain && !asserted {
+                    self.gpio_direction &= !mask;
+                } else {
+                    self.gpio_direction |= mask;
+                }
+            }
+        }
+        self.write_gpio()
+    }
+
+    /// Assert nSRST for `duration`, then release it
+    pub fn pulse_srst(&mut self, duration: Duration) -> Result<(), JtagError> {
+        self.set_reset(None, Some(true))?;
+        thread::sleep(duration);
+        self.set_reset(None, Some(false))
+    }
+
+    /// Write the cached GPIO state to both pin bytes
+    ///
+    /// Flushes, so the pins change right away rather than with the next read.
+    fn write_gpio(&mut self) -> Result<(), JtagError> {
+        let (output, direction) = (self.gpio_output, self.gpio_direction);
+        self.queue(&[
+            0x80,
+            output as u8,
+            direction as u8,
+            0x82,
+            (output >> 8) as u8,
+            (direction >> 8) as u8,
+        ])?;
+        self.flush()
+    }
+
+    /// Append MPSSE commands to the buffer, writing it out once it gets large
+    fn queue(&mut self, command: &[u8]) -> Result<(), JtagError> {
+        self.commands.extend_from_slice(command);
+        if self.commands.len() >= self.flush_threshold {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Send arbitrary MPSSE `command` bytes and read `read_len` reply bytes
+    ///
+    /// For opcodes without a wrapper in this crate. The commands go through
+    /// the same queue as everything else, so with `read_len` of 0 they stay
+    /// queued until the next read or `flush`. The TAP state tracker doesn't
+    /// see them: after moving TMS, call `reset` before any other scan.
+    pub fn raw_mpsse(&mut self, command: &[u8], read_len: usize) -> Result<Vec<u8>, JtagError> {
+        self.queue(command)?;
+        if read_len == 0 {
+            return Ok(vec![]);
+        }
+        self.read_response(read_len)
+    }
+
+    /// Write the queued MPSSE commands to the device
+    ///
+    /// Commands are buffered to save USB round trips. Reads flush on their
+    /// own, call this when a write-only operation must take effect now.
+    pub fn flush(&mut self) -> Result<(), JtagError> {
+        if !self.commands.is_empty() {
+            tracing::trace!("write {:02x?}", self.commands);
+            match &mut self.bitbang {
+                Some(bitbang) => {
+                    bitbang.transfer(&mut self.device, &self.commands, self.read_timeout)?
+                }
+                None => self.device.write_all(&self.commands)?,
+            }
+            self.commands.clear();
+        }
+        Ok(())
+    }
+
+    /// Read replies, from the MPSSE emulation in bitbang mode
+    fn read_device(&mut self, buf: &mut [u8]) -> Result<usize, JtagError> {
+        match &mut self.bitbang {
+            Some(bitbang) => Ok(bitbang.read(buf)),
+            None => Ok(self.device.read(buf)?),
+        }
+    }
+
+    fn purge(&mut self) -> Result<(), JtagError> {
+        self.leftover.clear();
+        if let Some(bitbang) = &mut self.bitbang {
+            bitbang.purge();
+        }
+        self.device.purge()
+    }
+
+    /// Set an IR value (data, length in bits) loaded right after `attach`
+    pub fn set_attach_ir(&mut self, ir: Option<(Vec<u8>, usize)>) {
+        self.attach_ir = ir;
+    }
+
+    /// Connect TDI to TDO internally (MPSSE loopback)
+    pub fn set_loopback(&mut self, enable: bool) -> Result<(), JtagError> {
+        let command = if enable { 0x84 } else { 0x85 };
+        self.queue(&[command])?;
+        self.loopback = enable;
+        Ok(())
+    }
+
+    pub fn is_loopback_enabled(&self) -> bool {
+        self.loopback
+    }
+
+    /// Check the MPSSE link by shifting a pattern through internal loopback
+    ///
+    /// Only byte shifts are used and TMS isn't touched, so the TAP state is
+    /// kept. Returns `false` if the pattern doesn't come back unchanged or
+    /// doesn't come back at all.
+    pub fn loopback_test(&mut self) -> Result<bool, JtagError> {
+        let pattern = [0x00, 0xff, 0x55, 0xaa, 0x01, 0x80, 0x12, 0x34, 0x56, 0x78];
+        let was_enabled = self.loopback;
+
+        self.set_loopback(true)?;
+        let n = (pattern.len() - 1) as u16;
+        self.queue(&[0x39, n as u8, (n >> 8) as u8])?;
+        self.queue(&pattern)?;
+        let reply = self.read_response(pattern.len());
+        self.set_loopback(was_enabled)?;
+
+        match reply {
+            Ok(reply) if reply[..] == pattern[..] => Ok(true),
+            Ok(reply) => {
+                log::debug!("loopback sent {:02x?}, got {:02x?}", pattern, reply);
+                Ok(false)
+            }
+            Err(JtagError::Timeout { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read the current levels of the low (bits 0..7) and high (bits 8..15) byte pins
+    pub fn read_pins(&mut self) -> Result<u16, JtagError> {
+        self.queue(&[0x81, 0x83])?;
+        let r = self.read_response(2)?;
+        Ok(u16::from_le_bytes([r[0], r[1]]))
+    }
+
+    /// Check that the pins selected by `mask` are at the `expected` levels
+    pub fn verify_pins(&mut self, expected: u16, mask: u16) -> Result<(), JtagError> {
+        let pins = self.read_pins()?;
+        if pins & mask != expected & mask {
+            return Err(JtagError::PinMismatch {
+                expected: expected & mask,
+                actual: pins & mask,
+                mask,
+            });
+        }
+        Ok(())
+    }
+
+    /// Drive a GPIO (0-7 on ADBUS, 8-15 on ACBUS) to `level`
+    ///
+    /// The level only shows on the pin once it's configured as an output.
+    pub fn gpio_set(&mut self, pin: u8, level: bool) -> Result<(), JtagError> {
+        let mask = user_gpio_mask(pin)?;
+        if level {
+            self.gpio_output |= mask;
+        } else {
+            self.gpio_output &= !mask;
+        }
+        self.write_gpio()
+    }
+
+    /// Configure a GPIO (0-7 on ADBUS, 8-15 on ACBUS) as an output or an input
+    pub fn gpio_set_direction(&mut self, pin: u8, output: bool) -> Result<(), JtagError> {
+        let mask = user_gpio_mask(pin)?;
+        if self.adaptive_clock && pin == self.rtck_pin && output {
+            return Err(JtagError::InvalidInput("the pin carries RTCK"));
+        }
+        if output {
+            self.gpio_direction |= mask;
+        } else {
+            self.gpio_direction &= !mask;
+        }
+        self.write_gpio()
+    }
+
+    /// Read the level of a GPIO (0-7 on ADBUS, 8-15 on ACBUS)
+    pub fn gpio_get(&mut self, pin: u8) -> Result<bool, JtagError> {
+        if pin > 15 {
+            return Err(JtagError::InvalidInput("GPIO number must be 0 to 15"));
+        }
+        Ok(self.read_pins()? & (1 << pin) != 0)
+    }
+
+    /// Discard stale data left in the device, bounded in size and time
+    fn drain(&mut self) {
+        let timeout = Duration::from_millis(100);
+        let mut buf = [0; 512];
+        let mut total = 0;
+
+        let t0 = Instant::now();
+        loop {
+            match self.read_device(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => total += n,
+            }
+            if total >= self.drain_limit || t0.elapsed() > timeout {
+                log::warn!(
+                    "drained {} bytes without emptying the device, it may be stuck streaming",
+                    total
+                );
+                break;
+            }
+        }
+    }
+
+    /// Read exactly `size` bytes, keeping any excess for the next reply
+    ///
+    /// libftdi strips the two modem status bytes heading every USB packet,
+    /// so only reply bytes arrive here. Still, a large USB read can end
+    /// inside the reply to a later command.
+    fn read_response(&mut self, size: usize) -> Result<Vec<u8>, JtagError> {
+        let mut result = self.read_bytes(size)?;
+
+        if result.len() > size {
+            log::debug!("read {} bytes past the reply", result.len() - size);
+            self.leftover.extend(result.drain(size..));
+        }
+
+        Ok(result)
+    }
+
+    /// Read at least `size` bytes, starting with any left over from the last reply
+    ///
+    /// Backs off with growing sleeps while nothing arrives, so a slow reply
+    /// doesn't keep a core busy polling the device.
+    fn read_bytes(&mut self, size: usize) -> Result<Vec<u8>, JtagError> {
+        self.flush()?;
+
+        let timeout = self.read_timeout_for(size);
+        let mut result: Vec<u8> = self.leftover.drain(..).collect();
+        let mut buf = Vec::new();
+        let mut backoff = Duration::from_micros(50);
+
+        let t0 = Instant::now();
+        while result.len() < size {
+            if t0.elapsed() > timeout {
+                return Err(JtagError::Timeout {
+                    expected: size,
+                    got: result.len(),
+                });
+            }
+
+            buf.resize((size - result.len()).max(self.min_read_size), 0);
+            let n = self.read_device(&mut buf)?;
+            if n == 0 {
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(Duration::from_millis(1));
+                continue;
+            }
+            result.extend_from_slice(&buf[..n]);
+            self.largest_read = self.largest_read.max(n);
+            backoff = Duration::from_micros(50);
+        }
+        tracing::trace!("read {:02x?}", result);
+
+        Ok(result)
+    }
+
+    /// Re-establish the command/response boundary without a full `attach`
+    ///
+    /// Sends the bogus opcode `0xab`, which MPSSE answers with `0xfa 0xab`.
+    ///
+    /// Commands still queued are dropped.
+    pub fn resync(&mut self) -> Result<(), JtagError> {
+        self.commands.clear();
+        // The dropped commands may have moved the TAP
+        self.state = None;
+        self.purge()?;
+        self.drain();
+
+        if !self.check_mpsse()? {
+            return Err(JtagError::SyncFailed);
+        }
+        Ok(())
+    }
+
+    /// Wait until the device has processed every command sent so far
+    ///
+    /// Sends the bogus opcode `0xaa` and discards the input up to its
+    /// `0xfa 0xaa` echo, stale replies included.
+    pub fn sync(&mut self) -> Result<(), JtagError> {
+        self.queue(&[0xaa])?;
+
+        let mut input = Vec::new();
+        loop {
+            match self.read_bytes(1) {
+                Ok(r) => input.extend_from_slice(&r),
+                Err(JtagError::Timeout { .. }) => return Err(JtagError::SyncFailed),
+                Err(e) => return Err(e),
+            }
+            if let Some(pos) = input.windows(2).position(|w| w == [0xfa, 0xaa]) {
+                if pos + 2 < input.len() {
+                    log::warn!(
+                        "{} bytes received after the sync echo",
+                        input.len() - pos - 2
+                    );
+                }
+                if pos > 0 {
+                    log::debug!("discarded {} stale bytes", pos);
+                }
+                return Ok(());
+            }
+            if input.len() > self.drain_limit {
+                return Err(JtagError::SyncFailed);
+            }
+        }
+    }
+
+    /// Check that the device is still in MPSSE mode
+    ///
+    /// MPSSE answers the bogus opcode `0xab` with `0xfa 0xab`, any other
+    /// mode doesn't.
+    pub fn check_mpsse(&mut self) -> Result<bool, JtagError> {
+        self.queue(&[0xab])?;
+        match self.read_bytes(2) {
+            Ok(r) => Ok(r == [0xfa, 0xab]),
+            Err(JtagError::Timeout { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn shift_tms(&mut self, data: &[u8], bits: usize) -> Result<(), JtagError> {
+        self.shift_tms_tdi(data, bits, false)
+    }
+
+    /// `shift_tms` holding TDI at `tdi`
+    fn shift_tms_tdi(&mut self, data: &[u8], bits: usize, tdi: bool) -> Result<(), JtagError> {
+        let command = tms_command(data, bits, tdi, self.clock_edge)?;
+        if let Some(mut state) = self.state {
+            let tms = BitSlice::<Lsb0, u8>::from_slice(data);
+            for &bit in tms[..bits].iter() {
+                state = state.next(bit);
+                self.record_state(state);
+            }
+            self.state = Some(state);
+        }
+        self.queue(&command)
+    }
+
+    /// Start a new state path at `state`
+    fn start_state_path(&mut self, state: TapState) {
+        if let Some(path) = &mut self.state_path {
+            path.clear();
+            path.push(state);
+        }
+    }
+
+    /// Append `state` to the state path unless the TAP stays in it
+    fn record_state(&mut self, state: TapState) {
+        if let Some(path) = &mut self.state_path {
+            if path.last() != Some(&state) {
+                path.push(state);
+            }
+        }
+    }
+
+    /// Record the states the TMS sequence `tms` goes through from `state`
+    fn record_tms_path(&mut self, mut state: TapState, (tms, bits): (u32, usize)) {
+        for i in 0..bits {
+            state = state.next(tms >> i & 1 != 0);
+            self.record_state(state);
+        }
+    }
+
+    /// Move the TAP to `target` along the shortest path from the tracked state
+    ///
+    /// Resets first if the state is unknown.
+    pub(crate) fn goto_state(&mut self, target: TapState) -> Result<(), JtagError> {
+        let current = match self.state {
+            Some(state) => {
+                self.start_state_path(state);
+                state
+            }
+            None => {
+                self.reset()?;
+                TapState::RunTestIdle
+            }
+        };
+        let (tms, bits) = tms_path(current, target)?;
+        tracing::trace!("{:?} -> {:?}", current, target);
+        if bits > 0 {
+            self.shift_tms(&tms.to_le_bytes(), bits)?;
+        }
+        Ok(())
+    }
+
+    /// Clock `cycles` TCKs with a constant TMS level
+    pub(crate) fn clock_tms(&mut self, level: bool, cycles: usize) -> Result<(), JtagError> {
+        if cycles == 0 {
+            return Ok(());
+        }
+        let tms = BitVec::<Lsb0, u8>::repeat(level, cycles);
+        self.shift_tms(tms.as_slice(), cycles)
+    }
+
+    /// Clock a single TCK with the given TMS and TDI levels
+    pub(crate) fn clock_bit(&mut self, tms: bool, tdi: bool) -> Result<(), JtagError> {
+        self.shift_tms_tdi(&[tms as u8], 1, tdi)
+    }
+
+    /// Clock out `tdi` with TMS held at `tms`, returning TDO if `capture` is set
+    ///
+    /// Every bit is its own TMS shift, so this works from any state, not
+    /// only SHIFT-xR. Meant for short sequences outside the scan structure.
+    pub fn shift_raw(
+        &mut self,
+        tms: bool,
+        tdi: &BitSlice<Lsb0, u8>,
+        capture: bool,
+    ) -> Result<BitVec<Lsb0, u8>, JtagError> {
+        let read = if capture { 0x20 } else { 0x00 };
+        let mut command = Vec::with_capacity(tdi.len() * 3);
+        for &bit in tdi.iter() {
+            let byte = (bit as u8) << 7 | tms as u8;
+            command.extend_from_slice(&[0x4a | read | self.clock_edge.opcode_bits(), 0, byte]);
+        }
+        if let Some(mut state) = self.state {
+            for _ in 0..tdi.len() {
+                state = state.next(tms);
+                self.record_state(state);
+            }
+            self.state = Some(state);
+        }
+        self.queue(&command)?;
+        if !capture {
+            return Ok(BitVec::new());
+        }
+        // The single bit of each TMS shift comes back in the top bit
+        let reply = self.read_response(tdi.len())?;
+        Ok(reply.iter().map(|byte| byte & 0x80 != 0).collect())
+    }
+
+    /// Shift IR or DR capturing TDO, then move on to `end`
+    pub(crate) fn scan_to(
+        &mut self,
+        ir: bool,
+        data: &[u8],
+        bits: usize,
+        end: TapState,
+    ) -> Result<Vec<u8>, JtagError> {
+        let shift = if ir {
+            TapState::ShiftIr
+        } else {
+            TapState::ShiftDr
+        };
+        self.goto_state(shift)?;
+        self.tranfer_tdi(data, bits, end)
+    }
+
+    /// TMS path from EXIT1 of the current shift state to `end`
+    ///
+    /// Only valid in SHIFT-xR, where the state is always known.
+    fn exit_path(&self, end: TapState) -> Result<(u32, usize), JtagError> {
+        let shift = self.state.expect("state is known in SHIFT-xR");
+        tms_path(shift.next(true), end)
+    }
+
+    /// Record the states from SHIFT-xR through EXIT1-xR along `exit`
+    fn record_exit(&mut self, exit: (u32, usize)) {
+        let exit1 = self.state.expect("state is known in SHIFT-xR").next(true);
+        self.record_state(exit1);
+        self.record_tms_path(exit1, exit);
+    }
+
+    /// Shift from SHIFT-xR, then move on to `end`
+    ///
+    /// Invalid lengths are rejected before anything is queued, leaving the
+    /// TAP in SHIFT-xR.
+    fn shift_tdi(&mut self, data: &[u8], bits: usize, end: TapState) -> Result<(), JtagError> {
+        let exit = self.exit_path(end)?;
+        let command = tdi_command(data, bits, false, exit, self.clock_edge)?;
+        self.record_exit(exit);
+        self.state = Some(end);
+        self.queue(&command)
+    }
+
+    fn tranfer_tdi(
+        &mut self,
+        data: &[u8],
+        bits: usize,
+        end: TapState,
+    ) -> Result<Vec<u8>, JtagError> {
+        if self.loopback {
+            log::warn!("capturing TDO with loopback enabled, reads will echo writes");
+        }
+
+        let exit = self.exit_path(end)?;
+        let command = tdi_command(data, bits, true, exit, self.clock_edge)?;
+        self.record_exit(exit);
+        self.state = Some(end);
+        self.queue(&command)?;
+        let reply = self.read_response(tdi_reply_len(bits))?;
+        Ok(tdi_reply(reply, bits, exit.1))
+    }
+
+    /// Set the number of TMS-high clocks used by `reset`
+    ///
+    /// Five clocks reach TEST-LOGIC-RESET from any state.
+    pub fn set_reset_clocks(&mut self, clocks: usize) {
+        self.reset_clocks = clocks;
+    }
+
+    /// Set extra TCK cycles spent in TEST-LOGIC-RESET by `reset`
+    ///
+    /// For parts whose internal reset needs clocks beyond the standard five.
+    pub fn set_tlr_dwell(&mut self, cycles: usize) {
+        self.tlr_dwell = cycles;
+    }
+
+    /// Keep `reset` in the command queue instead of flushing it right away
+    ///
+    /// Commands always reach the device in the order they were queued. By
+    /// default `reset` also writes the queue out, so the TAPs are reset when
+    /// it returns. Queued, the reset goes out with the next read or `flush`,
+    /// as part of one atomic write with the commands around it.
+    pub fn set_queue_reset(&mut self, enable: bool) {
+        self.queue_reset = enable;
+    }
+
+    /// Set the number of TAPs `scan` looks for before giving up
+    pub fn set_max_devices(&mut self, count: usize) {
+        self.max_devices = count;
+    }
+
+    /// Set the state IR and DR scans leave the TAP in, RUN-TEST/IDLE by default
+    ///
+    /// Parking in PAUSE-DR or PAUSE-IR keeps run-test counters from advancing
+    /// between operations. A parked scan is only updated when the next one
+    /// starts, or on `idle`. Only the stable states RUN-TEST/IDLE, PAUSE-DR
+    /// and PAUSE-IR are accepted.
+    pub fn set_park_state(&mut self, state: TapState) -> Result<(), JtagError> {
+        match state {
+            TapState::RunTestIdle | TapState::PauseDr | TapState::PauseIr => {
+                self.park_state = state;
+                Ok(())
+            }
+            _ => Err(JtagError::InvalidInput(
+                "the TAP can only be parked in RUN-TEST/IDLE, PAUSE-DR or PAUSE-IR",
+            )),
+        }
+    }
+
+    /// State scans leave the TAP in
+    pub fn park_state(&self) -> TapState {
+        self.park_state
+    }
+
+    /// Record every TAP state change, resetting the record with each operation
+    ///
+    /// `last_state_path` then returns the states the last reset, scan or
+    /// `idle` went through. Off by default.
+    pub fn set_record_state_path(&mut self, enable: bool) {
+        self.state_path = if enable { Some(Vec::new()) } else { None };
+    }
+
+    /// States the last operation went through, starting with the one it started in
+    ///
+    /// Staying in a state for several clocks is recorded once. Empty unless
+    /// enabled with `set_record_state_path`.
+    pub fn last_state_path(&self) -> &[TapState] {
+        self.state_path.as_deref().unwrap_or(&[])
+    }
+
+    /// Reset and go to RUN-TEST/IDLE
+    pub fn reset(&mut self) -> Result<(), JtagError> {
+        // The clocks into TEST-LOGIC-RESET and on to RUN-TEST/IDLE go out as one shift
+        let mut tms = BitVec::<Lsb0, u8>::repeat(true, self.reset_clocks + self.tlr_dwell);
+        let (path, bits) = TapState::TestLogicReset
+            .path_to(TapState::RunTestIdle)
+            .expect("RUN-TEST/IDLE follows TEST-LOGIC-RESET");
+        tms.extend((0..bits).map(|i| path >> i & 1 != 0));
+
+        self.state = None;
+        self.shift_tms(tms.as_slice(), tms.len())?;
+        self.state = Some(TapState::RunTestIdle);
+        self.start_state_path(TapState::TestLogicReset);
+        self.record_state(TapState::RunTestIdle);
+        if self.queue_reset {
+            Ok(())
+        } else {
+            self.flush()
+        }
+    }
+
+    /// Execute RUN-TEST/IDLE for a number of cycles
+    pub fn idle(&mut self, cycles: usize) -> Result<(), JtagError> {
+        self.goto_state(TapState::RunTestIdle)?;
+        if cycles == 0 {
+            return Ok(());
+        }
+        let mut buf = vec![];
+        buf.resize((cycles + 7) / 8, 0);
+        self.shift_tms(&buf, cycles)
+    }
+
+    /// Shift to IR and return to the park state
+    #[tracing::instrument(level = "debug", skip(self, data))]
+    pub fn shift_ir(&mut self, data: &[u8], bits: usize) -> Result<(), JtagError> {
+        self.goto_state(TapState::ShiftIr)?;
+        self.shift_tdi(data, bits, self.park_state)
+    }
+
+    /// Shift to IR and return to the park state
+    #[tracing::instrument(level = "debug", skip(self, data))]
+    pub fn transfer_ir(&mut self, data: &[u8], bits: usize) -> Result<Vec<u8>, JtagError> {
+        self.goto_state(TapState::ShiftIr)?;
+        self.tranfer_tdi(data, bits, self.park_state)
+    }
+
+    /// Shift to DR and return to the park state
+    #[tracing::instrument(level = "debug", skip(self, data))]
+    pub fn transfer_dr(&mut self, data: &[u8], bits: usize) -> Result<Vec<u8>, JtagError> {
+        if let Some(rate_limit) = &mut self.rate_limit {
+            rate_limit.acquire();
+        }
+        self.goto_state(TapState::ShiftDr)?;
+        match self.tranfer_tdi(data, bits, self.park_state) {
+            Err(e @ JtagError::Timeout { .. }) if self.auto_clock_fallback => {
+                self.transfer_dr_fallback(data, bits, e)
+            }
+            r => r,
+        }
+    }
+
+    /// Retry a timed-out `transfer_dr` at halved clocks down to `MIN_FALLBACK_HZ`
+    ///
+    /// The original clock is restored afterwards, whether a retry succeeded
+    /// or not.
+    fn transfer_dr_fallback(
+        &mut self,
+        data: &[u8],
+        bits: usize,
+        mut err: JtagError,
+    ) -> Result<Vec<u8>, JtagError> {
+        let original = self.clock_hz();
+        let mut hz = original;
+        while hz / 2 >= MIN_FALLBACK_HZ {
+            // Drop whatever is left of the late reply before retrying
+            self.sync()?;
+            hz = self.set_clock(hz / 2)?;
+            log::warn!("{}, retrying at {} Hz", err, hz);
+
+            self.goto_state(TapState::ShiftDr)?;
+            match self.tranfer_tdi(data, bits, self.park_state) {
+                Ok(r) => {
+                    log::info!("transfer succeeded at {} Hz, back to {} Hz", hz, original);
+                    self.set_clock(original)?;
+                    return Ok(r);
+                }
+                Err(e @ JtagError::Timeout { .. }) => err = e,
+                Err(e) => return Err(e),
+            }
+        }
+        self.sync()?;
+        self.set_clock(original)?;
+        Err(err)
+    }
+
+    /// `transfer_ir` on a bit slice, returning exactly as many bits as shifted in
+    pub fn transfer_ir_bits(
+        &mut self,
+        data: &BitSlice<Lsb0, u8>,
+    ) -> Result<BitVec<Lsb0, u8>, JtagError> {
+        let r = self.transfer_ir(&bits_to_bytes(data), data.len())?;
+        Ok(bytes_to_bits(r, data.len()))
+    }
+
+    /// `transfer_dr` on a bit slice, returning exactly as many bits as shifted in
+    pub fn transfer_dr_bits(
+        &mut self,
+        data: &BitSlice<Lsb0, u8>,
+    ) -> Result<BitVec<Lsb0, u8>, JtagError> {
+        let r = self.transfer_dr(&bits_to_bytes(data), data.len())?;
+        Ok(bytes_to_bits(r, data.len()))
+    }
+
+    /// Shift to DR without capturing TDO and return to the park state
+    ///
+    /// Nothing is read back, so the scan stays queued until the next read
+    /// or `flush`.
+    #[tracing::instrument(level = "debug", skip(self, data))]
+    pub fn shift_dr(&mut self, data: &[u8], bits: usize) -> Result<(), JtagError> {
+        if let Some(rate_limit) = &mut self.rate_limit {
+            rate_limit.acquire();
+        }
+        self.goto_state(TapState::ShiftDr)?;
+        self.shift_tdi(data, bits, self.park_state)
+    }
+
+    /// Shift `drive` into the boundary register and latch it through UPDATE-DR
+    ///
+    /// Used for EXTEST pin driving. TDO is not captured. Ends in
+    /// RUN-TEST/IDLE whatever the park state, so the pins change right away.
+    pub fn shift_dr_drive(
+        &mut self,
+        drive: &BitVec<Lsb0, u8>,
+        boundary_len: usize,
+    ) -> Result<(), JtagError> {
+        if boundary_len == 0 || drive.len() < boundary_len {
+            return Err(JtagError::InvalidInput(
+                "drive pattern doesn't cover the boundary register",
+            ));
+        }
+        let mut data = drive.clone();
+        data.truncate(boundary_len);
+
+        self.goto_state(TapState::ShiftDr)?;
+        // EXIT1-DR -> UPDATE-DR -> RUN-TEST/IDLE
+        self.shift_tdi(data.as_slice(), boundary_len, TapState::RunTestIdle)
+    }
+
+    /// Build a batch of IR/DR scans
+    pub fn sequence(&mut self) -> Sequence<'_, D> {
+        Sequence {
+            adapter: self,
+            ops: vec![],
+        }
+    }
+
+    /// Same as `transfer_dr`, but also report how long the transfer took
+    pub fn transfer_dr_timed(
+        &mut self,
+        data: &[u8],
+        bits: usize,
+    ) -> Result<TimedTransfer, JtagError> {
+        let t0 = Instant::now();
+        let tdo = self.transfer_dr(data, bits)?;
+        Ok(TimedTransfer {
+            tdo,
+            bits,
+            elapsed: t0.elapsed(),
+        })
+    }
+
+    /// Time DR transfers of `bytes` bytes and of a single byte, averaged over a few runs
+    ///
+    /// Resets the TAPs first, so the pattern goes through IDCODE or BYPASS
+    /// registers and doesn't disturb the targets.
+    pub fn benchmark(&mut self, bytes: usize) -> Result<BenchResult, JtagError> {
+        const RUNS: usize = 5;
+        if bytes == 0 {
+            return Err(JtagError::InvalidInput("benchmark needs at least one byte"));
+        }
+        let pattern: Vec<u8> = (0..bytes).map(|i| i as u8).collect();
+
+        self.reset()?;
+        let mut transfer_time = Duration::from_secs(0);
+        let mut latency = Duration::from_secs(0);
+        for _ in 0..RUNS {
+            transfer_time += self.transfer_dr_timed(&pattern, bytes * 8)?.elapsed;
+            latency += self.transfer_dr_timed(&pattern[..1], 8)?.elapsed;
+        }
+        let transfer_time = transfer_time / RUNS as u32;
+        let latency = latency / RUNS as u32;
+
+        let kbit_per_sec = (bytes * 8) as f64 / transfer_time.as_secs_f64() / 1000.0;
+        log::debug!(
+            "{} bytes in {:?} ({:.1} kbit/s), latency {:?}",
+            bytes,
+            transfer_time,
+            kbit_per_sec,
+            latency
+        );
+        Ok(BenchResult {
+            bytes,
+            runs: RUNS,
+            transfer_time,
+            kbit_per_sec,
+            latency,
+        })
+    }
+
+    /// Time a byte shift of `bytes` bytes through the enabled loopback, averaged over a few runs
+    fn time_loopback(&mut self, bytes: usize) -> Result<Duration, JtagError> {
+        const RUNS: u32 = 5;
+        let pattern: Vec<u8> = (0..bytes).map(|i| i as u8).collect();
+        let n = (bytes - 1) as u16;
+
+        let mut elapsed = Duration::from_secs(0);
+        for _ in 0..RUNS {
+            let t0 = Instant::now();
+            self.queue(&[0x39, n as u8, (n >> 8) as u8])?;
+            self.queue(&pattern)?;
+            self.read_response(bytes)?;
+            elapsed += t0.elapsed();
+        }
+        Ok(elapsed / RUNS)
+    }
+
+    /// Measure the USB round trip and size the command queue and reads to match
+    ///
+    /// Times byte shifts through the MPSSE loopback at a few sizes. TMS isn't
+    /// touched and TDO never comes from the chain, so the TAP state and the
+    /// targets are left alone. The latency is the time of a one-byte
+    /// transfer, the throughput the slope fitted over all sizes.
+    ///
+    /// The flush threshold is what that throughput clocks out during one
+    /// round trip, rounded up to a power of two between 512 bytes and
+    /// 64 KiB. Smaller writes leave the link idle while waiting for replies,
+    /// larger ones only delay the first reply. The minimum read size is the
+    /// largest chunk a single read returned during the measurement, which
+    /// is as much as the device hands over at once, rounded up to a power of
+    /// two between 64 bytes and the flush threshold.
+    pub fn auto_tune(&mut self) -> Result<TuneResult, JtagError> {
+        let was_enabled = self.loopback;
+        let min_read_size = self.min_read_size;
+        self.set_loopback(true)?;
+        // Offer the device room for everything it has, to see how much that is
+        self.set_min_read_size(FLUSH_THRESHOLD);
+        self.largest_read = 0;
+
+        let times = TUNE_SIZES
+            .iter()
+            .map(|&bytes| self.time_loopback(bytes))
+            .collect::<Result<Vec<_>, _>>();
+        self.set_min_read_size(min_read_size);
+        self.set_loopback(was_enabled)?;
+        let times = times?;
+        for (bytes, time) in TUNE_SIZES.iter().zip(&times) {
+            log::debug!("loopback of {} bytes: {:?}", bytes, time);
+        }
+
+        // Least-squares slope of the transfer time over the size
+        let n = TUNE_SIZES.len() as f64;
+        let mean_bytes = TUNE_SIZES.iter().sum::<usize>() as f64 / n;
+        let mean_secs = times.iter().map(Duration::as_secs_f64).sum::<f64>() / n;
+        let (mut covariance, mut variance) = (0.0, 0.0);
+        for (&bytes, time) in TUNE_SIZES.iter().zip(&times) {
+            let dx = bytes as f64 - mean_bytes;
+            covariance += dx * (time.as_secs_f64() - mean_secs);
+            variance += dx * dx;
+        }
+        let secs_per_byte = (covariance / variance).max(0.0);
+        let latency = times[0];
+        let bytes_per_sec = 1.0 / secs_per_byte;
+        let kbit_per_sec = bytes_per_sec * 8.0 / 1000.0;
+
+        // Saturates to usize::MAX if no time per byte was measurable
+        let in_flight = (bytes_per_sec * latency.as_secs_f64()) as usize;
+        let chunk = in_flight
+            .min(FLUSH_THRESHOLD)
+            .next_power_of_two()
+            .max(MIN_TUNED_CHUNK);
+        let read_size = self
+            .largest_read
+            .next_power_of_two()
+            .clamp(MIN_TUNED_READ, chunk);
+        log::debug!(
+            "{:.1} kbit/s, latency {:?}: {} bytes in flight, chunk {}, reads of up to {} bytes",
+            kbit_per_sec,
+            latency,
+            in_flight,
+            chunk,
+            self.largest_read
+        );
+
+        self.set_flush_threshold(chunk);
+        self.set_min_read_size(read_size);
+        Ok(TuneResult {
+            latency,
+            kbit_per_sec,
+            flush_threshold: chunk,
+            min_read_size: read_size,
+        })
+    }
+
+    /// Scan the chain for the IDCODE and IR length of every TAP
+    ///
+    /// If the IR capture can't be parsed, the total from `detect_irlen`
+    /// stands in for the length of the last TAP. An unusual capture earlier
+    /// in the chain still fails the scan.
+    pub fn scan_chain(&mut self) -> Result<Vec<JtagChainItem>, JtagError> {
+        self.scan_chain_with(|_| {})
+    }
+
+    /// Scan the chain, calling `f` for each TAP once its IR length is known
+    #[tracing::instrument(level = "debug", skip(self, f))]
+    pub fn scan_chain_with<F: FnMut(&JtagChainItem)>(
+        &mut self,
+        mut f: F,
+    ) -> Result<Vec<JtagChainItem>, JtagError> {
+        let max_device_count = self.max_devices;
+
+        self.reset()?;
+
+        // Room for an IDCODE per TAP, followed by the shifted-in ones
+        let cmd = vec![0xff; (max_device_count + 1) * 4];
+        // A cold device may stop answering partway through the reply
+        let r = match self.transfer_dr(&cmd, cmd.len() * 8) {
+            Err(JtagError::Timeout { expected, got }) => {
+                return Err(JtagError::ShortRead { expected, got })
+            }
+            r => r?,
+        };
+        if r.len() < cmd.len() {
+            return Err(JtagError::ShortRead {
+                expected: cmd.len(),
+                got: r.len(),
+            });
+        }
+        let capture = BitVec::<Lsb0, u8>::from_vec(r);
+        let mut targets = vec![];
+        for idcode in idcodes_from_capture(&capture, max_device_count) {
+            match idcode {
+                0 => log::debug!("tap found: no IDCODE"),
+                idcode => log::debug!("tap found: {:08x}", idcode),
+            }
+            targets.push(JtagChainItem::new(idcode, 0));
+        }
+        if targets.len() == max_device_count {
+            log::warn!(
+                "chain has at least {} TAPs, raise the limit with set_max_devices",
+                max_device_count
+            );
+        }
+
+        self.reset()?;
+        let irlens = match self.scan_ir_lengths(targets.len()) {
+            Ok(irlens) => irlens,
+            Err(e) => self.ir_lengths_from_bypass(targets.len(), e)?,
+        };
+        for (target, irlen) in targets.iter_mut().zip(irlens) {
+            target.irlen = irlen;
+            f(target);
+        }
+
+        self.chain = targets.clone();
+        Ok(targets)
+    }
+
+    /// Drop the cached chain and scan it again
+    ///
+    /// For chains that changed since the last scan, e.g. after a power cycle
+    /// of the board. Select the target again afterwards.
+    pub fn rescan(&mut self) -> Result<Vec<JtagChainItem>, JtagError> {
+        self.chain.clear();
+        self.scan_chain()
+    }
+
+    /// The cached chain, scanning it first if there is none
+    fn cached_chain(&mut self) -> Result<Vec<JtagChainItem>, JtagError> {
+        if self.chain.is_empty() {
+            self.scan_chain()
+        } else {
+            Ok(self.chain.clone())
+        }
+    }
+
+    /// Scan the chain and fail unless it has exactly `count` TAPs
+    pub fn expect_devices(&mut self, count: usize) -> Result<(), JtagError> {
+        let found = self.scan_chain()?.len();
+        if found != count {
+            return Err(JtagError::UnexpectedChain {
+                expected: count,
+                found,
+            });
+        }
+        Ok(())
+    }
+
+    /// Capture the IRs of `count` TAPs, followed by the shifted-in ones
+    fn capture_ir(&mut self, count: usize) -> Result<BitVec<Lsb0, u8>, JtagError> {
+        // Room for IRs of up to 32 bits each
+        let cmd = vec![0xff; (count + 1) * 4];
+        let r = self.transfer_ir(&cmd, cmd.len() * 8)?;
+        Ok(BitVec::from_vec(r))
+    }
+
+    /// Capture IR after reset and infer the lengths of `count` TAPs
+    fn scan_ir_lengths(&mut self, count: usize) -> Result<Vec<usize>, JtagError> {
+        let capture = self.capture_ir(count)?;
+
+        let irlens = ir_lengths_from_capture(&capture, count)?;
+        for (i, irlen) in irlens.iter().enumerate() {
+            log::debug!("tap {} irlen: {}", i, irlen);
+        }
+        Ok(irlens)
+    }
+
+    /// Measure the chain by timing a marker bit, returning (total IR bits, TAP count)
+    ///
+    /// Fills every IR with ones (BYPASS) behind a single zero and counts the
+    /// clocks until the zero comes out, then does the same through the
+    /// 1-bit BYPASS registers. Doesn't rely on the `...01` IR capture.
+    pub fn detect_irlen(&mut self) -> Result<(usize, usize), JtagError> {
+        // Room for IRs of up to 32 bits per TAP
+        let max = self.max_devices * 32;
+        let mut data = BitVec::<Lsb0, u8>::repeat(true, 2 * max + 1);
+        data.set(max, false);
+
+        self.reset()?;
+        let r = self.transfer_ir(data.as_slice(), data.len())?;
+        let ir_bits = marker_position(&BitVec::<Lsb0, u8>::from_vec(r), max)
+            .ok_or_else(|| JtagError::InvalidChain("IR marker bit lost".to_string()))?;
+
+        let r = self.transfer_dr(data.as_slice(), data.len())?;
+        let taps = marker_position(&BitVec::<Lsb0, u8>::from_vec(r), max)
+            .ok_or_else(|| JtagError::InvalidChain("BYPASS marker bit lost".to_string()))?;
+
+        log::debug!("chain has {} TAPs with {} IR bits in total", taps, ir_bits);
+        Ok((ir_bits, taps))
+    }
+
+    /// Fall back on `detect_irlen` when the IR capture can't be parsed
+    ///
+    /// The capture is trusted for all TAPs but the last one, which gets the
+    /// rest of the measured total. A chain whose capture already can't be
+    /// parsed before the last TAP keeps the original error.
+    fn ir_lengths_from_bypass(
+        &mut self,
+        count: usize,
+        err: JtagError,
+    ) -> Result<Vec<usize>, JtagError> {
+        log::warn!("{}, measuring the chain through BYPASS", err);
+        let (ir_bits, taps) = self.detect_irlen()?;
+        if taps != count {
+            return Err(JtagError::InvalidChain(format!(
+                "IDCODE scan found {} TAPs, BYPASS scan {}",
+                count, taps
+            )));
+        }
+
+        self.reset()?;
+        let capture = self.capture_ir(count)?;
+        let mut irlens = match leading_ir_lengths(&capture, count - 1) {
+            Ok(irlens) => irlens,
+            Err(_) => return Err(err),
+        };
+        match ir_bits.checked_sub(irlens.iter().sum()) {
+            Some(last @ 1..=32) => {
+                log::debug!("tap {} irlen from the total: {}", count - 1, last);
+                irlens.push(last);
+                Ok(irlens)
+            }
+            _ => Err(err),
+        }
+    }
+
+    /// Re-derive IR lengths for a chain with known IDCODEs, skipping the IDCODE scan
+    pub fn detect_ir_lengths_for(&mut self, idcodes: &[u32]) -> Result<Vec<usize>, JtagError> {
+        self.reset()?;
+        let irlens = self.scan_ir_lengths(idcodes.len())?;
+
+        self.chain = idcodes
+            .iter()
+            .zip(&irlens)
+            .map(|(&idcode, &irlen)| JtagChainItem::new(idcode, irlen))
+            .collect();
+        Ok(irlens)
+    }
+
+    /// Take the chain from known IR lengths instead of scanning it
+    ///
+    /// For chains whose IR capture can't be parsed. The TAPs get no IDCODE,
+    /// select one with `select_target_by_index` afterwards.
+    pub fn set_ir_lengths(&mut self, irlens: &[usize]) -> Result<(), JtagError> {
+        if irlens.iter().any(|&irlen| irlen == 0 || irlen > 32) {
+            return Err(JtagError::InvalidInput("IR lengths must be 1 to 32 bits"));
+        }
+        self.chain = irlens
+            .iter()
+            .map(|&irlen| JtagChainItem::new(0, irlen))
+            .collect();
+        self.chain_params = None;
+        Ok(())
+    }
+
+    /// Generate OpenOCD adapter and `jtag newtap` config for the last scanned chain
+    pub fn export_openocd_config(&self) -> String {
+        let channel = match self.interface {
+            ftdi::Interface::A | ftdi::Interface::Any => 0,
+            ftdi::Interface::B => 1,
+            ftdi::Interface::C => 2,
+            ftdi::Interface::D => 3,
+        };
+
+        let mut config = String::new();
+        config += "adapter driver ftdi\n";
+        config += &format!("ftdi vid_pid {:#06x} {:#06x}\n", self.vid, self.pid);
+        config += &format!("ftdi channel {}\n", channel);
+        // Same values as written by attach()
+        config += &format!(
+            "ftdi layout_init {:#06x} {:#06x}\n",
+            self.gpio_output, self.gpio_direction
+        );
+        config += "transport select jtag\n";
+        config += "\n";
+        for (i, tap) in self.chain.iter().enumerate() {
+            config += &format!("jtag newtap chip{} tap -irlen {}", i, tap.irlen);
+            if tap.idcode != 0 {
+                config += &format!(" -expected-id {:#010x}", tap.idcode);
+            }
+            config += "\n";
+        }
+        config
+    }
+
+    /// Select the TAP with the given IDCODE for register transfers
+    pub fn select_target(&mut self, idcode: u32) -> Result<(), JtagError> {
+        self.select_target_masked(idcode, 0xffffffff)
+    }
+
+    /// Select the TAP whose IDCODE matches `idcode` in the bits set in `mask`
+    ///
+    /// A mask of `0x0fffffff` ignores the version field. The chain is only
+    /// scanned if it isn't cached yet, see `rescan`.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn select_target_masked(&mut self, idcode: u32, mask: u32) -> Result<(), JtagError> {
+        let taps = self.cached_chain()?;
+
+        let matches = taps
+            .iter()
+            .filter(|tap| tap.idcode & mask == idcode & mask)
+            .count();
+        if matches > 1 {
+            return Err(JtagError::AmbiguousTarget { count: matches });
+        }
+
+        match taps
+            .iter()
+            .position(|tap| tap.idcode & mask == idcode & mask)
+        {
+            Some(index) => self.select_target_by_index(index),
+            None => Err(JtagError::TargetNotFound),
+        }
+    }
+
+    /// Select the TAP at `index` in the chain, in `scan_chain` order
+    ///
+    /// Needed when several TAPs share an IDCODE, which `select_target`
+    /// refuses as ambiguous.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn select_target_by_index(&mut self, index: usize) -> Result<(), JtagError> {
+        let taps = self.cached_chain()?;
+        if index >= taps.len() {
+            return Err(JtagError::TargetNotFound);
+        }
+
+        let params = chain_params(&taps, index);
+        log::debug!("Target chain params: {:?}", params);
+        self.chain_params = Some(params);
+        Ok(())
+    }
+
+    /// Probe each TAP for its BYPASS and IDCODE instructions
+    ///
+    /// Every IR value except all-zeros (usually EXTEST) is tried on TAPs with
+    /// an IDCODE and IRs of up to `MAX_SEARCH_IRLEN` bits. Unknown
+    /// instructions may have side effects, so only run this on boards where
+    /// that is acceptable.
+    pub fn discover_instructions(&mut self) -> Result<Vec<TapInstructions>, JtagError> {
+        const MAX_SEARCH_IRLEN: usize = 10;
+
+        let taps = self.scan_chain()?;
+        let mut found = vec![];
+        for (index, tap) in taps.iter().enumerate() {
+            let params = chain_params(&taps, index);
+            let all_ones = ((1u64 << params.irlen) - 1) as u32;
+            // The other TAPs are in BYPASS and add one bit each
+            let others = taps.len() - 1;
+
+            self.write_target_ir(&params, all_ones)?;
+            let bypass = self.measure_dr_len(others + 64)? == Some(others + 1);
+
+            let mut idcode = None;
+            // A TAP without IDCODE has nothing to look for
+            if tap.idcode != 0 && params.irlen <= MAX_SEARCH_IRLEN {
+                for ir in 1..all_ones {
+                    self.write_target_ir(&params, ir)?;
+                    if self.capture_target_dr32(&params)? == tap.idcode
+                        && self.measure_dr_len(others + 64)? == Some(others + 32)
+                    {
+                        idcode = Some(ir);
+                        break;
+                    }
+                }
+            }
+
+            log::debug!(
+                "tap {}: bypass {}, idcode instruction {:?}",
+                index,
+                bypass,
+                idcode
+            );
+            found.push(TapInstructions { bypass, idcode });
+        }
+
+        self.reset()?;
+        Ok(found)
+    }
+
+    /// Length of the DR path through the chain, `None` if longer than `max` bits
+    fn measure_dr_len(&mut self, max: usize) -> Result<Option<usize>, JtagError> {
+        // Captured bits come out first, followed by `max` zeros and a marker
+        let mut data = BitVec::<Lsb0, u8>::repeat(false, 2 * max + 1);
+        data.set(max, true);
+        let r = self.transfer_dr(data.as_slice(), data.len())?;
+
+        let r = BitVec::<Lsb0, u8>::from_vec(r);
+        Ok(r[max..].iter().position(|&bit| bit))
+    }
+
+    /// Capture 32 bits of DR from the target described by `params`
+    fn capture_target_dr32(&mut self, params: &ChainParams) -> Result<u32, JtagError> {
+        let drbits = params.drpre + 32 + params.drpost;
+        let r = self.transfer_dr(&vec![0; (drbits + 7) / 8], drbits)?;
+
+        let r = BitVec::<Lsb0, u8>::from_vec(r);
+        let r = bits_to_bytes(&r[params.drpre..params.drpre + 32]);
+        u32_from_reply(&r)
+    }
+
+    /// Capture the selected target's IR, returning the bits above the mandatory `01`
+    ///
+    /// Their meaning is device-specific. All TAPs are left in BYPASS.
+    pub fn read_ir_status(&mut self) -> Result<BitVec<Lsb0, u8>, JtagError> {
+        let params = self.get_chain_params()?;
+        if params.irlen < 2 {
+            return Err(JtagError::InvalidChain(format!(
+                "IR of {} bits has no room for the `01` capture",
+                params.irlen
+            )));
+        }
+        let irbits = params.irpre + params.irlen + params.irpost;
+        let r = self.transfer_ir(&vec![0xff; (irbits + 7) / 8], irbits)?;
+
+        let capture = BitVec::<Lsb0, u8>::from_vec(r);
+        let status = &capture[params.irpre + 2..params.irpre + params.irlen];
+        Ok(BitVec::from_bitslice(status))
+    }
+
+    fn get_chain_params(&self) -> Result<ChainParams, JtagError> {
+        match &self.chain_params {
+            Some(params) => Ok(params.clone()),
+            None => Err(JtagError::TargetNotSelected),
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, data))]
+    pub(crate) fn target_transfer(
+        &mut self,
+        address: u32,
+        data: Option<&[u8]>,
+        len_bits: usize,
+    ) -> Result<Vec<u8>, JtagError> {
+        self.target_select_register(address)?;
+        self.target_dr(data, len_bits)
+    }
+
+    /// Load the target IR with `address`, leaving the other TAPs in BYPASS
+    pub(crate) fn target_select_register(&mut self, address: u32) -> Result<(), JtagError> {
+        let params = self.get_chain_params()?;
+        self.write_target_ir(&params, address)
+    }
+
+    /// DR half of `target_transfer`, for the register already selected in IR
+    pub(crate) fn target_dr(
+        &mut self,
+        data: Option<&[u8]>,
+        len_bits: usize,
+    ) -> Result<Vec<u8>, JtagError> {
+        check_dr_len(data, len_bits)?;
+        let params = self.get_chain_params()?;
+        let drbits = params.drpre + len_bits + params.drpost;
+        let request = if let Some(data) = data {
+            dr_request(&params, data, len_bits)
+        } else {
+            vec![0; (drbits + 7) / 8]
+        };
+        let reply = self.transfer_dr(&request, drbits)?;
+
+        // Process the reply
+        let reply = BitVec::<Lsb0, u8>::from_vec(reply);
+        let reply = &reply[params.drpre..];
+        if self.verify_bypass && reply[len_bits..len_bits + params.drpost].any() {
+            return Err(JtagError::BypassMismatch);
+        }
+
+        Ok(bits_to_bytes(&reply[..len_bits]))
+    }
+
+    /// Write a target register without capturing TDO
+    #[tracing::instrument(level = "debug", skip(self, data))]
+    pub(crate) fn target_write(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        len_bits: usize,
+    ) -> Result<(), JtagError> {
+        check_dr_len(Some(data), len_bits)?;
+        let params = self.get_chain_params()?;
+        self.write_target_ir(&params, address)?;
+
+        let drbits = params.drpre + len_bits + params.drpost;
+        let request = dr_request(&params, data, len_bits);
+        self.shift_dr(&request, drbits)
+    }
+
+    /// 32-bit `target_transfer`, skipping the bit assembly on a single-TAP chain
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub(crate) fn target_transfer32(
+        &mut self,
+        address: u32,
+        value: Option<u32>,
+    ) -> Result<u32, JtagError> {
+        let params = self.get_chain_params()?;
+        let data = value.unwrap_or(0).to_le_bytes();
+
+        let r = if params.drpre == 0 && params.drpost == 0 {
+            self.write_target_ir(&params, address)?;
+            self.transfer_dr(&data, 32)?
+        } else {
+            self.target_transfer(address, value.map(|_| &data[..]), 32)?
+        };
+        u32_from_reply(&r)
+    }
+
+    /// Load `address` into the selected target's IR and BYPASS into the others
+    fn write_target_ir(&mut self, params: &ChainParams, address: u32) -> Result<(), JtagError> {
+        let irbits = params.irpre + params.irlen + params.irpost;
+        if irbits > 32 {
+            return Err(JtagError::InvalidChain(format!(
+                "IR chain of {} bits, at most 32 are supported",
+                irbits
+            )));
+        }
+
+        let max_address = ((1u64 << params.irlen) - 1) as u32;
+        if address > max_address {
+            return Err(JtagError::RegisterAddressOutOfRange {
+                addr: address,
+                max: max_address,
+                irlen: params.irlen,
+            });
+        }
+
+        let mut ir: u64 = (1 << params.irpre) - 1;
+        ir |= (address as u64) << params.irpre;
+        ir |= ((1 << params.irpost) - 1) << (params.irpre + params.irlen);
+        self.shift_ir(&(ir as u32).to_le_bytes(), irbits)
+    }
+}
+
+/// Check a shift length against the data given for it
+fn check_shift_len(data: &[u8], bits: usize) -> Result<(), JtagError> {
+    if bits == 0 {
+        return Err(JtagError::InvalidInput("shift length must not be zero"));
+    }
+    if data.len() * 8 < bits {
+        return Err(JtagError::InvalidInput(
+            "shift data shorter than its length",
+        ));
+    }
+    Ok(())
+}
+
+/// Check a register length against the data given for it
+fn check_dr_len(data: Option<&[u8]>, len_bits: usize) -> Result<(), JtagError> {
+    if len_bits == 0 {
+        return Err(JtagError::InvalidInput("register length must not be zero"));
+    }
+    match data {
+        Some(data) if data.len() * 8 < len_bits => Err(JtagError::InvalidInput(
+            "register data shorter than its length",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// The first 4 bytes of a reply as a little-endian `u32`
+fn u32_from_reply(r: &[u8]) -> Result<u32, JtagError> {
+    match r.get(0..4) {
+        Some(bytes) => Ok(u32::from_le_bytes(bytes.try_into().unwrap())),
+        None => Err(JtagError::ShortRead {
+            expected: 4,
+            got: r.len(),
+        }),
+    }
+}
+
+/// DR contents placing `len_bits` of `data` at the target, zeros for the BYPASS TAPs
+fn dr_request(params: &ChainParams, data: &[u8], len_bits: usize) -> Vec<u8> {
+    let mut data = BitVec::<Lsb0, u8>::from_slice(data);
+    data.truncate(len_bits);
+
+    let mut buf = BitVec::<Lsb0, u8>::new();
+    buf.resize(params.drpre, false);
+    buf.append(&mut data);
+    buf.resize(buf.len() + params.drpost, false);
+
+    buf.into_vec()
+}
+
+/// Pack bits into bytes, LSB first
+///
+/// Unlike `into_vec` on a `split_off` result, this doesn't keep the bit
+/// offset the slice had in its original storage.
+pub(crate) fn bits_to_bytes(bits: &BitSlice<Lsb0, u8>) -> Vec<u8> {
+    bits.iter()
+        .copied()
+        .collect::<BitVec<Lsb0, u8>>()
+        .into_vec()
+}
+
+/// Unpack the first `bits` bits of LSB-first bytes
+pub(crate) fn bytes_to_bits(bytes: Vec<u8>, bits: usize) -> BitVec<Lsb0, u8> {
+    let mut bits_vec = BitVec::from_vec(bytes);
+    bits_vec.truncate(bits);
+    bits_vec
+}
+
+/// Chain parameters for the TAP at `index`
+fn chain_params(taps: &[JtagChainItem], index: usize) -> ChainParams {
+    let mut params = ChainParams {
+        irpre: 0,
+        irpost: 0,
+        drpre: 0,
+        drpost: 0,
+        irlen: taps[index].irlen,
+    };
+    for tap in &taps[..index] {
+        params.irpre += tap.irlen;
+        params.drpre += 1;
+    }
+    for tap in &taps[index + 1..] {
+        params.irpost += tap.irlen;
+        params.drpost += 1;
+    }
+    params
+}
+
+struct SequenceOp {
+    ir: bool,
+    data: Vec<u8>,
+    bits: usize,
+    capture: bool,
+}
+
+/// A series of IR/DR scans sent to the device in a single write
+///
+/// Consecutive scans go through UPDATE-xR straight to the next SELECT-DR-SCAN
+/// instead of passing RUN-TEST/IDLE. The TAP ends in the park state.
+pub struct Sequence<'a, D: MpsseIo = ftdi::Device> {
+    adapter: &'a mut JtagAdapter<D>,
+    ops: Vec<SequenceOp>,
+}
+
+impl<'a, D: MpsseIo> Sequence<'a, D> {
+    /// Shift `bits` into IR
+    pub fn ir(self, data: &[u8], bits: usize) -> Self {
+        self.push(true, data.to_vec(), bits, false)
+    }
+
+    /// Capture `bits` of DR, shifting in zeros
+    pub fn dr_read(self, bits: usize) -> Self {
+        self.push(false, vec![0; (bits + 7) / 8], bits, true)
+    }
+
+    /// Shift `bits` into DR
+    pub fn dr_write(self, data: &[u8], bits: usize) -> Self {
+        self.push(false, data.to_vec(), bits, false)
+    }
+
+    fn push(mut self, ir: bool, data: Vec<u8>, bits: usize, capture: bool) -> Self {
+        self.ops.push(SequenceOp {
+            ir,
+            data,
+            bits,
+            capture,
+        });
+        self
+    }
+
+    /// Execute the sequence, returning the captured data of each `dr_read`
+    pub fn run(self) -> Result<Vec<Vec<u8>>, JtagError> {
+        if self.ops.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let park = self.adapter.park_state;
+        let edge = self.adapter.clock_edge;
+        let mut command = vec![];
+        let mut captures = vec![];
+        let shift = |op: &SequenceOp| {
+            if op.ir {
+                TapState::ShiftIr
+            } else {
+                TapState::ShiftDr
+            }
+        };
+        // Resets first if the state is unknown
+        self.adapter.goto_state(shift(&self.ops[0]))?;
+        for (i, op) in self.ops.iter().enumerate() {
+            // The exit from one scan carries the entry into the next
+            let end = self.ops.get(i + 1).map_or(park, shift);
+            let exit1 = shift(op).next(true);
+            let exit = tms_path(exit1, end)?;
+            command.extend(tdi_command(&op.data, op.bits, op.capture, exit, edge)?);
+            self.adapter.record_state(exit1);
+            self.adapter.record_tms_path(exit1, exit);
+            if op.capture {
+                captures.push((op.bits, exit.1));
+            }
+        }
+        self.adapter.queue(&command)?;
+
+        let total = captures.iter().map(|&(bits, _)| tdi_reply_len(bits)).sum();
+        let mut reply = self.adapter.read_response(total)?;
+
+        let mut results = vec![];
+        for (bits, exit_bits) in captures {
+            let rest = reply.split_off(tdi_reply_len(bits));
+            results.push(tdi_reply(reply, bits, exit_bits));
+            reply = rest;
+        }
+        Ok(results)
+    }
+}
+
+/// MPSSE commands clocking out `bits` of TMS, LSB first, with TDI held at `tdi`
+///
+/// A TMS shift clocks at most 7 bits, bit 7 of its data byte is the TDI
+/// level. The bits are repacked into as few 7-bit shifts as possible.
+fn tms_command(data: &[u8], bits: usize, tdi: bool, edge: ClockEdge) -> Result<Vec<u8>, JtagError> {
+    check_shift_len(data, bits)?;
+
+    let tms = BitSlice::<Lsb0, u8>::from_slice(data);
+    let mut command = vec![];
+    for chunk in tms[..bits].chunks(7) {
+        let byte = chunk
+            .iter()
+            .enumerate()
+            .fold((tdi as u8) << 7, |byte, (i, &bit)| byte | (bit as u8) << i);
+        command.extend_from_slice(&[0x4a | edge.opcode_bits(), (chunk.len() - 1) as u8, byte]);
+    }
+    Ok(command)
+}
+
+/// Mask of a GPIO that can be changed without disturbing the JTAG signals
+fn user_gpio_mask(pin: u8) -> Result<u16, JtagError> {
+    match pin {
+        0..=3 => Err(JtagError::InvalidInput("GPIOs 0-3 carry the JTAG signals")),
+        4..=15 => Ok(1 << pin),
+        _ => Err(JtagError::InvalidInput("GPIO number must be 0 to 15")),
+    }
+}
+
+/// Check that `pin` can carry RTCK next to the reset lines of `config`
+fn check_rtck_pin(pin: u8, config: &ResetConfig) -> Result<(), JtagError> {
+    user_gpio_mask(pin)?;
+    if config
+        .trst
+        .iter()
+        .chain(config.srst.iter())
+        .any(|reset| reset.pin == pin)
+    {
+        return Err(JtagError::InvalidInput("RTCK pin is wired to a reset line"));
+    }
+    Ok(())
+}
+
+/// TMS path from `from` to `to`, for `TapState::path_to`
+fn tms_path(from: TapState, to: TapState) -> Result<(u32, usize), JtagError> {
+    from.path_to(to)
+        .ok_or(JtagError::InvalidInput("no TMS path to the TAP state"))
+}
+
+/// MPSSE commands shifting `bits` of TDI, then following `exit` from EXIT1
+///
+/// The last bit goes out together with the TMS move to EXIT1, in one TMS
+/// shift that also carries up to 6 bits of the `exit` path (TMS LSB first,
+/// length in bits). Any rest of the path follows in plain TMS shifts.
+fn tdi_command(
+    mut data: &[u8],
+    mut bits: usize,
+    capture: bool,
+    exit: (u32, usize),
+    edge: ClockEdge,
+) -> Result<Vec<u8>, JtagError> {
+    check_shift_len(data, bits)?;
+
+    // TDO capture adds the read bit to each opcode
+    let read = if capture { 0x20 } else { 0x00 };
+    let read = read | edge.opcode_bits();
+    let mut command = vec![];
+
+    // A byte shift covers at most 65536 bytes, longer runs are split. Their
+    // replies simply concatenate, so the reply layout doesn't change.
+    let full_bytes = (bits - 1) / 8;
+    for chunk in data[..full_bytes].chunks(65536) {
+        command.extend_from_slice(&[0x18 | read]);
+        let n: u16 = (chunk.len() - 1) as u16;
+        command.extend_from_slice(&n.to_le_bytes());
+        command.extend_from_slice(chunk);
+    }
+    bits -= full_bytes * 8;
+    data = &data[full_bytes..];
+
+    let byte = data[0];
+    if bits > 1 {
+        let n = (bits - 2) as u8;
+        // Bits past the shift are left zero, so the stream doesn't depend on
+        // whatever the caller had there
+        let shifted = byte & ((1u16 << (bits - 1)) - 1) as u8;
+        command.extend_from_slice(&[0x1a | read, n, shifted]);
+    }
+
+    let (exit_tms, exit_bits) = exit;
+    let merged = exit_bits.min(6);
+    let last_bit = (byte >> (bits - 1)) & 0x01;
+    let exit_mask = (1 << merged) - 1;
+    let tms_byte = 0x01 | ((exit_tms & exit_mask) as u8) << 1 | (last_bit << 7);
+    command.extend_from_slice(&[0x4a | read, merged as u8, tms_byte]);
+    if exit_bits > merged {
+        let rest = exit_tms >> merged;
+        command.extend(tms_command(
+            &rest.to_le_bytes(),
+            exit_bits - merged,
+            false,
+            edge,
+        )?);
+    }
+
+    Ok(command)
+}
+
+/// Number of bytes MPSSE returns for a capturing `tdi_command`
+fn tdi_reply_len(bits: usize) -> usize {
+    let full_bytes = (bits - 1) / 8;
+    let mut expect_bytes = full_bytes + 1;
+    if bits - full_bytes * 8 > 1 {
+        expect_bytes += 1;
+    }
+    expect_bytes
+}
+
+/// Reassemble the reply to a capturing `tdi_command` into `bits` of TDO
+///
+/// TDO comes back LSB first, the same order TDI goes out, so bit `i` of the
+/// result is bit `i % 8` of byte `i / 8`. Byte shifts reply with whole bytes
+/// in that order. Bit shifts and TMS shifts instead shift TDO in from the
+/// top of their reply byte: the `n` bits of a bit shift end up in bits
+/// `8 - n..8`. The bit captured on the way to EXIT1 is the first of the
+/// final TMS shift, which also clocked `exit_bits` (at most 6) further.
+fn tdi_reply(mut reply: Vec<u8>, bits: usize, exit_bits: usize) -> Vec<u8> {
+    let full_bytes = (bits - 1) / 8;
+    let bits = bits - full_bytes * 8;
+
+    let tms_bits = 1 + exit_bits.min(6);
+    let mut last_byte = reply[reply.len() - 1] >> (8 - tms_bits) & 0x01;
+    if bits > 1 {
+        let partial = reply[reply.len() - 2] >> (8 - (bits - 1));
+        last_byte = partial | (last_byte << (bits - 1));
+    }
+    reply[full_bytes] = last_byte;
+    reply.truncate(full_bytes + 1);
+
+    reply
+}
+
+/// Split a DR capture after reset into the IDCODEs of up to `max` TAPs
+///
+/// A TAP with an IDCODE register shifts out a 32-bit value with the LSB set,
+/// one in BYPASS shifts out a single zero (reported as IDCODE 0). An
+/// all-ones "IDCODE" is the shifted-in data and marks the end of the chain.
+fn idcodes_from_capture(capture: &BitSlice<Lsb0, u8>, max: usize) -> Vec<u32> {
+    let mut idcodes = Vec::new();
+    let mut pos = 0;
+    while idcodes.len() < max && pos < capture.len() {
+        if !capture[pos] {
+            idcodes.push(0);
+            pos += 1;
+            continue;
+        }
+        if pos + 32 > capture.len() {
+            break;
+        }
+        let idcode = capture[pos..pos + 32]
+            .iter()
+            .rev()
+            .fold(0u32, |acc, &bit| (acc << 1) | bit as u32);
+        if idcode == 0xffffffff {
+            break;
+        }
+        idcodes.push(idcode);
+        pos += 32;
+    }
+    idcodes
+}
+
+/// Offset of the zero marker shifted in at `start` from its position in `capture`
+fn marker_position(capture: &BitSlice<Lsb0, u8>, start: usize) -> Option<usize> {
+    capture.get(start..)?.iter().position(|&bit| !bit)
+}
+
+/// Split an IR capture into the IR lengths of `count` TAPs
+///
+/// Past the last TAP only the shifted-in ones must come out. A last TAP
+/// capturing ones above its `01` still looks shorter than it is, only
+/// `detect_irlen` measures the real total.
+fn ir_lengths_from_capture(
+    capture: &BitSlice<Lsb0, u8>,
+    count: usize,
+) -> Result<Vec<usize>, JtagError> {
+    let irlens = leading_ir_lengths(capture, count)?;
+    let pos: usize = irlens.iter().sum();
+    if !capture[pos..].all() {
+        return Err(JtagError::InvalidChain(format!(
+            "IR capture has more than {} TAPs",
+            count
+        )));
+    }
+    Ok(irlens)
+}
+
+/// IR lengths of the first `count` TAPs of an IR capture, ignoring what follows
+///
+/// Every TAP captures `...01` (LSB first) into its IR, so each one starts
+/// with a one followed by a zero and extends up to the next one.
+fn leading_ir_lengths(capture: &BitSlice<Lsb0, u8>, count: usize) -> Result<Vec<usize>, JtagError> {
+    let mut irlens = Vec::with_capacity(count);
+    let mut pos = 0;
+    for i in 0..count {
+        if capture.get(pos) != Some(&true) || capture.get(pos + 1) != Some(&false) {
+            log::debug!("invalid irlen for tap {}", i);
+            return Err(JtagError::InvalidChain(format!(
+                "invalid IR capture for tap {}",
+                i
+            )));
+        }
+        match capture[pos + 1..].iter().position(|&bit| bit) {
+            Some(offset) => {
+                irlens.push(offset + 1);
+                pos += offset + 1;
+            }
+            None => {
+                return Err(JtagError::InvalidChain(
+                    "IR capture ended inside the chain".to_string(),
+                ))
+            }
+        }
+    }
+
+    if let Some(i) = irlens.iter().position(|&irlen| irlen > 32) {
+        return Err(JtagError::InvalidChain(format!(
+            "tap {} has an IR of {} bits, at most 32 are supported",
+            i, irlens[i]
+        )));
+    }
+
+    Ok(irlens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockJtag, MockTap};
+
+    /// IR capture of a chain with `irlens` (from TDO), followed by ones
+    fn capture(irlens: &[usize]) -> BitVec<Lsb0, u8> {
+        let mut bits = BitVec::<Lsb0, u8>::new();
+        for &irlen in irlens {
+            bits.push(true);
+            for _ in 1..irlen {
+                bits.push(false);
+            }
+        }
+        while bits.len() < (irlens.len() + 1) * 32 {
+            bits.push(true);
+        }
+        bits
+    }
+
+    #[test]
+    fn tms_command_clocks_exact_bit_counts() {
+        let data = [0xa5, 0x3c, 0x0f, 0xf0, 0x5a, 0xc3];
+        let tms = BitSlice::<Lsb0, u8>::from_slice(&data);
+        for bits in 1..=data.len() * 8 {
+            let command = tms_command(&data, bits, false, ClockEdge::default()).unwrap();
+
+            // As few shifts of up to 7 bits as possible
+            let shifts = bits / 7 + usize::from(bits % 7 > 0);
+            assert_eq!(command.len(), shifts * 3, "{} bits", bits);
+            let mut sent = BitVec::<Lsb0, u8>::new();
+            for shift in command.chunks(3) {
+                assert_eq!(shift[0], 0x4b);
+                let len = shift[1] as usize + 1;
+                assert!(len <= 7);
+                let byte = BitSlice::<Lsb0, u8>::from_element(&shift[2]);
+                sent.extend_from_slice(&byte[..len]);
+            }
+            assert_eq!(sent, tms[..bits], "{} bits", bits);
+        }
+    }
+
+    #[test]
+    fn tms_command_holds_tdi() {
+        let command = tms_command(&[0x00, 0x00], 9, true, ClockEdge::default()).unwrap();
+        assert_eq!(command, [0x4b, 6, 0x80, 0x4b, 1, 0x80]);
+    }
+
+    #[test]
+    fn tms_command_rejects_short_data() {
+        assert!(tms_command(&[0xff], 9, false, ClockEdge::default()).is_err());
+    }
+
+    #[test]
+    fn read_ir_status_rejects_short_ir() {
+        let tap = MockTap::new(Some(0x1000563d), 5).unwrap();
+        let mut adapter = JtagAdapter::from_io(MockJtag::new(vec![tap]));
+        adapter.attach().unwrap();
+        adapter.chain_params = Some(ChainParams {
+            irpre: 0,
+            irpost: 0,
+            drpre: 0,
+            drpost: 0,
+            irlen: 1,
+        });
+        assert!(matches!(
+            adapter.read_ir_status(),
+            Err(JtagError::InvalidChain(_))
+        ));
+    }
+
+    #[test]
+    fn ir_lengths_of_single_tap() {
+        assert_eq!(ir_lengths_from_capture(&capture(&[5]), 1).unwrap(), [5]);
+    }
+
+    #[test]
+    fn ir_lengths_of_chain() {
+        let irlens = [4, 5, 2];
+        assert_eq!(
+            ir_lengths_from_capture(&capture(&irlens), 3).unwrap(),
+            irlens
+        );
+    }
+
+    #[test]
+    fn ir_lengths_crossing_byte_boundaries() {
+        // 5 + 4 puts the third IR on bits 9 to 17, the fourth on 18 to 49
+        let irlens = [5, 4, 9, 32, 3];
+        assert_eq!(
+            ir_lengths_from_capture(&capture(&irlens), 5).unwrap(),
+            irlens
+        );
+
+        let irlens = [3, 7, 10];
+        assert_eq!(
+            ir_lengths_from_capture(&capture(&irlens), 3).unwrap(),
+            irlens
+        );
+    }
+
+    #[test]
+    fn ir_capture_without_01_is_rejected() {
+        let mut bits = capture(&[4, 6]);
+        // Second TAP captures `11` in its low bits
+        bits.set(5, true);
+        assert!(matches!(
+            ir_lengths_from_capture(&bits, 2),
+            Err(JtagError::InvalidChain(_))
+        ));
+    }
+
+    #[test]
+    fn ir_capture_with_extra_tap_is_rejected() {
+        assert!(matches!(
+            ir_lengths_from_capture(&capture(&[4, 5, 6]), 2),
+            Err(JtagError::InvalidChain(_))
+        ));
+    }
+
+    #[test]
+    fn ir_capture_ending_inside_chain_is_rejected() {
+        let mut bits = BitVec::<Lsb0, u8>::repeat(false, 16);
+        bits.set(0, true);
+        assert!(matches!(
+            ir_lengths_from_capture(&bits, 1),
+            Err(JtagError::InvalidChain(_))
+        ));
+    }
+
+    #[test]
+    fn ir_longer_than_32_bits_is_rejected() {
+        assert!(matches!(
+            ir_lengths_from_capture(&capture(&[33]), 1),
+            Err(JtagError::InvalidChain(_))
+        ));
+    }
+}
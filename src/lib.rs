@@ -0,0 +1,41 @@
+//! JTAG over FTDI MPSSE adapters
+
+#[cfg(feature = "async")]
+mod async_probe;
+mod bitbang;
+mod boundary;
+#[cfg(feature = "config")]
+mod config;
+mod error;
+pub mod ftdi;
+mod jtag;
+mod mock;
+mod mpsse;
+mod probe;
+#[cfg(feature = "probe-rs")]
+mod probe_rs_backend;
+pub mod remote_bitbang;
+mod riscv;
+pub mod svf;
+mod tap;
+
+#[cfg(feature = "async")]
+pub use async_probe::AsyncFtdiProbe;
+pub use boundary::{
+    BoundaryCell, BoundaryScan, InterconnectConfig, InterconnectDevice, InterconnectReport, Net,
+    NetFailure, NetFault,
+};
+#[cfg(feature = "config")]
+pub use config::{Config, ConfigError};
+pub use error::JtagError;
+pub use jtag::{
+    BenchResult, ChainParams, ClockEdge, GpioInit, JtagAdapter, JtagChainItem, ResetConfig,
+    ResetPin, Sequence, TapInstructions, TimedTransfer, TuneResult,
+};
+pub use mock::{MockJtag, MockTap};
+pub use mpsse::MpsseIo;
+pub use probe::FtdiProbe;
+#[cfg(feature = "probe-rs")]
+pub use probe_rs_backend::FtdiProbeFactory;
+pub use riscv::{DmiBusyPolicy, Dtmcs, RiscvDtm};
+pub use tap::TapState;
@@ -1,481 +1,138 @@
-use bitvec::order::Lsb0;
-use bitvec::vec::BitVec;
-use std::convert::TryInto;
-use std::io::{self, Read, Write};
-use std::sync::Mutex;
-use std::time::Duration;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::process;
 
-mod ftdi;
+use ftdi_playground::{ftdi, FtdiProbe};
 
-#[derive(Debug)]
-struct JtagChainItem {
-    idcode: u32,
-    irlen: usize,
-}
-
-#[derive(Clone, Debug)]
-struct ChainParams {
-    irpre: usize,
-    irpost: usize,
-    drpre: usize,
-    drpost: usize,
-    irlen: usize,
-}
-
-#[derive(Debug)]
-pub struct JtagAdapter {
-    device: ftdi::Device,
-    chain_params: Option<ChainParams>,
-}
-
-impl JtagAdapter {
-    pub fn open(vid: u16, pid: u16) -> Result<Self, ftdi::Error> {
-        let mut builder = ftdi::Builder::new();
-        builder.set_interface(ftdi::Interface::A)?;
-        let device = builder.usb_open(vid, pid)?;
-
-        Ok(Self {
-            device,
-            chain_params: None,
-        })
-    }
-
-    pub fn attach(&mut self) -> Result<(), ftdi::Error> {
-        self.device.usb_reset()?;
-        self.device.set_latency_timer(1)?;
-        self.device.set_bitmode(0x0b, ftdi::BitMode::Mpsse)?;
-        self.device.usb_purge_buffers()?;
-
-        let mut junk = vec![];
-        let _ = self.device.read_to_end(&mut junk);
-
-        // Minimal values, may not work with all probes
-        let output: u16 = 0x0008;
-        let direction: u16 = 0x000b;
-        self.device
-            .write_all(&[0x80, output as u8, direction as u8])?;
-        self.device
-            .write_all(&[0x82, (output >> 8) as u8, (direction >> 8) as u8])?;
-
-        // Disable loopback
-        self.device.write_all(&[0x85])?;
-
-        Ok(())
-    }
-
-    fn read_response(&mut self, size: usize) -> io::Result<Vec<u8>> {
-        let timeout = Duration::from_millis(10);
-        let mut result = Vec::new();
-
-        let t0 = std::time::Instant::now();
-        while result.len() < size {
-            if t0.elapsed() > timeout {
-                return Err(io::Error::from(io::ErrorKind::TimedOut));
-            }
-
-            self.device.read_to_end(&mut result)?;
-        }
-
-        if result.len() > size {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Read more data than expected",
-            ));
-        }
-
-        Ok(result)
-    }
-
-    fn shift_tms(&mut self, mut data: &[u8], mut bits: usize) -> io::Result<()> {
-        assert!(bits > 0);
-        assert!((bits + 7) / 8 <= data.len());
-
-        let mut command = vec![];
-
-        while bits > 0 {
-            if bits >= 8 {
-                command.extend_from_slice(&[0x4b, 0x07, data[0]]);
-                data = &data[1..];
-                bits -= 8;
-            } else {
-                command.extend_from_slice(&[0x4b, (bits - 1) as u8, data[0]]);
-                bits = 0;
-            }
-        }
-        self.device.write_all(&command)
-    }
-
-    fn shift_tdi(&mut self, mut data: &[u8], mut bits: usize) -> io::Result<()> {
-        assert!(bits > 0);
-        assert!((bits + 7) / 8 <= data.len());
-
-        let mut command = vec![];
-
-        let full_bytes = (bits - 1) / 8;
-        if full_bytes > 0 {
-            assert!(full_bytes <= 65536);
-
-            command.extend_from_slice(&[0x19]);
-            let n: u16 = (full_bytes - 1) as u16;
-            command.extend_from_slice(&n.to_le_bytes());
-            command.extend_from_slice(&data[..full_bytes]);
-
-            bits -= full_bytes * 8;
-            data = &data[full_bytes..];
-        }
-        assert!(bits <= 8);
-
-        if bits > 0 {
-            let byte = data[0];
-            if bits > 1 {
-                let n = (bits - 2) as u8;
-                command.extend_from_slice(&[0x1b, n, byte]);
-            }
-
-            let last_bit = (byte >> (bits - 1)) & 0x01;
-            let tms_byte = 0x01 | (last_bit << 7);
-            command.extend_from_slice(&[0x4b, 0x00, tms_byte]);
-        }
-
-        self.device.write_all(&command)
-    }
-
-    fn tranfer_tdi(&mut self, mut data: &[u8], mut bits: usize) -> io::Result<Vec<u8>> {
-        assert!(bits > 0);
-        assert!((bits + 7) / 8 <= data.len());
-
-        let mut command = vec![];
-
-        let full_bytes = (bits - 1) / 8;
-        if full_bytes > 0 {
-            assert!(full_bytes <= 65536);
-
-            command.extend_from_slice(&[0x39]);
-            let n: u16 = (full_bytes - 1) as u16;
-            command.extend_from_slice(&n.to_le_bytes());
-            command.extend_from_slice(&data[..full_bytes]);
-
-            bits -= full_bytes * 8;
-            data = &data[full_bytes..];
-        }
-        assert!(0 < bits && bits <= 8);
-
-        let byte = data[0];
-        if bits > 1 {
-            let n = (bits - 2) as u8;
-            command.extend_from_slice(&[0x3b, n, byte]);
-        }
-
-        let last_bit = (byte >> (bits - 1)) & 0x01;
-        let tms_byte = 0x01 | (last_bit << 7);
-        command.extend_from_slice(&[0x6b, 0x00, tms_byte]);
-
-        self.device.write_all(&command)?;
-
-        let mut expect_bytes = full_bytes + 1;
-        if bits > 1 {
-            expect_bytes += 1;
-        }
-
-        let mut reply = self.read_response(expect_bytes)?;
-
-        let mut last_byte = reply[reply.len() - 1] & 0x01;
-        if bits > 1 {
-            let byte = reply[reply.len() - 2];
-            last_byte = byte | (last_byte << (bits - 1));
-        }
-        reply[full_bytes] = last_byte;
-        reply.truncate(full_bytes + 1);
-
-        Ok(reply)
-    }
-
-    /// Reset and go to RUN-TEST/IDLE
-    pub fn reset(&mut self) -> io::Result<()> {
-        self.shift_tms(&[0xff, 0xff, 0xff, 0xff, 0x7f], 40)
-    }
-
-    /// Execute RUN-TEST/IDLE for a number of cycles
-    pub fn idle(&mut self, cycles: usize) -> io::Result<()> {
-        if cycles == 0 {
-            return Ok(());
-        }
-        let mut buf = vec![];
-        buf.resize((cycles + 7) / 8, 0);
-        self.shift_tms(&buf, cycles)
-    }
-
-    /// Shift to IR and return to IDLE
-    pub fn shift_ir(&mut self, data: &[u8], bits: usize) -> io::Result<()> {
-        self.shift_tms(&[0b0011], 4)?;
-        self.shift_tdi(data, bits)?;
-        self.shift_tms(&[0b01], 2)?;
-        Ok(())
-    }
-
-    /// Shift to IR and return to IDLE
-    pub fn transfer_ir(&mut self, data: &[u8], bits: usize) -> io::Result<Vec<u8>> {
-        self.shift_tms(&[0b0011], 4)?;
-        let r = self.tranfer_tdi(data, bits)?;
-        self.shift_tms(&[0b01], 2)?;
-        Ok(r)
-    }
+fn main() {
+    env_logger::init();
 
-    /// Shift to DR and return to IDLE
-    pub fn transfer_dr(&mut self, data: &[u8], bits: usize) -> io::Result<Vec<u8>> {
-        self.shift_tms(&[0b001], 3)?;
-        let r = self.tranfer_tdi(data, bits)?;
-        self.shift_tms(&[0b01], 2)?;
-        Ok(r)
+    let target = Arg::with_name("target")
+        .long("target")
+        .takes_value(true)
+        .required(true)
+        .help("IDCODE of the TAP to access");
+    let matches = App::new("ftdi-playground")
+        .about("JTAG over FTDI MPSSE adapters")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("vid")
+                .long("vid")
+                .takes_value(true)
+                .default_value("0x0403")
+                .help("USB vendor ID"),
+        )
+        .arg(
+            Arg::with_name("pid")
+                .long("pid")
+                .takes_value(true)
+                .default_value("0x6010")
+                .help("USB product ID"),
+        )
+        .arg(
+            Arg::with_name("serial")
+                .long("serial")
+                .takes_value(true)
+                .help("USB serial number of the adapter"),
+        )
+        .arg(
+            Arg::with_name("interface")
+                .long("interface")
+                .takes_value(true)
+                .possible_values(&["A", "B", "C", "D"])
+                .default_value("A")
+                .help("Channel of a multi-channel chip"),
+        )
+        .arg(
+            Arg::with_name("clock")
+                .long("clock")
+                .takes_value(true)
+                .help("TCK frequency in Hz"),
+        )
+        .subcommand(SubCommand::with_name("scan").about("Print the TAPs of the chain"))
+        .subcommand(
+            SubCommand::with_name("read")
+                .about("Read a 32-bit register of the target")
+                .arg(target.clone())
+                .arg(Arg::with_name("addr").required(true).help("IR value")),
+        )
+        .subcommand(
+            SubCommand::with_name("write")
+                .about("Write a 32-bit register of the target")
+                .arg(target)
+                .arg(Arg::with_name("addr").required(true).help("IR value"))
+                .arg(Arg::with_name("value").required(true)),
+        )
+        .subcommand(SubCommand::with_name("reset").about("Reset the TAPs through TMS"))
+        .get_matches();
+
+    if let Err(e) = run(&matches) {
+        eprintln!("error: {}", e);
+        process::exit(1);
     }
+}
 
-    fn scan(&mut self) -> io::Result<Vec<JtagChainItem>> {
-        let max_device_count = 8;
-
-        self.reset()?;
-
-        let cmd = vec![0xff; max_device_count * 4];
-        let r = self.transfer_dr(&cmd, cmd.len() * 8)?;
-        let mut targets = vec![];
-        for i in 0..max_device_count {
-            let idcode = u32::from_le_bytes(r[i * 4..(i + 1) * 4].try_into().unwrap());
-            if idcode != 0xffffffff {
-                log::debug!("tap found: {:08x}", idcode);
-                let target = JtagChainItem { idcode, irlen: 0 };
-                targets.push(target);
-            } else {
-                break;
-            }
-        }
-
-        self.reset()?;
-        let cmd = vec![0xff; max_device_count];
-        let mut r = self.transfer_ir(&cmd, cmd.len() * 8)?;
-
-        let mut ir = 0;
-        let mut irbits = 0;
-        for (i, target) in targets.iter_mut().enumerate() {
-            if r.len() > 0 && irbits < 8 {
-                let byte = r[0];
-                r.remove(0);
-                ir |= (byte as u32) << irbits;
-                irbits += 8;
-            }
-            if ir & 0b11 == 0b01 {
-                ir &= !1;
-                let irlen = ir.trailing_zeros();
-                ir = ir >> irlen;
-                irbits -= irlen;
-                log::debug!("tap {} irlen: {}", i, irlen);
-                target.irlen = irlen as usize;
-            } else {
-                log::debug!("invalid irlen for tap {}", i);
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "Invalid IR sequence during the chain scan",
-                ));
-            }
-        }
+fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let vid = usb_id(matches, "vid")?;
+    let pid = usb_id(matches, "pid")?;
+    let interface = match matches.value_of("interface") {
+        Some("B") => ftdi::Interface::B,
+        Some("C") => ftdi::Interface::C,
+        Some("D") => ftdi::Interface::D,
+        _ => ftdi::Interface::A,
+    };
 
-        Ok(targets)
+    let mut builder = ftdi::Builder::new();
+    builder.set_interface(interface)?;
+    let device = match matches.value_of("serial") {
+        Some(serial) => builder.usb_open_serial(vid, pid, serial)?,
+        None => builder.usb_open(vid, pid)?,
+    };
+    let mut probe = FtdiProbe::from_device(device);
+    probe.attach()?;
+    if matches.is_present("clock") {
+        let hz = probe.set_clock(number(matches, "clock")?)?;
+        log::info!("TCK {} Hz", hz);
     }
 
-    pub fn select_target(&mut self, idcode: u32) -> io::Result<()> {
-        let taps = self.scan()?;
-
-        let mut found = false;
-        let mut params = ChainParams {
-            irpre: 0,
-            irpost: 0,
-            drpre: 0,
-            drpost: 0,
-            irlen: 0,
-        };
-        for tap in taps {
-            if tap.idcode == idcode {
-                params.irlen = tap.irlen;
-                found = true;
-            } else if found {
-                params.irpost += tap.irlen;
-                params.drpost += 1;
-            } else {
-                params.irpre += tap.irlen;
-                params.drpre += 1;
+    match matches.subcommand() {
+        ("scan", _) => {
+            for (i, tap) in probe.scan_chain()?.iter().enumerate() {
+                println!("{}: {}", i, tap);
             }
         }
-
-        if found {
-            log::debug!("Target chain params: {:?}", params);
-            self.chain_params = Some(params);
-            Ok(())
-        } else {
-            Err(io::Error::new(io::ErrorKind::NotFound, "target not found"))
+        ("read", Some(args)) => {
+            probe.select_target(number(args, "target")?)?;
+            let value = probe.read_register32(number(args, "addr")?)?;
+            println!("{:#010x}", value);
         }
-    }
-
-    fn get_chain_params(&self) -> io::Result<ChainParams> {
-        match &self.chain_params {
-            Some(params) => Ok(params.clone()),
-            None => Err(io::Error::new(
-                io::ErrorKind::Other,
-                "target is not selected",
-            )),
+        ("write", Some(args)) => {
+            probe.select_target(number(args, "target")?)?;
+            probe.write_register32_void(number(args, "addr")?, number(args, "value")?)?;
+            probe.flush()?;
         }
-    }
-
-    fn target_transfer(
-        &mut self,
-        address: u32,
-        data: Option<&[u8]>,
-        len_bits: usize,
-    ) -> io::Result<Vec<u8>> {
-        let params = self.get_chain_params()?;
-        let max_address = (1 << params.irlen) - 1;
-        if address > max_address {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "invalid register address",
-            ));
+        ("reset", _) => {
+            probe.reset()?;
+            probe.flush()?;
         }
-
-        // Write IR register
-        let irbits = params.irpre + params.irlen + params.irpost;
-        assert!(irbits <= 32);
-        let mut ir: u32 = (1 << params.irpre) - 1;
-        ir |= address << params.irpre;
-        ir |= ((1 << params.irpost) - 1) << (params.irpre + params.irlen);
-        self.shift_ir(&ir.to_le_bytes(), irbits)?;
-
-        let drbits = params.drpre + len_bits + params.drpost;
-        let request = if let Some(data) = data {
-            let mut data = BitVec::<Lsb0, u8>::from_slice(data);
-            data.truncate(len_bits);
-
-            let mut buf = BitVec::<Lsb0, u8>::new();
-            buf.resize(params.drpre, false);
-            buf.append(&mut data);
-            buf.resize(buf.len() + params.drpost, false);
-
-            buf.into_vec()
-        } else {
-            vec![0; (drbits + 7) / 8]
-        };
-        let reply = self.transfer_dr(&request, drbits)?;
-
-        // Process the reply
-        let mut reply = BitVec::<Lsb0, u8>::from_vec(reply);
-        if params.drpre > 0 {
-            reply = reply.split_off(params.drpre);
-        }
-        reply.truncate(len_bits);
-        let reply = reply.into_vec();
-
-        Ok(reply)
+        _ => unreachable!("clap requires a subcommand"),
     }
+    Ok(())
 }
 
-#[derive(Debug)]
-pub struct FtdiProbe {
-    adapter: Mutex<JtagAdapter>,
-    speed_khz: u32,
-    idle_cycles: u8,
-}
-
-impl FtdiProbe {
-    pub fn open(vid: u16, pid: u16) -> Result<Self, ftdi::Error> {
-        let adapter = JtagAdapter::open(vid, pid)?;
-        let probe = FtdiProbe {
-            adapter: Mutex::new(adapter),
-            speed_khz: 0,
-            idle_cycles: 0,
-        };
-        log::debug!("opened probe: {:?}", probe);
-        Ok(probe)
-    }
-
-    pub fn attach(&mut self) -> Result<(), ftdi::Error> {
-        log::debug!("attaching...");
-        let adapter = self.adapter.get_mut().unwrap();
-
-        adapter.attach()
-    }
-
-    pub fn test(&mut self) {
-        let adapter = self.adapter.get_mut().unwrap();
-
-        adapter.reset().unwrap();
-        adapter.shift_ir(&[0x10], 5).unwrap();
-        adapter.idle(42).unwrap();
-        //adapter.shift_ir(&[0x1f, 0x02], 10).unwrap();
-        adapter.select_target(0x1000563d).unwrap();
-    }
-}
-
-
-impl FtdiProbe {
-    fn read_register(&mut self, address: u32, len: u32) -> io::Result<Vec<u8>> {
-        log::debug!("read_register({:#x}, {})", address, len);
-        let adapter = self.adapter.get_mut().unwrap();
-        let r = adapter
-            .target_transfer(address, None, len as usize)?;
-        adapter
-            .idle(self.idle_cycles as usize)?;
-        log::debug!("read_register result: {:?})", r);
-        Ok(r)
-    }
-
-    fn set_idle_cycles(&mut self, idle_cycles: u8) {
-        log::debug!("set_idle_cycles({})", idle_cycles);
-        self.idle_cycles = idle_cycles;
-    }
-
-    fn write_register(&mut self, address: u32, data: &[u8], len: u32) -> io::Result<Vec<u8>> {
-        log::debug!("write_register({:#x}, {:?}, {})", address, data, len);
-        let adapter = self.adapter.get_mut().unwrap();
-        let r = adapter
-            .target_transfer(address, Some(data), len as usize)?;
-        adapter
-            .idle(self.idle_cycles as usize)?;
-        log::debug!("write_register result: {:?})", r);
-        Ok(r)
-    }
-
-    fn read_register32(&mut self, address: u32) -> io::Result<u32> {
-        let r = self.read_register(address, 32)?;
-        Ok(u32::from_le_bytes(r[0..4].try_into().unwrap()))
-    }
-
-    fn write_register32(&mut self, address: u32, value: u32) -> io::Result<u32> {
-        let r = self.write_register(address, &value.to_le_bytes(), 32)?;
-        Ok(u32::from_le_bytes(r[0..4].try_into().unwrap()))
-    }
-}
-
-fn main() {
-    env_logger::init();
-
-    println!("Opening probe...");
-
-    let mut probe = match FtdiProbe::open(0x0403, 0x6010) {
-        Ok(probe) => probe,
-        Err(e) => {
-            println!("Cannot find/open device: {:?}", e);
-            return;
-        }
+/// Parse a decimal or `0x`-prefixed hex argument
+fn number(matches: &ArgMatches, name: &str) -> Result<u32, Box<dyn Error>> {
+    let value = matches.value_of(name).unwrap_or_default();
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
     };
-    probe.attach().unwrap();
-    probe.test();
-
-    probe.set_idle_cycles(8);
-
-    let r = probe.read_register32(0x01).unwrap();
-    println!("idcode: {:08x}", r);
+    parsed.map_err(|_| format!("invalid {}: {}", name, value).into())
+}
 
-    let r = probe.read_register32(0x10).unwrap();
-    println!("dtmcs: {:08x}", r);
-    let r = probe.read_register32(0x11).unwrap();
-    println!("dmi: {:08x}", r);
-    probe.write_register32(0x10, 0b11 << 16).unwrap();
-    let r = probe.read_register32(0x10).unwrap();
-    println!("dtmcs: {:08x}", r);
+/// A 16-bit USB vendor or product ID
+fn usb_id(matches: &ArgMatches, name: &str) -> Result<u16, Box<dyn Error>> {
+    let value = number(matches, name)?;
+    u16::try_from(value).map_err(|_| format!("{} {:#x} doesn't fit in 16 bits", name, value).into())
 }
@@ -0,0 +1,413 @@
+//! Simulated TAP chain behind an MPSSE command interpreter
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+use crate::error::JtagError;
+use crate::ftdi::ChipType;
+use crate::mpsse::MpsseIo;
+use crate::tap::TapState;
+
+/// Instruction selecting the IDCODE register of a `MockTap` that has one
+const IDCODE_IR: u32 = 0x01;
+
+/// A TAP of a `MockJtag` chain
+///
+/// The all-ones instruction selects BYPASS, as does any instruction without
+/// a register. IR captures `0b01` unless set otherwise.
+#[derive(Clone, Debug)]
+pub struct MockTap {
+    idcode: Option<u32>,
+    irlen: usize,
+    /// Data registers by instruction, values LSB first
+    registers: Vec<(u32, Vec<bool>)>,
+    ir_capture: u32,
+    ir: u32,
+    shift: Vec<bool>,
+}
+
+impl MockTap {
+    /// A TAP with `irlen` bits of IR, loading IDCODE (instruction 1) on reset if it has one
+    pub fn new(idcode: Option<u32>, irlen: usize) -> Result<Self, JtagError> {
+        if !(2..=32).contains(&irlen) {
+            return Err(JtagError::InvalidInput(
+                "mock IR length must be 2 to 32 bits",
+            ));
+        }
+
+        let mut tap = Self {
+            idcode,
+            irlen,
+            registers: Vec::new(),
+            ir_capture: 0b01,
+            ir: 0,
+            shift: vec![false],
+        };
+        tap.reset();
+        Ok(tap)
+    }
+
+    /// Add a `len`-bit data register, initially zero, selected by `ir`
+    pub fn with_register(mut self, ir: u32, len: usize) -> Result<Self, JtagError> {
+        if len == 0 {
+            return Err(JtagError::InvalidInput(
+                "mock register length must not be zero",
+            ));
+        }
+        self.registers.push((ir, vec![false; len]));
+        Ok(self)
+    }
+
+    /// Capture `value` into IR instead of `0b01`, like TAPs that put status bits there
+    pub fn with_ir_capture(mut self, value: u32) -> Result<Self, JtagError> {
+        if u64::from(value) >> self.irlen != 0 {
+            return Err(JtagError::InvalidInput("mock IR capture wider than the IR"));
+        }
+        self.ir_capture = value;
+        Ok(self)
+    }
+
+    fn reset(&mut self) {
+        self.ir = match self.idcode {
+            Some(_) => IDCODE_IR,
+            None => self.bypass_ir(),
+        };
+    }
+
+    fn bypass_ir(&self) -> u32 {
+        ((1u64 << self.irlen) - 1) as u32
+    }
+
+    fn register_mut(&mut self, ir: u32) -> Option<&mut Vec<bool>> {
+        self.registers
+            .iter_mut()
+            .find(|(i, _)| *i == ir)
+            .map(|(_, value)| value)
+    }
+
+    fn capture_ir(&mut self) {
+        let capture = self.ir_capture;
+        self.shift = (0..self.irlen).map(|i| capture >> i & 1 != 0).collect();
+    }
+
+    fn capture_dr(&mut self) {
+        let ir = self.ir;
+        if ir == self.bypass_ir() {
+            self.shift = vec![false];
+        } else if let Some(value) = self.register_mut(ir) {
+            self.shift = value.clone();
+        } else if let (Some(idcode), IDCODE_IR) = (self.idcode, ir) {
+            self.shift = (0..32).map(|i| idcode >> i & 1 != 0).collect();
+        } else {
+            self.shift = vec![false];
+        }
+    }
+
+    fn update_ir(&mut self) {
+        self.ir = self
+            .shift
+            .iter()
+            .enumerate()
+            .fold(0, |ir, (i, &bit)| ir | (bit as u32) << i);
+    }
+
+    fn update_dr(&mut self) {
+        let shift = self.shift.clone();
+        if let Some(value) = self.register_mut(self.ir) {
+            *value = shift;
+        }
+    }
+
+    /// Shift `tdi` in at the TDI end, returning the bit leaving at the TDO end
+    fn shift_bit(&mut self, tdi: bool) -> bool {
+        let tdo = self.shift.remove(0);
+        self.shift.push(tdi);
+        tdo
+    }
+}
+
+/// MPSSE device simulating a JTAG chain, for running without hardware
+///
+/// TAPs are listed from the TDO end, the order `scan_chain` reports them
+/// in. Commands are executed as they're written, replies queue up until
+/// read. Clock, GPIO and adaptive clocking commands are accepted and
+/// ignored apart from the pin levels read back.
+///
+/// With a chip type lacking MPSSE, written bytes are pin levels instead,
+/// each answered with the pins as they were before it, as in synchronous
+/// bitbang mode.
+#[derive(Debug)]
+pub struct MockJtag {
+    taps: Vec<MockTap>,
+    state: TapState,
+    /// Start of a command still missing some of its bytes
+    input: Vec<u8>,
+    output: VecDeque<u8>,
+    loopback: bool,
+    tms: bool,
+    tdo: bool,
+    gpio_output: [u8; 2],
+    gpio_direction: [u8; 2],
+    chip_type: ChipType,
+    bitbang: bool,
+}
+
+impl MockJtag {
+    pub fn new(taps: Vec<MockTap>) -> Self {
+        Self {
+            taps,
+            state: TapState::TestLogicReset,
+            input: Vec::new(),
+            output: VecDeque::new(),
+            loopback: false,
+            tms: true,
+            tdo: true,
+            gpio_output: [0; 2],
+            gpio_direction: [0; 2],
+            chip_type: ChipType::Ft2232H,
+            bitbang: false,
+        }
+    }
+
+    /// Report `chip_type` instead of an FT2232H
+    pub fn with_chip_type(mut self, chip_type: ChipType) -> Self {
+        self.chip_type = chip_type;
+        self
+    }
+
+    /// Value of the data register `ir` of TAP `tap`, LSB first
+    pub fn register(&self, tap: usize, ir: u32) -> Option<Vec<u8>> {
+        let (_, value) = self
+            .taps
+            .get(tap)?
+            .registers
+            .iter()
+            .find(|(i, _)| *i == ir)?;
+        let mut bytes = vec![0; (value.len() + 7) / 8];
+        for (i, &bit) in value.iter().enumerate() {
+            bytes[i / 8] |= (bit as u8) << (i % 8);
+        }
+        Some(bytes)
+    }
+
+    /// Preload the data register `ir` of TAP `tap` with `value`, LSB first
+    pub fn set_register(&mut self, tap: usize, ir: u32, value: &[u8]) -> Result<(), JtagError> {
+        let register = self
+            .taps
+            .get_mut(tap)
+            .and_then(|tap| tap.register_mut(ir))
+            .ok_or(JtagError::InvalidInput("no such mock register"))?;
+        for (i, bit) in register.iter_mut().enumerate() {
+            let byte = value.get(i / 8).copied().unwrap_or(0);
+            *bit = byte >> (i % 8) & 1 != 0;
+        }
+        Ok(())
+    }
+
+    /// One TCK cycle, returning TDO as sampled on the rising edge
+    fn clock(&mut self, tms: bool, tdi: bool) -> bool {
+        let mut tdo = tdi;
+        if self.state == TapState::ShiftDr || self.state == TapState::ShiftIr {
+            for tap in self.taps.iter_mut().rev() {
+                tdo = tap.shift_bit(tdo);
+            }
+            self.tdo = tdo;
+        } else {
+            // Not driven outside the shift states, pulled up
+            self.tdo = true;
+        }
+
+        self.state = self.state.next(tms);
+        self.tms = tms;
+        for tap in self.taps.iter_mut() {
+            match self.state {
+                TapState::TestLogicReset => tap.reset(),
+                TapState::CaptureIr => tap.capture_ir(),
+                TapState::CaptureDr => tap.capture_dr(),
+                TapState::UpdateIr => tap.update_ir(),
+                TapState::UpdateDr => tap.update_dr(),
+                _ => {}
+            }
+        }
+
+        if self.loopback {
+            tdi
+        } else {
+            tdo
+        }
+    }
+
+    /// Clock `bits` bits, LSB first, returning TDO shifted in from the top as MPSSE does
+    fn clock_bits(&mut self, tms: Option<u8>, tdi: u8, bits: usize) -> u8 {
+        let mut reply = 0;
+        for i in 0..bits {
+            let tms = match tms {
+                Some(tms) => tms >> i & 1 != 0,
+                None => self.tms,
+            };
+            let tdo = self.clock(tms, tdi >> i & 1 != 0);
+            reply = reply >> 1 | (tdo as u8) << 7;
+        }
+        reply
+    }
+
+    /// Length of the command at the start of `input`, `None` if it's incomplete
+    fn command_len(input: &[u8]) -> Option<usize> {
+        let op = *input.first()?;
+        let len = match op {
+            // TMS shift
+            op if op < 0x80 && op & 0x40 != 0 => 3,
+            // Bit shift, the data byte only comes with TDI output
+            op if op < 0x80 && op & 0x02 != 0 => 2 + (op & 0x10 != 0) as usize,
+            op if op < 0x80 => {
+                let n = u16::from_le_bytes([*input.get(1)?, *input.get(2)?]) as usize + 1;
+                3 + if op & 0x10 != 0 { n } else { 0 }
+            }
+            0x80 | 0x82 | 0x86 | 0x8f => 3,
+            0x8e => 2,
+            _ => 1,
+        };
+        if input.len() < len {
+            return None;
+        }
+        Some(len)
+    }
+
+    /// Apply one bitbang pin byte, clocking the chain on a rising TCK edge
+    fn write_pins(&mut self, pins: u8) {
+        let mut tdo = self.tdo;
+        if self.gpio_output[0] & 0x01 == 0 && pins & 0x01 != 0 {
+            tdo = self.clock(pins & 0x08 != 0, pins & 0x02 != 0);
+        }
+        let inputs = if tdo { 0xff } else { !(1 << 2) };
+        let direction = self.gpio_direction[0];
+        self.output
+            .push_back(self.gpio_output[0] & direction | inputs & !direction);
+        self.gpio_output[0] = pins;
+    }
+
+    fn execute(&mut self, command: &[u8]) {
+        let op = command[0];
+        let read = op & 0x20 != 0;
+        match op {
+            op if op < 0x80 && op & 0x40 != 0 => {
+                let bits = command[1] as usize + 1;
+                let tdi = if command[2] & 0x80 != 0 { 0xff } else { 0x00 };
+                let reply = self.clock_bits(Some(command[2]), tdi, bits);
+                if read {
+                    self.output.push_back(reply);
+                }
+            }
+            op if op < 0x80 && op & 0x02 != 0 => {
+                let bits = command[1] as usize + 1;
+                let tdi = command.get(2).copied().unwrap_or(0);
+                let reply = self.clock_bits(None, tdi, bits);
+                if read {
+                    self.output.push_back(reply);
+                }
+            }
+            op if op < 0x80 => {
+                let n = u16::from_le_bytes([command[1], command[2]]) as usize + 1;
+                for i in 0..n {
+                    let tdi = command.get(3 + i).copied().unwrap_or(0);
+                    let reply = self.clock_bits(None, tdi, 8);
+                    if read {
+                        self.output.push_back(reply);
+                    }
+                }
+            }
+            0x80 | 0x82 => {
+                let port = (op == 0x82) as usize;
+                self.gpio_output[port] = command[1];
+                self.gpio_direction[port] = command[2];
+            }
+            0x81 | 0x83 => {
+                let port = (op == 0x83) as usize;
+                // Inputs read high, except TDO
+                let mut inputs = 0xff;
+                if port == 0 && !self.tdo {
+                    inputs &= !(1 << 2);
+                }
+                let direction = self.gpio_direction[port];
+                self.output
+                    .push_back(self.gpio_output[port] & direction | inputs & !direction);
+            }
+            0x84 => self.loopback = true,
+            0x85 => self.loopback = false,
+            0x86 | 0x87 | 0x8a | 0x8b | 0x8c | 0x8d | 0x8e | 0x8f | 0x96 | 0x97 => {}
+            op => self.output.extend(&[0xfa, op]),
+        }
+    }
+}
+
+impl Read for MockJtag {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.output.len());
+        for (dst, src) in buf.iter_mut().zip(self.output.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MockJtag {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.bitbang {
+            for &pins in buf {
+                self.write_pins(pins);
+            }
+            return Ok(buf.len());
+        }
+
+        self.input.extend_from_slice(buf);
+        while let Some(len) = Self::command_len(&self.input) {
+            let command: Vec<u8> = self.input.drain(..len).collect();
+            self.execute(&command);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl MpsseIo for MockJtag {
+    fn usb_reset(&mut self) -> Result<(), JtagError> {
+        self.purge()
+    }
+
+    fn set_latency_timer(&mut self, _ms: u8) -> Result<(), JtagError> {
+        Ok(())
+    }
+
+    fn enter_mpsse(&mut self, direction: u8) -> Result<(), JtagError> {
+        self.gpio_direction[0] = direction;
+        self.loopback = false;
+        self.bitbang = false;
+        Ok(())
+    }
+
+    fn enter_bitbang(&mut self, direction: u8) -> Result<(), JtagError> {
+        self.gpio_direction[0] = direction;
+        self.loopback = false;
+        self.bitbang = true;
+        Ok(())
+    }
+
+    fn reset_bitmode(&mut self) -> Result<(), JtagError> {
+        self.loopback = false;
+        self.bitbang = false;
+        Ok(())
+    }
+
+    fn purge(&mut self) -> Result<(), JtagError> {
+        self.input.clear();
+        self.output.clear();
+        Ok(())
+    }
+
+    fn chip_type(&self) -> ChipType {
+        self.chip_type
+    }
+}
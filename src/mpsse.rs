@@ -0,0 +1,70 @@
+//! Transport carrying MPSSE commands and their replies
+
+use std::fmt::Debug;
+use std::io::{Read, Write};
+
+use crate::error::JtagError;
+use crate::ftdi;
+
+const BITBANG_BAUDRATE: i32 = 1_000_000;
+
+/// A device that executes MPSSE command bytes and returns their replies
+///
+/// `Write` takes command bytes, `Read` returns whatever reply bytes are
+/// available right now, possibly none. Implemented by `ftdi::Device` and
+/// by `MockJtag` for running without hardware.
+pub trait MpsseIo: Read + Write + Debug {
+    /// Reset the device, as done at the start of `attach`
+    fn usb_reset(&mut self) -> Result<(), JtagError>;
+
+    fn set_latency_timer(&mut self, ms: u8) -> Result<(), JtagError>;
+
+    /// Switch to MPSSE mode with the given pin directions
+    fn enter_mpsse(&mut self, direction: u8) -> Result<(), JtagError>;
+
+    /// Switch to synchronous bitbang mode with the given pin directions
+    ///
+    /// Used instead of `enter_mpsse` on chips without an MPSSE engine.
+    fn enter_bitbang(&mut self, direction: u8) -> Result<(), JtagError>;
+
+    /// Leave MPSSE or bitbang mode, back to the chip's default function
+    fn reset_bitmode(&mut self) -> Result<(), JtagError>;
+
+    /// Drop unsent commands and unread replies
+    fn purge(&mut self) -> Result<(), JtagError>;
+
+    /// Chip family, deciding the base clock and the commands available
+    fn chip_type(&self) -> ftdi::ChipType;
+}
+
+impl MpsseIo for ftdi::Device {
+    fn usb_reset(&mut self) -> Result<(), JtagError> {
+        Ok(ftdi::Device::usb_reset(self)?)
+    }
+
+    fn set_latency_timer(&mut self, ms: u8) -> Result<(), JtagError> {
+        Ok(ftdi::Device::set_latency_timer(self, ms)?)
+    }
+
+    fn enter_mpsse(&mut self, direction: u8) -> Result<(), JtagError> {
+        Ok(self.set_bitmode(direction, ftdi::BitMode::Mpsse)?)
+    }
+
+    fn enter_bitbang(&mut self, direction: u8) -> Result<(), JtagError> {
+        self.set_bitmode(direction, ftdi::BitMode::SyncBb)?;
+        // Pin update rate, libftdi scales it for bitbang mode
+        Ok(self.set_baudrate(BITBANG_BAUDRATE)?)
+    }
+
+    fn reset_bitmode(&mut self) -> Result<(), JtagError> {
+        Ok(self.set_bitmode(0x00, ftdi::BitMode::Reset)?)
+    }
+
+    fn purge(&mut self) -> Result<(), JtagError> {
+        Ok(self.usb_purge_buffers()?)
+    }
+
+    fn chip_type(&self) -> ftdi::ChipType {
+        ftdi::Device::chip_type(self)
+    }
+}
@@ -0,0 +1,705 @@
+use bitvec::order::Lsb0;
+use bitvec::slice::BitSlice;
+use bitvec::vec::BitVec;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::error::JtagError;
+use crate::ftdi;
+use crate::jtag::{
+    bits_to_bytes, bytes_to_bits, BenchResult, ChainParams, ClockEdge, GpioInit, JtagAdapter,
+    JtagChainItem, ResetConfig, Sequence, TuneResult,
+};
+use crate::mpsse::MpsseIo;
+use crate::tap::TapState;
+
+#[derive(Debug)]
+pub struct FtdiProbe<D: MpsseIo = ftdi::Device> {
+    adapter: Mutex<JtagAdapter<D>>,
+    speed_khz: u32,
+    idle_cycles: usize,
+    /// Dwell time `idle_cycles` is derived from, kept across clock changes
+    idle_time: Option<Duration>,
+    auto_idle: bool,
+    attach_clock: u32,
+    /// Chain handed to probe-rs, from `set_scan_chain` or the last scan
+    #[cfg(feature = "probe-rs")]
+    pub(crate) probe_rs_chain: Vec<probe_rs::config::ScanChainElement>,
+}
+
+impl FtdiProbe {
+    pub fn open(vid: u16, pid: u16) -> Result<Self, JtagError> {
+        Ok(Self::new(JtagAdapter::open(vid, pid)?))
+    }
+
+    /// Open the given channel of a multi-channel chip, e.g. interface B of an FT4232H
+    pub fn open_interface(
+        vid: u16,
+        pid: u16,
+        interface: ftdi::Interface,
+    ) -> Result<Self, JtagError> {
+        Ok(Self::new(JtagAdapter::open_interface(vid, pid, interface)?))
+    }
+
+    /// Open the probe with the given USB serial number
+    ///
+    /// Tells identical adapters apart, e.g. several FT2232H boards on one host.
+    pub fn open_by_serial(vid: u16, pid: u16, serial: &str) -> Result<Self, JtagError> {
+        Ok(Self::new(JtagAdapter::open_by_serial(vid, pid, serial)?))
+    }
+
+    /// Open the `index`-th probe (counting from 0) with the given IDs
+    pub fn open_by_index(vid: u16, pid: u16, index: usize) -> Result<Self, JtagError> {
+        Ok(Self::new(JtagAdapter::open_by_index(vid, pid, index)?))
+    }
+
+    /// Wrap a device the caller opened, e.g. with `ftdi::Builder::usb_open_device`
+    ///
+    /// `attach` works the same as for a probe opened by IDs.
+    pub fn from_device(device: ftdi::Device) -> Self {
+        Self::new(JtagAdapter::from_device(device))
+    }
+
+    /// Open the first MPSSE-capable device with the given vendor ID
+    ///
+    /// Looks through `ftdi::list_vendor_devices`, so any product ID is found,
+    /// including the custom ones of clone and vendor cables, e.g. a VID of
+    /// 0x15ba. Chips without MPSSE and devices that can't be opened, e.g.
+    /// because another program has them, are skipped. Returns the probe and
+    /// the product ID of the opened device.
+    pub fn open_any(vid: u16) -> Result<(Self, u16), JtagError> {
+        let devices = ftdi::list_vendor_devices(vid)?;
+        for (i, device) in devices.iter().enumerate() {
+            if !device.chip_type.has_mpsse() {
+                continue;
+            }
+            // Index among the devices with the same IDs, libftdi walks the
+            // same libusb device list
+            let index = devices[..i].iter().filter(|d| d.pid == device.pid).count();
+            match Self::open_by_index(vid, device.pid, index) {
+                Ok(probe) => return Ok((probe, device.pid)),
+                Err(e) => log::debug!(
+                    "skipping {:04x}:{:04x} on bus {} address {}: {}",
+                    vid,
+                    device.pid,
+                    device.bus,
+                    device.address,
+                    e
+                ),
+            }
+        }
+        Err(ftdi::Error::DeviceNotFound.into())
+    }
+
+    /// Read the raw contents of the adapter's EEPROM
+    pub fn read_eeprom(&mut self) -> Result<Vec<u8>, JtagError> {
+        self.adapter.get_mut().unwrap().read_eeprom()
+    }
+
+    /// Read the adapter's EEPROM and decode IDs, strings and pin functions
+    ///
+    /// The CBUS functions tell which pins the vendor assigned to I/O.
+    pub fn eeprom_info(&mut self) -> Result<ftdi::EepromInfo, JtagError> {
+        self.adapter.get_mut().unwrap().eeprom_info()
+    }
+}
+
+impl<D: MpsseIo> FtdiProbe<D> {
+    /// Drive any MPSSE transport, e.g. a `MockJtag`
+    pub fn from_io(io: D) -> Self {
+        Self::new(JtagAdapter::from_io(io))
+    }
+
+    pub(crate) fn adapter_mut(&mut self) -> &mut JtagAdapter<D> {
+        self.adapter.get_mut().unwrap()
+    }
+
+    fn new(adapter: JtagAdapter<D>) -> Self {
+        let probe = FtdiProbe {
+            adapter: Mutex::new(adapter),
+            speed_khz: 0,
+            idle_cycles: 0,
+            idle_time: None,
+            auto_idle: true,
+            attach_clock: 1_000_000,
+            #[cfg(feature = "probe-rs")]
+            probe_rs_chain: vec![],
+        };
+        log::debug!("opened probe: {:?}", probe);
+        probe
+    }
+
+    pub fn attach(&mut self) -> Result<(), JtagError> {
+        log::debug!("attaching...");
+        let adapter = self.adapter.get_mut().unwrap();
+
+        adapter.attach()?;
+        self.set_clock(self.attach_clock)?;
+        self.flush()
+    }
+
+    /// Release the reset lines and take the chip out of MPSSE mode, also done on drop
+    pub fn close(&mut self) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().close()
+    }
+
+    /// Make `close` turn the reset lines into inputs instead of driving them released
+    pub fn set_tristate_on_close(&mut self, enable: bool) {
+        self.adapter
+            .get_mut()
+            .unwrap()
+            .set_tristate_on_close(enable)
+    }
+
+    /// Set the GPIO levels and directions written by `attach`
+    pub fn set_gpio_init(&mut self, init: GpioInit) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().set_gpio_init(init)
+    }
+
+    /// Set the TCK frequency programmed by `attach`, 1 MHz by default
+    pub fn set_attach_clock(&mut self, hz: u32) {
+        self.attach_clock = hz;
+    }
+
+    /// Program the TCK frequency, returning the frequency actually achieved
+    pub fn set_clock(&mut self, hz: u32) -> Result<u32, JtagError> {
+        let actual = self.adapter.get_mut().unwrap().set_clock(hz)?;
+        self.clock_changed(actual);
+        Ok(actual)
+    }
+
+    /// Program the fastest TCK not above `max_hz`, returning it
+    ///
+    /// A `max_hz` above the chip's fastest clock selects that clock.
+    pub fn set_clock_capped(&mut self, max_hz: u32) -> Result<u32, JtagError> {
+        let actual = self.adapter.get_mut().unwrap().set_clock_capped(max_hz)?;
+        self.clock_changed(actual);
+        Ok(actual)
+    }
+
+    /// Keep the speed and the idle cycles of `set_idle_time` in step with TCK
+    fn clock_changed(&mut self, hz: u32) {
+        self.speed_khz = hz / 1000;
+        if let Some(time) = self.idle_time {
+            self.set_idle_time(time);
+        }
+    }
+
+    pub fn speed_khz(&self) -> u32 {
+        self.speed_khz
+    }
+
+    /// Chip family detected from the USB descriptor
+    pub fn chip_type(&self) -> ftdi::ChipType {
+        self.adapter.lock().unwrap().chip_type()
+    }
+
+    /// TCK frequency the divisor is programmed for, the MPSSE default until `set_clock`
+    pub fn clock_hz(&self) -> u32 {
+        self.adapter.lock().unwrap().clock_hz()
+    }
+
+    /// Set the maximum number of stale bytes discarded during `attach`
+    pub fn set_drain_limit(&mut self, bytes: usize) {
+        self.adapter.get_mut().unwrap().set_drain_limit(bytes);
+    }
+
+    /// Enable RTCK adaptive clocking, sampling RTCK on GPIOL3 (ADBUS7)
+    ///
+    /// For targets whose JTAG clock is gated or can't follow a fixed TCK.
+    pub fn set_adaptive_clock(&mut self, enable: bool) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().set_adaptive_clock(enable)
+    }
+
+    /// Set the GPIO sampled as RTCK, GPIOL3 (ADBUS7) by default
+    pub fn set_rtck_pin(&mut self, pin: u8) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().set_rtck_pin(pin)
+    }
+
+    /// Retry a `transfer_dr` that timed out at halved clocks, down to 100 kHz
+    ///
+    /// For marginal cables. The original clock is restored after the retry.
+    /// Replies that arrive but are wrong are not retried, and a retried scan
+    /// is shifted again in full, so DMI operations and other side effects
+    /// of the register happen twice.
+    pub fn set_auto_clock_fallback(&mut self, enable: bool) {
+        self.adapter
+            .get_mut()
+            .unwrap()
+            .set_auto_clock_fallback(enable);
+    }
+
+    /// Set the state scans leave the TAP in, RUN-TEST/IDLE by default
+    ///
+    /// PAUSE-DR or PAUSE-IR suit targets sensitive to run-test clocking.
+    pub fn set_park_state(&mut self, state: TapState) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().set_park_state(state)
+    }
+
+    /// Record the TAP states each operation goes through
+    pub fn set_record_state_path(&mut self, enable: bool) {
+        self.adapter
+            .get_mut()
+            .unwrap()
+            .set_record_state_path(enable);
+    }
+
+    /// States the last operation went through, empty unless recording
+    pub fn last_state_path(&self) -> Vec<TapState> {
+        self.adapter.lock().unwrap().last_state_path().to_vec()
+    }
+
+    /// Set the USB latency timer (1-255 ms, 1 by default)
+    ///
+    /// 1 ms suits interactive debugging with many small transfers. Raise it
+    /// for bulk transfers like bitstreams, or if the USB stack is unstable.
+    pub fn set_latency_timer(&mut self, ms: u8) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().set_latency_timer(ms)
+    }
+
+    /// Select the clock edges for driving TDI/TMS and sampling TDO
+    pub fn set_clock_edge(&mut self, edge: ClockEdge) {
+        self.adapter.get_mut().unwrap().set_clock_edge(edge);
+    }
+
+    /// Set the base timeout for a device reply, scaled up by the TCK frequency
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.adapter.get_mut().unwrap().set_read_timeout(timeout);
+    }
+
+    /// Set the number of TAPs looked for when scanning the chain
+    pub fn set_max_devices(&mut self, count: usize) {
+        self.adapter.get_mut().unwrap().set_max_devices(count);
+    }
+
+    /// Set the GPIO wiring of the nTRST/nSRST lines, released by `attach`
+    pub fn set_reset_config(&mut self, config: ResetConfig) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().set_reset_config(config)
+    }
+
+    /// Assert (`true`) or release (`false`) nTRST and nSRST, `None` leaves one as is
+    ///
+    /// Without a TRST pin, asserting nTRST resets the TAP through TMS.
+    pub fn set_reset(&mut self, trst: Option<bool>, srst: Option<bool>) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().set_reset(trst, srst)
+    }
+
+    /// Assert nSRST for `duration`, then release it
+    pub fn pulse_srst(&mut self, duration: Duration) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().pulse_srst(duration)
+    }
+
+    /// Drive a spare GPIO (0-7 on ADBUS, 8-15 on ACBUS) to `level`
+    pub fn gpio_set(&mut self, pin: u8, level: bool) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().gpio_set(pin, level)
+    }
+
+    /// Read the level of a GPIO (0-7 on ADBUS, 8-15 on ACBUS)
+    pub fn gpio_get(&mut self, pin: u8) -> Result<bool, JtagError> {
+        self.adapter.get_mut().unwrap().gpio_get(pin)
+    }
+
+    /// Configure a spare GPIO as an output or an input
+    pub fn gpio_set_direction(&mut self, pin: u8, output: bool) -> Result<(), JtagError> {
+        self.adapter
+            .get_mut()
+            .unwrap()
+            .gpio_set_direction(pin, output)
+    }
+
+    /// Write out the queued MPSSE commands
+    ///
+    /// Reads flush on their own, this is only needed for write-only operations.
+    pub fn flush(&mut self) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().flush()
+    }
+
+    /// Send raw MPSSE commands and read `read_len` reply bytes
+    ///
+    /// Bypasses the TAP state tracker, call `reset` after moving TMS.
+    pub fn raw_mpsse(&mut self, command: &[u8], read_len: usize) -> Result<Vec<u8>, JtagError> {
+        self.adapter.get_mut().unwrap().raw_mpsse(command, read_len)
+    }
+
+    /// Set an IR value (data, length in bits) loaded right after `attach`
+    pub fn set_attach_ir(&mut self, ir: Option<(Vec<u8>, usize)>) {
+        self.adapter.get_mut().unwrap().set_attach_ir(ir);
+    }
+
+    pub fn is_loopback_enabled(&self) -> bool {
+        self.adapter.lock().unwrap().is_loopback_enabled()
+    }
+
+    /// Wait until the adapter has processed every command sent so far
+    pub fn sync(&mut self) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().sync()
+    }
+
+    /// Check that the adapter itself works, independent of any target
+    ///
+    /// Shifts a pattern through MPSSE loopback and compares what comes back.
+    pub fn loopback_test(&mut self) -> Result<bool, JtagError> {
+        self.adapter.get_mut().unwrap().loopback_test()
+    }
+
+    /// Measure DR throughput and USB latency at the current settings
+    pub fn benchmark(&mut self, bytes: usize) -> Result<BenchResult, JtagError> {
+        self.adapter.get_mut().unwrap().benchmark(bytes)
+    }
+
+    /// Size the command queue and USB reads from the measured round trip
+    pub fn auto_tune(&mut self) -> Result<TuneResult, JtagError> {
+        self.adapter.get_mut().unwrap().auto_tune()
+    }
+
+    /// Build a batch of IR/DR scans
+    pub fn sequence(&mut self) -> Sequence<'_, D> {
+        self.adapter.get_mut().unwrap().sequence()
+    }
+
+    /// Lightweight health check, false if the device dropped out of MPSSE mode
+    pub fn is_alive(&mut self) -> bool {
+        let adapter = self.adapter.get_mut().unwrap();
+        matches!(adapter.check_mpsse(), Ok(true))
+    }
+
+    /// Generate an OpenOCD config snippet for the last scanned chain
+    pub fn export_openocd_config(&self) -> String {
+        self.adapter.lock().unwrap().export_openocd_config()
+    }
+
+    /// TAPs found by the last scan
+    pub fn chain(&self) -> Vec<JtagChainItem> {
+        self.adapter.lock().unwrap().chain().to_vec()
+    }
+
+    /// Parameters of the selected target
+    pub fn chain_params(&self) -> Option<ChainParams> {
+        self.adapter.lock().unwrap().chain_params().cloned()
+    }
+
+    /// Reset and go to RUN-TEST/IDLE
+    pub fn reset(&mut self) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().reset()
+    }
+
+    /// Shift to IR and return to the park state
+    pub fn shift_ir(&mut self, data: &[u8], bits: usize) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().shift_ir(data, bits)
+    }
+
+    /// Shift to IR and return to the park state
+    pub fn transfer_ir(&mut self, data: &[u8], bits: usize) -> Result<Vec<u8>, JtagError> {
+        self.adapter.get_mut().unwrap().transfer_ir(data, bits)
+    }
+
+    /// Shift to DR without capturing TDO and return to the park state
+    pub fn shift_dr(&mut self, data: &[u8], bits: usize) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().shift_dr(data, bits)
+    }
+
+    /// Shift bits to IR and return to the park state
+    pub fn transfer_ir_bits(
+        &mut self,
+        data: &BitSlice<Lsb0, u8>,
+    ) -> Result<BitVec<Lsb0, u8>, JtagError> {
+        self.adapter.get_mut().unwrap().transfer_ir_bits(data)
+    }
+
+    /// Shift bits to DR and return to the park state
+    pub fn transfer_dr_bits(
+        &mut self,
+        data: &BitSlice<Lsb0, u8>,
+    ) -> Result<BitVec<Lsb0, u8>, JtagError> {
+        self.adapter.get_mut().unwrap().transfer_dr_bits(data)
+    }
+
+    /// Shift to DR and return to the park state
+    pub fn transfer_dr(&mut self, data: &[u8], bits: usize) -> Result<Vec<u8>, JtagError> {
+        self.adapter.get_mut().unwrap().transfer_dr(data, bits)
+    }
+
+    /// Scan the chain for the IDCODE and IR length of every TAP
+    pub fn scan_chain(&mut self) -> Result<Vec<JtagChainItem>, JtagError> {
+        self.adapter.get_mut().unwrap().scan_chain()
+    }
+
+    /// Measure the chain through BYPASS, returning (total IR bits, TAP count)
+    pub fn detect_irlen(&mut self) -> Result<(usize, usize), JtagError> {
+        self.adapter.get_mut().unwrap().detect_irlen()
+    }
+
+    /// Select the TAP with the given IDCODE, scanning the chain unless it's cached
+    pub fn select_target(&mut self, idcode: u32) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().select_target(idcode)
+    }
+
+    /// Select the TAP at `index` in the chain, for TAPs sharing an IDCODE
+    pub fn select_target_by_index(&mut self, index: usize) -> Result<(), JtagError> {
+        self.adapter
+            .get_mut()
+            .unwrap()
+            .select_target_by_index(index)
+    }
+
+    /// Take the chain from known IR lengths instead of scanning it
+    pub fn set_ir_lengths(&mut self, irlens: &[usize]) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().set_ir_lengths(irlens)
+    }
+
+    /// Drop the cached chain and scan it again
+    pub fn rescan(&mut self) -> Result<Vec<JtagChainItem>, JtagError> {
+        self.adapter.get_mut().unwrap().rescan()
+    }
+}
+
+impl<D: MpsseIo> FtdiProbe<D> {
+    /// Read a register of the selected target, `len` in bits like all lengths
+    pub fn read_register(&mut self, address: u32, len: usize) -> Result<Vec<u8>, JtagError> {
+        log::debug!("read_register({:#x}, {})", address, len);
+        let adapter = self.adapter.get_mut().unwrap();
+        let r = adapter.target_transfer(address, None, len)?;
+        if self.auto_idle {
+            adapter.idle(self.idle_cycles)?;
+        }
+        log::debug!("read_register result: {:?})", r);
+        Ok(r)
+    }
+
+    /// Read several registers given as (address, length in bits)
+    ///
+    /// IR is only shifted when the address differs from the previous one, so
+    /// repeated reads of the same register cost a single DR scan each.
+    pub fn read_registers(&mut self, regs: &[(u32, usize)]) -> Result<Vec<Vec<u8>>, JtagError> {
+        log::debug!("read_registers({:?})", regs);
+        let adapter = self.adapter.get_mut().unwrap();
+        let mut results = Vec::with_capacity(regs.len());
+        let mut selected = None;
+        for &(address, len) in regs {
+            if selected != Some(address) {
+                adapter.target_select_register(address)?;
+                selected = Some(address);
+            }
+            results.push(adapter.target_dr(None, len)?);
+            if self.auto_idle {
+                adapter.idle(self.idle_cycles)?;
+            }
+        }
+        Ok(results)
+    }
+
+    /// Cycles spent in RUN-TEST/IDLE after register transfers
+    pub fn idle_cycles(&self) -> usize {
+        self.idle_cycles
+    }
+
+    pub fn set_idle_cycles(&mut self, idle_cycles: u8) {
+        log::debug!("set_idle_cycles({})", idle_cycles);
+        self.idle_cycles = idle_cycles.into();
+        self.idle_time = None;
+    }
+
+    /// Stay in RUN-TEST/IDLE for at least `time` after register transfers
+    ///
+    /// The cycle count follows the clock, rounding up, and is recomputed by
+    /// `set_clock`. `set_idle_cycles` replaces it with a fixed count.
+    pub fn set_idle_time(&mut self, time: Duration) {
+        let hz = self.clock_hz();
+        let cycles = (time.as_nanos() * u128::from(hz) + 999_999_999) / 1_000_000_000;
+        log::debug!("set_idle_time({:?}): {} cycles at {} Hz", time, cycles, hz);
+        self.idle_cycles = cycles as usize;
+        self.idle_time = Some(time);
+    }
+
+    /// Control whether register transfers end with `idle_cycles` in RUN-TEST/IDLE
+    ///
+    /// When disabled, the caller is responsible for calling `idle`.
+    pub fn set_auto_idle(&mut self, enable: bool) {
+        self.auto_idle = enable;
+    }
+
+    /// Execute RUN-TEST/IDLE for a number of cycles
+    pub fn idle(&mut self, cycles: usize) -> Result<(), JtagError> {
+        self.adapter.get_mut().unwrap().idle(cycles)
+    }
+
+    pub fn write_register(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        len: usize,
+    ) -> Result<Vec<u8>, JtagError> {
+        log::debug!("write_register({:#x}, {:?}, {})", address, data, len);
+        let adapter = self.adapter.get_mut().unwrap();
+        let r = adapter.target_transfer(address, Some(data), len)?;
+        if self.auto_idle {
+            adapter.idle(self.idle_cycles)?;
+        }
+        log::debug!("write_register result: {:?})", r);
+        Ok(r)
+    }
+
+    /// Shift the DR of the selected target without writing IR first
+    ///
+    /// Accesses the register the last transfer selected again, saving the
+    /// IR scan.
+    pub fn write_selected_dr(&mut self, data: &[u8], len: usize) -> Result<Vec<u8>, JtagError> {
+        let adapter = self.adapter.get_mut().unwrap();
+        let r = adapter.target_dr(Some(data), len)?;
+        if self.auto_idle {
+            adapter.idle(self.idle_cycles)?;
+        }
+        Ok(r)
+    }
+
+    /// Write a register without reading back its previous value
+    ///
+    /// Saves the USB reply of `write_register` for bulk writes.
+    pub fn write_register_void(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        len: usize,
+    ) -> Result<(), JtagError> {
+        log::debug!("write_register_void({:#x}, {:?}, {})", address, data, len);
+        let adapter = self.adapter.get_mut().unwrap();
+        adapter.target_write(address, data, len)?;
+        if self.auto_idle {
+            adapter.idle(self.idle_cycles)?;
+        }
+        Ok(())
+    }
+
+    /// Write a register and read it back, failing with `VerifyMismatch` if it differs
+    ///
+    /// Only for registers that hold the written value, e.g. scratch or data
+    /// registers. Catches a TCK too fast for the wiring early.
+    pub fn write_register_verified(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        len: usize,
+    ) -> Result<(), JtagError> {
+        let mask = vec![0xff; (len + 7) / 8];
+        self.write_register_verified_masked(address, data, &mask, len)
+    }
+
+    /// `write_register_verified` comparing only the bits set in `mask`
+    pub fn write_register_verified_masked(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        mask: &[u8],
+        len: usize,
+    ) -> Result<(), JtagError> {
+        if mask.len() * 8 < len {
+            return Err(JtagError::InvalidInput("mask shorter than the register"));
+        }
+        self.write_register_void(address, data, len)?;
+        let actual = self.read_register(address, len)?;
+
+        let expected = bits_to_bytes(&bytes_to_bits(data.to_vec(), len));
+        let differs = (0..len).any(|i| {
+            let bit = |bytes: &[u8]| bytes[i / 8] >> (i % 8) & 1;
+            mask[i / 8] >> (i % 8) & 1 != 0 && bit(&expected) != bit(&actual)
+        });
+        if differs {
+            return Err(JtagError::VerifyMismatch {
+                addr: address,
+                expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    /// Write a register and return its previous contents
+    ///
+    /// The old value is shifted out on TDO while the new one is shifted in,
+    /// in the same DR scan, so no update can slip in between the two.
+    pub fn exchange_register(
+        &mut self,
+        address: u32,
+        write: &[u8],
+        len: usize,
+    ) -> Result<Vec<u8>, JtagError> {
+        self.write_register(address, write, len)
+    }
+
+    /// `exchange_register` on bits, for registers that aren't a whole number of bytes
+    ///
+    /// The register is as wide as `write`, the result has the same length.
+    pub fn exchange_register_bits(
+        &mut self,
+        address: u32,
+        write: &BitSlice<Lsb0, u8>,
+    ) -> Result<BitVec<Lsb0, u8>, JtagError> {
+        let r = self.write_register(address, &bits_to_bytes(write), write.len())?;
+        Ok(bytes_to_bits(r, write.len()))
+    }
+
+    /// 32-bit `exchange_register`
+    pub fn exchange_register32(&mut self, address: u32, value: u32) -> Result<u32, JtagError> {
+        log::debug!("exchange_register32({:#x}, {:#010x})", address, value);
+        let r = self.register32(address, Some(value))?;
+        log::debug!("exchange_register32 result: {:#010x}", r);
+        Ok(r)
+    }
+
+    fn register32(&mut self, address: u32, value: Option<u32>) -> Result<u32, JtagError> {
+        let adapter = self.adapter.get_mut().unwrap();
+        let r = adapter.target_transfer32(address, value)?;
+        if self.auto_idle {
+            adapter.idle(self.idle_cycles)?;
+        }
+        Ok(r)
+    }
+
+    pub fn read_register32(&mut self, address: u32) -> Result<u32, JtagError> {
+        log::debug!("read_register32({:#x})", address);
+        let r = self.register32(address, None)?;
+        log::debug!("read_register32 result: {:#010x}", r);
+        Ok(r)
+    }
+
+    /// Read a register of up to 64 bits as an integer, LSB first
+    pub fn read_register_int(&mut self, address: u32, bits: usize) -> Result<u64, JtagError> {
+        if bits == 0 || bits > 64 {
+            return Err(JtagError::InvalidInput(
+                "register width must be 1 to 64 bits",
+            ));
+        }
+        let r = self.read_register(address, bits)?;
+
+        let mut buf = [0; 8];
+        buf[..r.len()].copy_from_slice(&r);
+        let mask = !0u64 >> (64 - bits);
+        Ok(u64::from_le_bytes(buf) & mask)
+    }
+
+    pub fn read_register_u16(&mut self, address: u32) -> Result<u16, JtagError> {
+        Ok(self.read_register_int(address, 16)? as u16)
+    }
+
+    pub fn read_register_u64(&mut self, address: u32) -> Result<u64, JtagError> {
+        self.read_register_int(address, 64)
+    }
+
+    /// Write a 32-bit register, discarding the value shifted out
+    ///
+    /// TDO is not captured, which saves the USB read of the shifted out value.
+    pub fn write_register32_void(&mut self, address: u32, value: u32) -> Result<(), JtagError> {
+        log::debug!("write_register32_void({:#x}, {:#010x})", address, value);
+        let adapter = self.adapter.get_mut().unwrap();
+        adapter.target_write(address, &value.to_le_bytes(), 32)?;
+        if self.auto_idle {
+            adapter.idle(self.idle_cycles)?;
+        }
+        Ok(())
+    }
+
+    #[deprecated(note = "use exchange_register32 or write_register32_void")]
+    pub fn write_register32(&mut self, address: u32, value: u32) -> Result<u32, JtagError> {
+        self.exchange_register32(address, value)
+    }
+}
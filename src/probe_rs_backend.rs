@@ -0,0 +1,218 @@
+//! `FtdiProbe` as a probe-rs JTAG probe
+
+use bitvec::order::Lsb0;
+use bitvec::vec::BitVec;
+use bitvec1::vec::BitVec as ProbeRsBitVec;
+use probe_rs::architecture::riscv::communication_interface::{RiscvError, RiscvInterfaceBuilder};
+use probe_rs::architecture::riscv::JtagDtmBuilder;
+use probe_rs::config::ScanChainElement;
+use probe_rs::probe::{
+    DebugProbe, DebugProbeError, DebugProbeInfo, DebugProbeSelector, JtagAccess, JtagSequence,
+    ProbeError, ProbeFactory, WireProtocol,
+};
+use std::fmt;
+use std::iter::FromIterator;
+use std::time::Duration;
+
+use crate::error::JtagError;
+use crate::ftdi;
+use crate::mpsse::MpsseIo;
+use crate::probe::FtdiProbe;
+
+/// SRST pulse length of `target_reset`
+const RESET_PULSE: Duration = Duration::from_millis(100);
+
+// Also converts into `DebugProbeError::ProbeSpecific`
+impl ProbeError for JtagError {}
+
+/// Bits `0..len` of LSB-first bytes, in whatever bit vector the caller needs
+fn bits<B: FromIterator<bool>>(bytes: &[u8], len: usize) -> B {
+    (0..len).map(|i| bytes[i / 8] >> (i % 8) & 1 != 0).collect()
+}
+
+/// Lists and opens FTDI probes for probe-rs
+///
+/// Hand it to `probe_rs::probe::list::Lister::with_lister`, or open a probe
+/// directly and wrap it with `probe_rs::probe::Probe::from_specific_probe`.
+#[derive(Debug)]
+pub struct FtdiProbeFactory;
+
+impl fmt::Display for FtdiProbeFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FTDI MPSSE")
+    }
+}
+
+impl ProbeFactory for FtdiProbeFactory {
+    fn open(&self, selector: &DebugProbeSelector) -> Result<Box<dyn DebugProbe>, DebugProbeError> {
+        let probe = match &selector.serial_number {
+            Some(serial) => {
+                FtdiProbe::open_by_serial(selector.vendor_id, selector.product_id, serial)?
+            }
+            None => FtdiProbe::open(selector.vendor_id, selector.product_id)?,
+        };
+        Ok(Box::new(probe))
+    }
+
+    fn list_probes(&self) -> Vec<DebugProbeInfo> {
+        let devices = match ftdi::list_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                log::debug!("listing FTDI devices failed: {}", e);
+                return vec![];
+            }
+        };
+        devices
+            .into_iter()
+            .filter(|device| device.chip_type.has_mpsse())
+            .map(|device| {
+                DebugProbeInfo::new(
+                    device.product.unwrap_or_else(|| "FTDI MPSSE".to_string()),
+                    device.vid,
+                    device.pid,
+                    device.serial,
+                    &FtdiProbeFactory,
+                    None,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Probe-rs drives SRST through this impl, which must be set up with
+/// `set_reset_config`. RISC-V targets are debugged through probe-rs' own
+/// JTAG DTM on top of `JtagAccess`. ARM targets need a JTAG-DP, which this
+/// backend doesn't implement, so it reports no ARM interface.
+impl<D: MpsseIo + Send + 'static> DebugProbe for FtdiProbe<D> {
+    fn get_name(&self) -> &str {
+        "FTDI MPSSE"
+    }
+
+    fn speed_khz(&self) -> u32 {
+        FtdiProbe::speed_khz(self)
+    }
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        let hz = self.set_clock(speed_khz.saturating_mul(1000))?;
+        Ok(hz / 1000)
+    }
+
+    fn attach(&mut self) -> Result<(), DebugProbeError> {
+        Ok(FtdiProbe::attach(self)?)
+    }
+
+    fn detach(&mut self) -> Result<(), probe_rs::Error> {
+        self.flush().map_err(|e| DebugProbeError::from(e).into())
+    }
+
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        Ok(self.pulse_srst(RESET_PULSE)?)
+    }
+
+    fn target_reset_assert(&mut self) -> Result<(), DebugProbeError> {
+        Ok(self.set_reset(None, Some(true))?)
+    }
+
+    fn target_reset_deassert(&mut self) -> Result<(), DebugProbeError> {
+        Ok(self.set_reset(None, Some(false))?)
+    }
+
+    fn select_protocol(&mut self, protocol: WireProtocol) -> Result<(), DebugProbeError> {
+        match protocol {
+            WireProtocol::Jtag => Ok(()),
+            _ => Err(DebugProbeError::UnsupportedProtocol(protocol)),
+        }
+    }
+
+    fn active_protocol(&self) -> Option<WireProtocol> {
+        Some(WireProtocol::Jtag)
+    }
+
+    fn try_as_jtag_probe(&mut self) -> Option<&mut dyn JtagAccess> {
+        Some(self)
+    }
+
+    fn try_get_riscv_interface_builder<'probe>(
+        &'probe mut self,
+    ) -> Result<Box<dyn RiscvInterfaceBuilder<'probe> + 'probe>, RiscvError> {
+        Ok(Box::new(JtagDtmBuilder::new(self)))
+    }
+
+    fn has_riscv_interface(&self) -> bool {
+        true
+    }
+
+    fn into_probe(self: Box<Self>) -> Box<dyn DebugProbe> {
+        self
+    }
+}
+
+/// Register accesses go to the target selected with `select_target`, with
+/// the other TAPs of the chain in BYPASS.
+impl<D: MpsseIo + Send + 'static> JtagAccess for FtdiProbe<D> {
+    fn set_scan_chain(&mut self, scan_chain: &[ScanChainElement]) -> Result<(), DebugProbeError> {
+        let irlens: Vec<usize> = scan_chain.iter().map(|tap| tap.ir_len().into()).collect();
+        self.set_ir_lengths(&irlens)?;
+        self.probe_rs_chain = scan_chain.to_vec();
+        Ok(())
+    }
+
+    fn scan_chain(&mut self) -> Result<&[ScanChainElement], DebugProbeError> {
+        if self.probe_rs_chain.is_empty() {
+            let taps = FtdiProbe::scan_chain(self)?;
+            self.probe_rs_chain = taps
+                .iter()
+                .map(|tap| ScanChainElement {
+                    name: None,
+                    ir_len: Some(tap.irlen as u8),
+                })
+                .collect();
+        }
+        Ok(&self.probe_rs_chain)
+    }
+
+    fn shift_raw_sequence(
+        &mut self,
+        sequence: JtagSequence,
+    ) -> Result<ProbeRsBitVec, DebugProbeError> {
+        let tdi: BitVec<Lsb0, u8> = sequence.data.iter().map(|bit| *bit).collect();
+        let tdo = self
+            .adapter_mut()
+            .shift_raw(sequence.tms, &tdi, sequence.tdo_capture)?;
+        Ok(tdo.iter().copied().collect())
+    }
+
+    fn tap_reset(&mut self) -> Result<(), DebugProbeError> {
+        Ok(self.reset()?)
+    }
+
+    fn set_idle_cycles(&mut self, idle_cycles: u8) -> Result<(), DebugProbeError> {
+        FtdiProbe::set_idle_cycles(self, idle_cycles);
+        Ok(())
+    }
+
+    fn idle_cycles(&self) -> u8 {
+        FtdiProbe::idle_cycles(self).min(u8::MAX.into()) as u8
+    }
+
+    fn select_target(&mut self, index: usize) -> Result<(), DebugProbeError> {
+        Ok(self.select_target_by_index(index)?)
+    }
+
+    fn write_register(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        len: u32,
+    ) -> Result<ProbeRsBitVec, DebugProbeError> {
+        let len = len as usize;
+        let r = FtdiProbe::write_register(self, address, data, len)?;
+        Ok(bits(&r, len))
+    }
+
+    fn write_dr(&mut self, data: &[u8], len: u32) -> Result<ProbeRsBitVec, DebugProbeError> {
+        let len = len as usize;
+        let r = self.write_selected_dr(data, len)?;
+        Ok(bits(&r, len))
+    }
+}
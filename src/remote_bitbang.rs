@@ -0,0 +1,119 @@
+//! OpenOCD `remote_bitbang` server
+//!
+//! OpenOCD drives the pins one ASCII character at a time: `0`-`7` set TCK,
+//! TMS and TDI (bits 2, 1 and 0), `R` samples TDO, `r`-`u` set TRST and SRST
+//! (bits 1 and 0, set means asserted) and `Q` closes the connection. Every
+//! rising TCK edge becomes a one-bit MPSSE TMS shift.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+use crate::error::JtagError;
+use crate::jtag::JtagAdapter;
+use crate::mpsse::MpsseIo;
+use crate::probe::FtdiProbe;
+
+/// ADBUS2
+const TDO_MASK: u16 = 1 << 2;
+
+/// Serve a single OpenOCD connection on `addr` until it quits or disconnects
+///
+/// The probe must be attached. Use with e.g.
+/// `openocd -c "adapter driver remote_bitbang; remote_bitbang port 3335"`.
+pub fn serve<D: MpsseIo>(mut probe: FtdiProbe<D>, addr: SocketAddr) -> Result<(), JtagError> {
+    let listener = TcpListener::bind(addr)?;
+    log::info!("remote_bitbang listening on {}", listener.local_addr()?);
+
+    let (stream, peer) = listener.accept()?;
+    log::info!("remote_bitbang connection from {}", peer);
+    stream.set_nodelay(true)?;
+
+    let mut session = Session {
+        adapter: probe.adapter_mut(),
+        tck: false,
+        tms: false,
+        tdi: false,
+    };
+    session.run(stream)
+}
+
+struct Session<'a, D: MpsseIo> {
+    adapter: &'a mut JtagAdapter<D>,
+    tck: bool,
+    tms: bool,
+    tdi: bool,
+}
+
+impl<D: MpsseIo> Session<'_, D> {
+    fn run(&mut self, mut stream: TcpStream) -> Result<(), JtagError> {
+        let mut buf = [0; 4096];
+        let mut replies = vec![];
+
+        loop {
+            let n = stream.read(&mut buf)?;
+            if n == 0 {
+                log::info!("remote_bitbang client disconnected");
+                break;
+            }
+
+            let mut quit = false;
+            for &c in &buf[..n] {
+                if c == b'Q' {
+                    quit = true;
+                    break;
+                }
+                if let Some(reply) = self.command(c)? {
+                    replies.push(reply);
+                }
+            }
+
+            // Replies and clocks must be out before blocking on the next read
+            self.adapter.flush()?;
+            stream.write_all(&replies)?;
+            replies.clear();
+
+            if quit {
+                log::info!("remote_bitbang client quit");
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle one protocol character, returning the reply byte if there's one
+    fn command(&mut self, c: u8) -> Result<Option<u8>, JtagError> {
+        match c {
+            b'0'..=b'7' => {
+                let bits = c - b'0';
+                let tck = bits & 0x4 != 0;
+                self.tms = bits & 0x2 != 0;
+                self.tdi = bits & 0x1 != 0;
+                // MPSSE clocks whole cycles, so only rising edges matter
+                if tck && !self.tck {
+                    self.adapter.clock_bit(self.tms, self.tdi)?;
+                }
+                self.tck = tck;
+            }
+            b'R' => {
+                // TCK is low after an MPSSE clock, TDO already holds the next bit
+                let tdo = self.adapter.read_pins()? & TDO_MASK != 0;
+                return Ok(Some(if tdo { b'1' } else { b'0' }));
+            }
+            b'r'..=b'u' => {
+                let bits = c - b'r';
+                self.adapter
+                    .set_reset(Some(bits & 0x2 != 0), Some(bits & 0x1 != 0))?;
+            }
+            // LED control
+            b'B' | b'b' => {}
+            b'O' | b'o' | b'c'..=b'f' | b'V' => {
+                return Err(JtagError::InvalidInput(
+                    "remote_bitbang SWD commands are not supported",
+                ));
+            }
+            b'\r' | b'\n' => {}
+            c => log::warn!("remote_bitbang: ignoring unknown command {:?}", c as char),
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,310 @@
+//! RISC-V Debug Transport Module (debug spec 0.13) access
+
+use std::fmt;
+
+use crate::error::JtagError;
+use crate::ftdi;
+use crate::mpsse::MpsseIo;
+use crate::probe::FtdiProbe;
+
+const DTMCS: u32 = 0x10;
+const DMI: u32 = 0x11;
+
+const ABSTRACTCS: u32 = 0x16;
+const SBCS: u32 = 0x38;
+const SBADDRESS0: u32 = 0x39;
+const SBDATA0: u32 = 0x3c;
+
+const ABSTRACTCS_BUSY: u32 = 1 << 12;
+
+const SBCS_SBBUSYERROR: u32 = 1 << 22;
+const SBCS_SBBUSY: u32 = 1 << 21;
+const SBCS_SBREADONADDR: u32 = 1 << 20;
+const SBCS_SBACCESS32: u32 = 2 << 17;
+const SBCS_SBAUTOINCREMENT: u32 = 1 << 16;
+const SBCS_SBREADONDATA: u32 = 1 << 15;
+const SBCS_SBERROR: u32 = 7 << 12;
+
+const DMI_OP_NOP: u8 = 0;
+const DMI_OP_READ: u8 = 1;
+const DMI_OP_WRITE: u8 = 2;
+
+const DMI_STATUS_SUCCESS: u8 = 0;
+const DMI_STATUS_FAILED: u8 = 2;
+const DMI_STATUS_BUSY: u8 = 3;
+
+/// Busy replies tolerated for a single access before giving up
+const MAX_BUSY_RETRIES: usize = 16;
+
+/// Decoded DTM control and status register (`dtmcs`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dtmcs {
+    /// 0 for debug spec 0.11, 1 for 0.13
+    pub version: u8,
+    /// Width of the DMI address field
+    pub abits: u8,
+    /// 0: no error, 2: an operation failed, 3: an operation was busy
+    pub dmistat: u8,
+    /// Minimal RUN-TEST/IDLE cycles to stay in between DMI scans
+    pub idle: u8,
+}
+
+impl Dtmcs {
+    pub fn from_raw(value: u32) -> Self {
+        Self {
+            version: (value & 0xf) as u8,
+            abits: ((value >> 4) & 0x3f) as u8,
+            dmistat: ((value >> 10) & 0x3) as u8,
+            idle: ((value >> 12) & 0x7) as u8,
+        }
+    }
+
+    /// Command word clearing a sticky DMI error or busy condition
+    pub fn dmireset() -> u32 {
+        1 << 16
+    }
+
+    /// Command word resetting the DTM, cancelling an outstanding DMI operation
+    pub fn dmihardreset() -> u32 {
+        1 << 17
+    }
+}
+
+impl fmt::Display for Dtmcs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let version = match self.version {
+            0 => "0.11",
+            1 => "0.13",
+            15 => "custom",
+            _ => "unknown",
+        };
+        let dmistat = match self.dmistat {
+            0 => "ok",
+            2 => "failed",
+            3 => "busy",
+            _ => "reserved",
+        };
+        write!(
+            f,
+            "version {} ({}), abits {}, dmistat {} ({}), idle {}",
+            self.version, version, self.abits, self.dmistat, dmistat, self.idle
+        )
+    }
+}
+
+/// How the DMI layer tells that the Debug Module is busy
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DmiBusyPolicy {
+    /// A busy `op` in the DMI reply, as in the spec
+    #[default]
+    OpField,
+    /// `abstractcs.busy`, polled before every access, for DTMs whose `op`
+    /// field never reports busy. A busy `op` still means the operation was
+    /// dropped, it's issued again once `abstractcs` is idle.
+    StatusRegister,
+    /// Either of them
+    Both,
+}
+
+/// Debug Module Interface access through a RISC-V DTM
+///
+/// The probe must be attached with the DTM selected as the target.
+#[derive(Debug)]
+pub struct RiscvDtm<D: MpsseIo = ftdi::Device> {
+    probe: FtdiProbe<D>,
+    abits: u32,
+    idle_cycles: u8,
+    busy_policy: DmiBusyPolicy,
+}
+
+impl<D: MpsseIo> RiscvDtm<D> {
+    /// Read `dtmcs` to size the DMI register and set the minimal idle cycles
+    pub fn new(mut probe: FtdiProbe<D>) -> Result<Self, JtagError> {
+        let dtmcs = Dtmcs::from_raw(probe.read_register32(DTMCS)?);
+        log::debug!("dtmcs: {}", dtmcs);
+        if dtmcs.abits == 0 {
+            return Err(JtagError::InvalidInput("dtmcs reports no DMI address bits"));
+        }
+
+        probe.set_idle_cycles(dtmcs.idle);
+        Ok(Self {
+            probe,
+            abits: dtmcs.abits.into(),
+            idle_cycles: dtmcs.idle,
+            busy_policy: DmiBusyPolicy::default(),
+        })
+    }
+
+    pub fn read_dtmcs(&mut self) -> Result<Dtmcs, JtagError> {
+        Ok(Dtmcs::from_raw(self.probe.read_register32(DTMCS)?))
+    }
+
+    pub fn into_probe(self) -> FtdiProbe<D> {
+        self.probe
+    }
+
+    /// Width of the DMI address field
+    pub fn abits(&self) -> u32 {
+        self.abits
+    }
+
+    /// Select how a busy Debug Module is detected, `OpField` by default
+    pub fn set_busy_policy(&mut self, policy: DmiBusyPolicy) {
+        self.busy_policy = policy;
+    }
+
+    pub fn dmi_read(&mut self, addr: u32) -> Result<u32, JtagError> {
+        self.dmi_access(addr, 0, DMI_OP_READ)
+    }
+
+    pub fn dmi_write(&mut self, addr: u32, value: u32) -> Result<(), JtagError> {
+        self.dmi_access(addr, value, DMI_OP_WRITE)?;
+        Ok(())
+    }
+
+    /// Clear a sticky DMI error or busy condition
+    pub fn dmi_reset(&mut self) -> Result<(), JtagError> {
+        self.probe.write_register32_void(DTMCS, Dtmcs::dmireset())
+    }
+
+    /// Read `words` 32-bit words from the system bus starting at `base`
+    ///
+    /// `sbaddress0` is written once, then every `sbdata0` read fetches the
+    /// next word through `sbautoincrement`, which is turned off again
+    /// afterwards. A bus error stops the read and reports the byte offset of
+    /// the failing word.
+    pub fn read_memory_block32(&mut self, base: u32, words: usize) -> Result<Vec<u32>, JtagError> {
+        if base & 3 != 0 {
+            return Err(JtagError::InvalidInput(
+                "system bus address must be 32-bit aligned",
+            ));
+        }
+        if u64::from(base) + 4 * words as u64 > 1 << 32 {
+            return Err(JtagError::InvalidInput(
+                "block runs past the end of the address space",
+            ));
+        }
+        if words == 0 {
+            return Ok(vec![]);
+        }
+
+        let sbcs = SBCS_SBACCESS32 | SBCS_SBREADONADDR | SBCS_SBAUTOINCREMENT | SBCS_SBREADONDATA;
+        // Also clears the sticky errors of earlier accesses
+        self.dmi_write(SBCS, sbcs | SBCS_SBBUSYERROR | SBCS_SBERROR)?;
+        let r = self.read_autoincrement(base, words, sbcs);
+        self.dmi_write(SBCS, SBCS_SBACCESS32 | SBCS_SBBUSYERROR | SBCS_SBERROR)?;
+        r
+    }
+
+    /// Body of `read_memory_block32`, with `sbcs` set up for autoincrement
+    fn read_autoincrement(
+        &mut self,
+        base: u32,
+        words: usize,
+        sbcs: u32,
+    ) -> Result<Vec<u32>, JtagError> {
+        self.dmi_write(SBADDRESS0, base)?;
+        let mut data = Vec::with_capacity(words);
+        for i in 0..words {
+            self.wait_system_bus(i as u32 * 4)?;
+            if i + 1 == words {
+                // Don't fetch past the end of the block
+                self.dmi_write(SBCS, sbcs & !SBCS_SBREADONDATA)?;
+            }
+            data.push(self.dmi_read(SBDATA0)?);
+        }
+        Ok(data)
+    }
+
+    /// Poll `sbcs` until the bus read of the word at `offset` is done
+    fn wait_system_bus(&mut self, offset: u32) -> Result<(), JtagError> {
+        for _ in 0..MAX_BUSY_RETRIES {
+            let sbcs = self.dmi_read(SBCS)?;
+            let sberror = ((sbcs & SBCS_SBERROR) >> 12) as u8;
+            if sberror != 0 {
+                return Err(JtagError::SystemBusFailed { offset, sberror });
+            }
+            if sbcs & SBCS_SBBUSY == 0 {
+                return Ok(());
+            }
+        }
+        Err(JtagError::SystemBusBusy { offset })
+    }
+
+    /// Issue an operation, then a NOP scan collecting its status and data
+    ///
+    /// A busy status means the operation was dropped: the sticky condition
+    /// is cleared, the idle cycles raised and the operation issued again.
+    /// With the `StatusRegister` policy, the access waits for
+    /// `abstractcs.busy` to clear before it's issued again instead of
+    /// raising the idle cycles.
+    fn dmi_access(&mut self, addr: u32, data: u32, op: u8) -> Result<u32, JtagError> {
+        if u64::from(addr) >> self.abits != 0 {
+            return Err(JtagError::InvalidInput("DMI address wider than abits"));
+        }
+        if self.busy_policy != DmiBusyPolicy::OpField && addr != ABSTRACTCS {
+            self.wait_abstract_command(addr)?;
+        }
+
+        for _ in 0..MAX_BUSY_RETRIES {
+            self.dmi_scan(addr, data, op)?;
+            let (status, value) = self.dmi_scan(0, 0, DMI_OP_NOP)?;
+            match status {
+                DMI_STATUS_SUCCESS => return Ok(value),
+                DMI_STATUS_BUSY if self.busy_policy == DmiBusyPolicy::StatusRegister => {
+                    self.dmi_reset()?;
+                    log::debug!("DMI busy at {:#x}, waiting for abstractcs", addr);
+                    if addr != ABSTRACTCS {
+                        self.wait_abstract_command(addr)?;
+                    }
+                }
+                DMI_STATUS_FAILED => {
+                    self.dmi_reset()?;
+                    return Err(JtagError::DmiFailed { addr });
+                }
+                DMI_STATUS_BUSY => {
+                    self.dmi_reset()?;
+                    self.idle_cycles = self.idle_cycles.saturating_add(1);
+                    self.probe.set_idle_cycles(self.idle_cycles);
+                    log::debug!("DMI busy, idle cycles raised to {}", self.idle_cycles);
+                }
+                _ => return Err(JtagError::DmiFailed { addr }),
+            }
+        }
+        Err(JtagError::DmiBusy { addr })
+    }
+
+    /// Poll `abstractcs` until no abstract command runs, before accessing `addr`
+    ///
+    /// Every busy read raises the idle cycles, as a busy `op` would.
+    fn wait_abstract_command(&mut self, addr: u32) -> Result<(), JtagError> {
+        for _ in 0..MAX_BUSY_RETRIES {
+            if self.dmi_access(ABSTRACTCS, 0, DMI_OP_READ)? & ABSTRACTCS_BUSY == 0 {
+                return Ok(());
+            }
+            self.idle_cycles = self.idle_cycles.saturating_add(1);
+            self.probe.set_idle_cycles(self.idle_cycles);
+            log::debug!(
+                "abstractcs busy, idle cycles raised to {}",
+                self.idle_cycles
+            );
+        }
+        Err(JtagError::DmiBusy { addr })
+    }
+
+    /// Shift `address:data:op` into DMI, returning the captured `(op, data)`
+    fn dmi_scan(&mut self, addr: u32, data: u32, op: u8) -> Result<(u8, u32), JtagError> {
+        let bits = self.abits as usize + 34;
+        let value = u128::from(addr) << 34 | u128::from(data) << 2 | u128::from(op);
+        let len = (bits + 7) / 8;
+
+        let r = self
+            .probe
+            .write_register(DMI, &value.to_le_bytes()[..len], bits)?;
+
+        let mut buf = [0; 16];
+        buf[..r.len()].copy_from_slice(&r);
+        let capture = u128::from_le_bytes(buf);
+        Ok(((capture & 0x3) as u8, (capture >> 2) as u32))
+    }
+}
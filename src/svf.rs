@@ -0,0 +1,478 @@
+//! Serial Vector Format (SVF) player
+
+use bitvec::order::Lsb0;
+use bitvec::vec::BitVec;
+use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::error::JtagError;
+use crate::jtag::{bits_to_bytes, JtagAdapter};
+use crate::mpsse::MpsseIo;
+use crate::probe::FtdiProbe;
+use crate::tap::TapState;
+
+#[derive(Debug, Error)]
+pub enum SvfError {
+    #[error("JTAG error: {0}")]
+    Jtag(#[from] JtagError),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+    #[error("line {line}: unsupported command {command}")]
+    Unsupported { line: usize, command: String },
+    #[error("line {line}: TDO mismatch, expected {expected}, got {actual} (mask {mask})")]
+    TdoMismatch {
+        line: usize,
+        expected: String,
+        actual: String,
+        mask: String,
+    },
+}
+
+/// Play an SVF file, checking every masked TDO value
+///
+/// Supports SIR/SDR with their header/trailer commands, ENDIR/ENDDR,
+/// RUNTEST, STATE, FREQUENCY and TRST. TDO of headers and trailers isn't
+/// checked. The line of the first TDO mismatch is reported in the error.
+pub fn play_svf<D: MpsseIo, R: Read>(
+    probe: &mut FtdiProbe<D>,
+    mut reader: R,
+) -> Result<(), SvfError> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+
+    let mut player = Player::new(probe.adapter_mut());
+    for statement in statements(&text)? {
+        player.execute(&statement)?;
+    }
+    player.adapter.flush()?;
+    Ok(())
+}
+
+/// One `;`-terminated command with the line it starts on
+struct Statement {
+    line: usize,
+    tokens: Vec<String>,
+}
+
+/// Split the text into statements, dropping comments
+///
+/// Parenthesized hex strings become a single token without whitespace.
+fn statements(text: &str) -> Result<Vec<Statement>, SvfError> {
+    let mut statements = vec![];
+    let mut tokens: Vec<String> = vec![];
+    let mut token = String::new();
+    let mut start = None;
+    let mut in_parens = false;
+    let mut line = 1;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '!' if !in_parens => {
+                skip_line(&mut chars);
+            }
+            '/' if !in_parens && chars.peek() == Some(&'/') => {
+                skip_line(&mut chars);
+            }
+            '(' => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+                in_parens = true;
+                token.push('(');
+            }
+            ')' => {
+                in_parens = false;
+                token.push(')');
+                tokens.push(std::mem::take(&mut token));
+            }
+            ';' if !in_parens => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+                if !tokens.is_empty() {
+                    statements.push(Statement {
+                        line: start.take().unwrap_or(line),
+                        tokens: std::mem::take(&mut tokens),
+                    });
+                }
+            }
+            c if c.is_whitespace() => {
+                if !in_parens && !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => {
+                start.get_or_insert(line);
+                token.push(c.to_ascii_uppercase());
+            }
+        }
+        if c == '\n' {
+            line += 1;
+        }
+    }
+
+    if in_parens || !tokens.is_empty() || !token.is_empty() {
+        return Err(SvfError::Syntax {
+            line,
+            message: "unterminated statement at the end of the file".to_string(),
+        });
+    }
+    Ok(statements)
+}
+
+/// Drop everything up to the end of the line, keeping the newline
+fn skip_line(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    while chars.next_if(|&c| c != '\n').is_some() {}
+}
+
+/// Scan pattern of SIR/SDR or one of the header/trailer commands
+///
+/// TDI, MASK and SMASK carry over to the next command of the same kind as
+/// long as the length doesn't change, TDO is only checked when given.
+#[derive(Default)]
+struct Pattern {
+    len: usize,
+    tdi: Vec<u8>,
+    tdo: Option<Vec<u8>>,
+    mask: Vec<u8>,
+}
+
+struct Player<'a, D: MpsseIo> {
+    adapter: &'a mut JtagAdapter<D>,
+    sir: Pattern,
+    sdr: Pattern,
+    hir: Pattern,
+    hdr: Pattern,
+    tir: Pattern,
+    tdr: Pattern,
+    endir: TapState,
+    enddr: TapState,
+    run_state: TapState,
+    run_end: TapState,
+}
+
+impl<'a, D: MpsseIo> Player<'a, D> {
+    fn new(adapter: &'a mut JtagAdapter<D>) -> Self {
+        Self {
+            adapter,
+            sir: Pattern::default(),
+            sdr: Pattern::default(),
+            hir: Pattern::default(),
+            hdr: Pattern::default(),
+            tir: Pattern::default(),
+            tdr: Pattern::default(),
+            endir: TapState::RunTestIdle,
+            enddr: TapState::RunTestIdle,
+            run_state: TapState::RunTestIdle,
+            run_end: TapState::RunTestIdle,
+        }
+    }
+
+    fn execute(&mut self, statement: &Statement) -> Result<(), SvfError> {
+        let line = statement.line;
+        let tokens = &statement.tokens[..];
+        log::trace!("svf line {}: {:?}", line, tokens);
+
+        match tokens[0].as_str() {
+            "SIR" => {
+                update_pattern(&mut self.sir, line, &tokens[1..])?;
+                self.scan(line, true)
+            }
+            "SDR" => {
+                update_pattern(&mut self.sdr, line, &tokens[1..])?;
+                self.scan(line, false)
+            }
+            "HIR" => update_pattern(&mut self.hir, line, &tokens[1..]),
+            "HDR" => update_pattern(&mut self.hdr, line, &tokens[1..]),
+            "TIR" => update_pattern(&mut self.tir, line, &tokens[1..]),
+            "TDR" => update_pattern(&mut self.tdr, line, &tokens[1..]),
+            "ENDIR" => {
+                self.endir = stable_state(line, tokens.get(1))?;
+                Ok(())
+            }
+            "ENDDR" => {
+                self.enddr = stable_state(line, tokens.get(1))?;
+                Ok(())
+            }
+            "STATE" => {
+                // Explicit paths are taken as given by their final state
+                let state = stable_state(line, tokens.last().filter(|_| tokens.len() > 1))?;
+                self.adapter.goto_state(state)?;
+                Ok(())
+            }
+            "RUNTEST" => self.runtest(line, &tokens[1..]),
+            "FREQUENCY" => self.frequency(line, &tokens[1..]),
+            "TRST" => {
+                let assert = match tokens.get(1).map(String::as_str) {
+                    Some("ON") => true,
+                    Some("OFF") | Some("Z") | Some("ABSENT") => false,
+                    _ => return Err(syntax(line, "TRST needs ON, OFF, Z or ABSENT")),
+                };
+                self.adapter.set_reset(Some(assert), None)?;
+                Ok(())
+            }
+            command => Err(SvfError::Unsupported {
+                line,
+                command: command.to_string(),
+            }),
+        }
+    }
+
+    /// Shift header, pattern and trailer in one scan and check the pattern's TDO
+    fn scan(&mut self, line: usize, ir: bool) -> Result<(), SvfError> {
+        let (header, pattern, trailer, end) = if ir {
+            (&self.hir, &self.sir, &self.tir, self.endir)
+        } else {
+            (&self.hdr, &self.sdr, &self.tdr, self.enddr)
+        };
+        if pattern.len == 0 {
+            return Ok(());
+        }
+
+        // The header is shifted first, the trailer last
+        let mut data = BitVec::<Lsb0, u8>::new();
+        for part in [header, pattern, trailer].iter() {
+            let mut bits = BitVec::<Lsb0, u8>::from_slice(&part.tdi);
+            bits.truncate(part.len);
+            data.append(&mut bits);
+        }
+        let total = data.len();
+        let offset = header.len;
+        let len = pattern.len;
+        let expected = pattern.tdo.clone();
+        let mask = pattern.mask.clone();
+
+        let r = self.adapter.scan_to(ir, data.as_slice(), total, end)?;
+
+        if let Some(expected) = expected {
+            let actual = BitVec::<Lsb0, u8>::from_vec(r);
+            let actual = bits_to_bytes(&actual[offset..offset + len]);
+
+            let mismatch = (0..len).any(|i| {
+                let bit = |bytes: &[u8]| bytes[i / 8] >> (i % 8) & 1;
+                bit(&mask) == 1 && bit(&actual) != bit(&expected)
+            });
+            if mismatch {
+                return Err(SvfError::TdoMismatch {
+                    line,
+                    expected: to_hex(&expected, len),
+                    actual: to_hex(&actual, len),
+                    mask: to_hex(&mask, len),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// `RUNTEST [run_state] [count TCK|SCK] [time SEC [MAXIMUM time SEC]] [ENDSTATE end_state]`
+    fn runtest(&mut self, line: usize, mut tokens: &[String]) -> Result<(), SvfError> {
+        if let Some(name) = tokens.first().filter(|t| svf_state(t).is_some()) {
+            let state = stable_state(line, Some(name))?;
+            self.run_state = state;
+            self.run_end = state;
+            tokens = &tokens[1..];
+        }
+
+        let mut cycles = 0;
+        let mut min_time = Duration::from_secs(0);
+        while !tokens.is_empty() {
+            match tokens {
+                [count, unit, rest @ ..] if unit == "TCK" || unit == "SCK" => {
+                    let count = whole_number(line, count)?;
+                    if unit == "TCK" {
+                        cycles = count;
+                    } else {
+                        log::warn!("line {}: ignoring {} SCK cycles", line, count);
+                    }
+                    tokens = rest;
+                }
+                [time, unit, rest @ ..] if unit == "SEC" => {
+                    min_time = Duration::try_from_secs_f64(number(line, time)?)
+                        .map_err(|_| syntax(line, &format!("invalid time {}", time)))?;
+                    tokens = rest;
+                }
+                [maximum, _, unit, rest @ ..] if maximum == "MAXIMUM" && unit == "SEC" => {
+                    tokens = rest;
+                }
+                [endstate, state, rest @ ..] if endstate == "ENDSTATE" => {
+                    self.run_end = stable_state(line, Some(state))?;
+                    tokens = rest;
+                }
+                _ => return Err(syntax(line, "malformed RUNTEST")),
+            }
+        }
+
+        self.adapter.goto_state(self.run_state)?;
+        // Every stable state but TEST-LOGIC-RESET is held with TMS low
+        let tms = self.run_state == TapState::TestLogicReset;
+        self.adapter.clock_tms(tms, cycles)?;
+        if min_time > Duration::from_secs(0) {
+            // Let the clocks finish before starting the wait
+            self.adapter.sync()?;
+            thread::sleep(min_time);
+        }
+        self.adapter.goto_state(self.run_end)?;
+        Ok(())
+    }
+
+    /// `FREQUENCY [cycles HZ]`, an upper bound for TCK
+    fn frequency(&mut self, line: usize, tokens: &[String]) -> Result<(), SvfError> {
+        let hz = match tokens {
+            [] => return Ok(()),
+            [hz, unit] if unit == "HZ" => number(line, hz)? as u32,
+            _ => return Err(syntax(line, "malformed FREQUENCY")),
+        };
+        match self.adapter.set_clock(hz) {
+            Err(JtagError::ClockOutOfRange { max, .. }) if hz > max => {
+                self.adapter.set_clock(max)?;
+            }
+            result => {
+                result?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Apply `length [TDI (..)] [TDO (..)] [MASK (..)] [SMASK (..)]` to a pattern
+fn update_pattern(pattern: &mut Pattern, line: usize, tokens: &[String]) -> Result<(), SvfError> {
+    let len = tokens
+        .first()
+        .ok_or_else(|| syntax(line, "missing scan length"))?;
+    let len = whole_number(line, len)?;
+    let bytes = (len + 7) / 8;
+
+    let mut tdi = None;
+    let mut tdo = None;
+    let mut mask = None;
+    for pair in tokens[1..].chunks(2) {
+        let value = match pair {
+            [_, value] => hex(line, value, len)?,
+            _ => return Err(syntax(line, "missing scan data")),
+        };
+        match pair[0].as_str() {
+            "TDI" => tdi = Some(value),
+            "TDO" => tdo = Some(value),
+            "MASK" => mask = Some(value),
+            // Only selects which TDI bits matter, all of them are driven anyway
+            "SMASK" => {}
+            key => return Err(syntax(line, &format!("unknown scan field {}", key))),
+        }
+    }
+
+    if len != pattern.len {
+        if tdi.is_none() && len > 0 {
+            return Err(syntax(line, "TDI required when the scan length changes"));
+        }
+        pattern.len = len;
+        pattern.tdi = vec![0; bytes];
+        pattern.mask = vec![0xff; bytes];
+    }
+    if let Some(tdi) = tdi {
+        pattern.tdi = tdi;
+    }
+    if let Some(mask) = mask {
+        pattern.mask = mask;
+    }
+    pattern.tdo = tdo;
+    Ok(())
+}
+
+/// Parse `(hex)`, MSB first as written, into LSB-first bytes covering `bits`
+fn hex(line: usize, token: &str, bits: usize) -> Result<Vec<u8>, SvfError> {
+    let digits = token
+        .strip_prefix('(')
+        .and_then(|t| t.strip_suffix(')'))
+        .ok_or_else(|| syntax(line, "scan data must be in parentheses"))?;
+
+    let mut bytes = vec![0; (bits + 7) / 8];
+    for (i, c) in digits.chars().rev().enumerate() {
+        let nibble = c
+            .to_digit(16)
+            .ok_or_else(|| syntax(line, "invalid hex digit"))? as u8;
+        match bytes.get_mut(i / 2) {
+            Some(byte) => *byte |= nibble << (4 * (i % 2)),
+            None if nibble == 0 => {}
+            None => return Err(syntax(line, "scan data longer than the scan")),
+        }
+    }
+    Ok(bytes)
+}
+
+/// Format LSB-first bytes as SVF-style hex, MSB first
+fn to_hex(bytes: &[u8], bits: usize) -> String {
+    let digits = (bits + 3) / 4;
+    (0..digits)
+        .rev()
+        .map(|i| format!("{:X}", bytes[i / 2] >> (4 * (i % 2)) & 0xf))
+        .collect()
+}
+
+/// Parse a finite, non-negative number, written as an integer or a float
+fn number(line: usize, token: &str) -> Result<f64, SvfError> {
+    token
+        .parse()
+        .ok()
+        .filter(|n: &f64| n.is_finite() && *n >= 0.0)
+        .ok_or_else(|| syntax(line, &format!("invalid number {}", token)))
+}
+
+/// Parse a whole number of bits or clocks, like `1.0E2`
+fn whole_number(line: usize, token: &str) -> Result<usize, SvfError> {
+    let n = number(line, token)?;
+    if n.fract() != 0.0 || n > f64::from(u32::MAX) {
+        return Err(syntax(line, &format!("invalid count {}", token)));
+    }
+    Ok(n as usize)
+}
+
+fn syntax(line: usize, message: &str) -> SvfError {
+    SvfError::Syntax {
+        line,
+        message: message.to_string(),
+    }
+}
+
+fn svf_state(name: &str) -> Option<TapState> {
+    let state = match name {
+        "RESET" => TapState::TestLogicReset,
+        "IDLE" => TapState::RunTestIdle,
+        "DRSELECT" => TapState::SelectDrScan,
+        "DRCAPTURE" => TapState::CaptureDr,
+        "DRSHIFT" => TapState::ShiftDr,
+        "DREXIT1" => TapState::Exit1Dr,
+        "DRPAUSE" => TapState::PauseDr,
+        "DREXIT2" => TapState::Exit2Dr,
+        "DRUPDATE" => TapState::UpdateDr,
+        "IRSELECT" => TapState::SelectIrScan,
+        "IRCAPTURE" => TapState::CaptureIr,
+        "IRSHIFT" => TapState::ShiftIr,
+        "IREXIT1" => TapState::Exit1Ir,
+        "IRPAUSE" => TapState::PauseIr,
+        "IREXIT2" => TapState::Exit2Ir,
+        "IRUPDATE" => TapState::UpdateIr,
+        _ => return None,
+    };
+    Some(state)
+}
+
+fn tap_state(line: usize, name: Option<&String>) -> Result<TapState, SvfError> {
+    name.and_then(|name| svf_state(name))
+        .ok_or_else(|| syntax(line, "expected a TAP state"))
+}
+
+/// States SVF allows to end scans and RUNTEST in
+fn stable_state(line: usize, name: Option<&String>) -> Result<TapState, SvfError> {
+    match tap_state(line, name)? {
+        state @ TapState::TestLogicReset
+        | state @ TapState::RunTestIdle
+        | state @ TapState::PauseDr
+        | state @ TapState::PauseIr => Ok(state),
+        _ => Err(syntax(line, "expected IDLE, RESET, DRPAUSE or IRPAUSE")),
+    }
+}
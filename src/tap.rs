@@ -0,0 +1,107 @@
+//! TAP controller state machine
+
+/// States of the IEEE 1149.1 TAP controller
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TapState {
+    TestLogicReset,
+    RunTestIdle,
+    SelectDrScan,
+    CaptureDr,
+    ShiftDr,
+    Exit1Dr,
+    PauseDr,
+    Exit2Dr,
+    UpdateDr,
+    SelectIrScan,
+    CaptureIr,
+    ShiftIr,
+    Exit1Ir,
+    PauseIr,
+    Exit2Ir,
+    UpdateIr,
+}
+
+impl TapState {
+    const ALL: [TapState; 16] = [
+        TapState::TestLogicReset,
+        TapState::RunTestIdle,
+        TapState::SelectDrScan,
+        TapState::CaptureDr,
+        TapState::ShiftDr,
+        TapState::Exit1Dr,
+        TapState::PauseDr,
+        TapState::Exit2Dr,
+        TapState::UpdateDr,
+        TapState::SelectIrScan,
+        TapState::CaptureIr,
+        TapState::ShiftIr,
+        TapState::Exit1Ir,
+        TapState::PauseIr,
+        TapState::Exit2Ir,
+        TapState::UpdateIr,
+    ];
+
+    /// State after one TCK with the given TMS level
+    pub(crate) fn next(self, tms: bool) -> TapState {
+        use TapState::*;
+
+        match (self, tms) {
+            (TestLogicReset, false) => RunTestIdle,
+            (TestLogicReset, true) => TestLogicReset,
+            (RunTestIdle, false) => RunTestIdle,
+            (RunTestIdle, true) => SelectDrScan,
+
+            (SelectDrScan, false) => CaptureDr,
+            (SelectDrScan, true) => SelectIrScan,
+            (CaptureDr, false) | (ShiftDr, false) | (Exit2Dr, false) => ShiftDr,
+            (CaptureDr, true) | (ShiftDr, true) => Exit1Dr,
+            (Exit1Dr, false) | (PauseDr, false) => PauseDr,
+            (Exit1Dr, true) | (Exit2Dr, true) => UpdateDr,
+            (PauseDr, true) => Exit2Dr,
+            (UpdateDr, false) | (UpdateIr, false) => RunTestIdle,
+            (UpdateDr, true) | (UpdateIr, true) => SelectDrScan,
+
+            (SelectIrScan, false) => CaptureIr,
+            (SelectIrScan, true) => TestLogicReset,
+            (CaptureIr, false) | (ShiftIr, false) | (Exit2Ir, false) => ShiftIr,
+            (CaptureIr, true) | (ShiftIr, true) => Exit1Ir,
+            (Exit1Ir, false) | (PauseIr, false) => PauseIr,
+            (Exit1Ir, true) | (Exit2Ir, true) => UpdateIr,
+            (PauseIr, true) => Exit2Ir,
+        }
+    }
+
+    /// Shortest TMS sequence (LSB first, length in bits) leading to `target`
+    ///
+    /// A path never goes through a Pause state it doesn't end in, and never
+    /// resumes a shift through EXIT2. From PAUSE-xR, a scan starts over
+    /// through EXIT2, UPDATE, SELECT and CAPTURE, as in SVF, so the previous
+    /// scan is updated and the next one captures. `None` if `target` can
+    /// only be reached through another Pause state, like EXIT2-xR from
+    /// anywhere but PAUSE-xR.
+    pub(crate) fn path_to(self, target: TapState) -> Option<(u32, usize)> {
+        let index = |state: TapState| Self::ALL.iter().position(|&s| s == state).unwrap();
+
+        let mut visited = [false; 16];
+        let mut queue = std::collections::VecDeque::new();
+        visited[index(self)] = true;
+        queue.push_back((self, 0u32, 0usize));
+
+        while let Some((state, tms, len)) = queue.pop_front() {
+            if state == target {
+                return Some((tms, len));
+            }
+            for &bit in [false, true].iter() {
+                let next = state.next(bit);
+                let pause = next == TapState::PauseDr || next == TapState::PauseIr;
+                let resume = !bit && (state == TapState::Exit2Dr || state == TapState::Exit2Ir);
+                if visited[index(next)] || (pause && next != target) || resume {
+                    continue;
+                }
+                visited[index(next)] = true;
+                queue.push_back((next, tms | (bit as u32) << len, len + 1));
+            }
+        }
+        None
+    }
+}
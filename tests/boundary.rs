@@ -0,0 +1,157 @@
+//! Boundary-scan interconnect tests on a mock board
+
+mod common;
+
+use std::cell::RefCell;
+
+use bitvec::prelude::*;
+use common::SharedMock;
+use ftdi_playground::{
+    BoundaryCell, FtdiProbe, InterconnectConfig, InterconnectDevice, JtagError, MockTap, Net,
+    NetFailure, NetFault,
+};
+
+const EXTEST: u32 = 0x0;
+const SAMPLE: u32 = 0x2;
+
+/// Three nets from cells 0-2 of the driver, enabled by cell 3 when low, to
+/// cells 4-6 of the receiver. An undriven net is pulled up.
+#[derive(Debug, Default)]
+struct Board {
+    /// SAMPLE/PRELOAD registers as last seen, by chain position
+    preloaded: [Vec<u8>; 3],
+    open: Option<usize>,
+    /// Two nets shorted together, the low one wins
+    short: Option<(usize, usize)>,
+}
+
+impl Board {
+    /// Latch preloads, then settle the pins driven by the driver's EXTEST register
+    fn settle(&mut self, jtag: &SharedMock) {
+        // SAMPLE/PRELOAD and EXTEST share the update latches
+        for &tap in [0, 2].iter() {
+            let preload = jtag.register(tap, SAMPLE).unwrap();
+            if preload != self.preloaded[tap] {
+                jtag.set_register(tap, EXTEST, &preload);
+                self.preloaded[tap] = preload;
+            }
+        }
+
+        let driver = jtag.register(0, EXTEST).unwrap()[0];
+        let enabled = driver & 0x08 == 0;
+        let mut pins: Vec<bool> = (0..3).map(|n| !enabled || driver >> n & 1 != 0).collect();
+        if let Some((a, b)) = self.short {
+            let level = pins[a] && pins[b];
+            pins[a] = level;
+            pins[b] = level;
+        }
+        if let Some(net) = self.open {
+            pins[net] = true;
+        }
+
+        let mut receiver = jtag.register(2, EXTEST).unwrap()[0] & !0x70;
+        for (n, &pin) in pins.iter().enumerate() {
+            receiver |= (pin as u8) << (4 + n);
+        }
+        jtag.set_register(2, EXTEST, &[receiver]);
+    }
+}
+
+/// Driver, a BYPASS-only TAP and receiver, wired up as `board`, with the chain scanned
+fn board(board: Board) -> (FtdiProbe<SharedMock>, SharedMock) {
+    let jtag = SharedMock::new(vec![
+        MockTap::new(Some(0x1234_5001), 4)
+            .unwrap()
+            .with_register(EXTEST, 6)
+            .unwrap()
+            .with_register(SAMPLE, 6)
+            .unwrap(),
+        MockTap::new(None, 3).unwrap(),
+        MockTap::new(Some(0x4321_0003), 5)
+            .unwrap()
+            .with_register(EXTEST, 8)
+            .unwrap()
+            .with_register(SAMPLE, 8)
+            .unwrap(),
+    ]);
+    let board = RefCell::new(board);
+    jtag.between_commands(move |jtag| board.borrow_mut().settle(jtag));
+
+    let mut probe = FtdiProbe::from_io(jtag.clone());
+    probe.attach().unwrap();
+    probe.scan_chain().unwrap();
+    (probe, jtag)
+}
+
+fn config() -> InterconnectConfig {
+    let device = |tap, bsr_len, safe| InterconnectDevice {
+        tap,
+        bsr_len,
+        extest: EXTEST,
+        sample: SAMPLE,
+        safe: BitVec::from_vec(vec![safe]),
+    };
+    // Outputs disabled on the driver
+    let mut driver = device(0, 6, 0x08);
+    driver.safe.truncate(6);
+    InterconnectConfig {
+        devices: vec![driver, device(2, 8, 0x00)],
+        nets: (0..3)
+            .map(|n| Net {
+                driver: BoundaryCell { device: 0, cell: n },
+                enable: Some((BoundaryCell { device: 0, cell: 3 }, false)),
+                receivers: vec![BoundaryCell {
+                    device: 1,
+                    cell: 4 + n,
+                }],
+            })
+            .collect(),
+    }
+}
+
+#[test]
+fn interconnect_passes_on_a_good_board() {
+    let (mut probe, jtag) = board(Board::default());
+
+    let report = probe.interconnect_test(&config()).unwrap();
+    assert!(report.passed(), "{:?}", report);
+    // Left driving the safe values
+    assert_eq!(jtag.register(0, EXTEST).unwrap(), [0x08]);
+}
+
+#[test]
+fn interconnect_finds_opens_and_shorts() {
+    let (mut probe, _) = board(Board {
+        open: Some(1),
+        short: Some((0, 2)),
+        ..Board::default()
+    });
+
+    let report = probe.interconnect_test(&config()).unwrap();
+    let failure = |net, fault| NetFailure {
+        net,
+        receiver: BoundaryCell {
+            device: 1,
+            cell: 4 + net,
+        },
+        fault,
+    };
+    assert_eq!(
+        report.failures,
+        [
+            failure(0, NetFault::Short { net: 2 }),
+            failure(1, NetFault::StuckAt(true)),
+            failure(2, NetFault::Short { net: 0 }),
+        ]
+    );
+}
+
+#[test]
+fn interconnect_rejects_cells_outside_the_register() {
+    let (mut probe, _) = board(Board::default());
+
+    let mut config = config();
+    config.nets[0].receivers[0].cell = 8;
+    let r = probe.interconnect_test(&config);
+    assert!(matches!(r, Err(JtagError::InvalidInput(_))), "{:?}", r);
+}
@@ -0,0 +1,422 @@
+//! Chain scanning, target selection and register transfers on mock chains
+
+mod common;
+
+use std::time::Duration;
+
+use common::{attach, three_taps};
+use ftdi_playground::{JtagAdapter, JtagError, MockJtag, MockTap, TapState};
+
+#[test]
+fn scan_reports_every_tap() {
+    let (mut probe, _) = attach(three_taps().unwrap());
+
+    let chain = probe.scan_chain().unwrap();
+    let found: Vec<_> = chain.iter().map(|tap| (tap.idcode, tap.irlen)).collect();
+    assert_eq!(found, [(0x1000563d, 5), (0, 4), (0x4ba00477, 4)]);
+}
+
+#[test]
+fn scan_finds_all_of_twelve_taps() {
+    // Alternating IDCODE and BYPASS-only TAPs with IRs of 2 to 13 bits
+    let taps = (0..12)
+        .map(|i| {
+            let idcode = if i % 2 == 0 {
+                Some(0x0000_1001 | i << 12)
+            } else {
+                None
+            };
+            MockTap::new(idcode, i as usize + 2)
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let (mut probe, _) = attach(taps);
+
+    let chain = probe.scan_chain().unwrap();
+    assert_eq!(chain.len(), 12);
+    for (i, tap) in chain.iter().enumerate() {
+        let idcode = if i % 2 == 0 {
+            0x1001 | (i as u32) << 12
+        } else {
+            0
+        };
+        assert_eq!((tap.idcode, tap.irlen), (idcode, i + 2), "tap {}", i);
+    }
+
+    probe.select_target(0x1001 | 10 << 12).unwrap();
+    let params = probe.chain_params().unwrap();
+    assert_eq!((params.irpre, params.irlen, params.irpost), (65, 12, 13));
+    assert_eq!((params.drpre, params.drpost), (10, 1));
+}
+
+#[test]
+fn expect_devices_reports_the_chain_found() {
+    let mut adapter = JtagAdapter::from_io(MockJtag::new(three_taps().unwrap()));
+    adapter.attach().unwrap();
+
+    adapter.expect_devices(3).unwrap();
+    let r = adapter.expect_devices(2);
+    assert!(
+        matches!(
+            r,
+            Err(JtagError::UnexpectedChain {
+                expected: 2,
+                found: 3
+            })
+        ),
+        "{:?}",
+        r
+    );
+}
+
+#[test]
+fn discover_instructions_skips_taps_without_idcode() {
+    let mut adapter = JtagAdapter::from_io(MockJtag::new(three_taps().unwrap()));
+    adapter.attach().unwrap();
+
+    let found = adapter.discover_instructions().unwrap();
+    let found: Vec<_> = found.iter().map(|tap| (tap.bypass, tap.idcode)).collect();
+    assert_eq!(found, [(true, Some(1)), (true, None), (true, Some(1))]);
+}
+
+#[test]
+fn truncated_scan_reply_is_a_short_read() {
+    let (mut probe, mock) = attach(three_taps().unwrap());
+    probe.set_max_devices(4);
+    probe.set_read_timeout(Duration::from_millis(1));
+
+    mock.limit_reads(10);
+    let r = probe.scan_chain();
+    assert!(
+        matches!(r, Err(JtagError::ShortRead { got: 10, .. })),
+        "{:?}",
+        r
+    );
+}
+
+#[test]
+fn timed_out_transfer_retries_at_lower_clock() {
+    let (mut probe, mock) = attach(three_taps().unwrap());
+    probe.set_read_timeout(Duration::from_millis(1));
+    probe.select_target(0x1000563d).unwrap();
+    let hz = probe.clock_hz();
+
+    mock.lose_reads(2);
+    let r = probe.read_register32(0x1);
+    assert!(matches!(r, Err(JtagError::Timeout { .. })), "{:?}", r);
+    probe.sync().unwrap();
+
+    probe.set_auto_clock_fallback(true);
+    mock.take_written();
+    mock.lose_reads(2);
+    assert_eq!(probe.read_register32(0x1).unwrap(), 0x1000563d);
+    assert_eq!(probe.clock_hz(), hz);
+
+    // Retried at half the clock, then set back
+    probe.flush().unwrap();
+    let written = mock.take_written();
+    let position = |hz: u32| {
+        let divisor = (30_000_000 / hz - 1) as u16;
+        let [low, high] = divisor.to_le_bytes();
+        written.windows(3).position(|w| w == [0x86, low, high])
+    };
+    assert!(position(hz / 2).unwrap() < position(hz).unwrap());
+}
+
+#[test]
+fn select_target_computes_chain_params() {
+    let (mut probe, _) = attach(three_taps().unwrap());
+
+    probe.select_target(0x4ba00477).unwrap();
+    let params = probe.chain_params().unwrap();
+    assert_eq!((params.irpre, params.irlen, params.irpost), (9, 4, 0));
+    assert_eq!((params.drpre, params.drpost), (2, 0));
+
+    probe.select_target(0x1000563d).unwrap();
+    let params = probe.chain_params().unwrap();
+    assert_eq!((params.irpre, params.irlen, params.irpost), (0, 5, 8));
+    assert_eq!((params.drpre, params.drpost), (0, 2));
+}
+
+#[test]
+fn select_target_rejects_unknown_idcode() {
+    let (mut probe, _) = attach(three_taps().unwrap());
+
+    let r = probe.select_target(0x12345678);
+    assert!(matches!(r, Err(JtagError::TargetNotFound)), "{:?}", r);
+}
+
+#[test]
+fn read_register32_reads_idcode() {
+    let (mut probe, _) = attach(three_taps().unwrap());
+
+    probe.select_target(0x4ba00477).unwrap();
+    assert_eq!(probe.read_register32(0x1).unwrap(), 0x4ba00477);
+    probe.select_target(0x1000563d).unwrap();
+    assert_eq!(probe.read_register32(0x1).unwrap(), 0x1000563d);
+}
+
+#[test]
+fn register_transfers_reach_the_selected_tap() {
+    let (mut probe, mock) = attach(three_taps().unwrap());
+
+    probe.select_target(0x1000563d).unwrap();
+    let dmi = [0x01, 0x02, 0x03, 0x04, 0x05, 0x01];
+    probe.write_register(0x11, &dmi, 41).unwrap();
+    assert_eq!(mock.register(0, 0x11).unwrap(), dmi);
+
+    probe.select_target(0x4ba00477).unwrap();
+    let dpacc = [0xff, 0xee, 0xdd, 0xcc, 0x07];
+    probe.write_register(0xa, &dpacc, 35).unwrap();
+    assert_eq!(mock.register(2, 0xa).unwrap(), dpacc);
+    // The DTM kept its value
+    assert_eq!(mock.register(0, 0x11).unwrap(), dmi);
+
+    assert_eq!(probe.read_register(0xa, 35).unwrap(), dpacc);
+}
+
+#[test]
+fn read_registers_matches_individual_reads() {
+    let (mut probe, mock) = attach(three_taps().unwrap());
+    probe.select_target(0x1000563d).unwrap();
+    let load = || {
+        mock.set_register(0, 0x10, &[0x78, 0x56, 0x34, 0x12]);
+        mock.set_register(0, 0x11, &[0x01, 0x02, 0x03, 0x04, 0x05, 0x01]);
+    };
+    // Reads shift in zeros, so the repeated read sees what the first one left
+    let regs = [(0x10, 32), (0x11, 41), (0x11, 41), (0x10, 32)];
+
+    load();
+    let single: Vec<_> = regs
+        .iter()
+        .map(|&(address, len)| probe.read_register(address, len).unwrap())
+        .collect();
+    probe.flush().unwrap();
+    let single_written = mock.take_written();
+
+    load();
+    let batched = probe.read_registers(&regs).unwrap();
+    probe.flush().unwrap();
+    let batched_written = mock.take_written();
+
+    assert_eq!(batched, single);
+    assert_eq!(batched[1], [0x01, 0x02, 0x03, 0x04, 0x05, 0x01]);
+    assert_eq!(batched[2], [0; 6]);
+
+    // Repeating the address skips the IR shift a read on its own needs
+    load();
+    probe.read_registers(&regs[1..3]).unwrap();
+    probe.flush().unwrap();
+    let repeated = mock.take_written().len();
+    load();
+    probe.read_registers(&regs[1..2]).unwrap();
+    probe.flush().unwrap();
+    let first = mock.take_written().len();
+    probe.read_registers(&regs[2..3]).unwrap();
+    probe.flush().unwrap();
+    let second = mock.take_written().len();
+    assert!(repeated - first < second);
+    assert!(batched_written.len() < single_written.len());
+}
+
+#[test]
+fn write_register_returns_previous_value() {
+    let (mut probe, mock) = attach(three_taps().unwrap());
+    mock.set_register(0, 0x10, &[0x78, 0x56, 0x34, 0x12]);
+
+    probe.select_target(0x1000563d).unwrap();
+    let old = probe.exchange_register32(0x10, 0xcafef00d).unwrap();
+    assert_eq!(old, 0x12345678);
+    assert_eq!(mock.register(0, 0x10).unwrap(), [0x0d, 0xf0, 0xfe, 0xca]);
+}
+
+#[test]
+fn parked_scans_update_when_the_next_one_starts() {
+    let (mut probe, mock) = attach(three_taps().unwrap());
+    mock.set_register(0, 0x10, &[0x78, 0x56, 0x34, 0x12]);
+    probe.select_target(0x1000563d).unwrap();
+
+    let r = probe.set_park_state(TapState::ShiftDr);
+    assert!(matches!(r, Err(JtagError::InvalidInput(_))), "{:?}", r);
+    probe.set_park_state(TapState::PauseDr).unwrap();
+
+    let old = probe.exchange_register32(0x10, 0xcafef00d).unwrap();
+    assert_eq!(old, 0x12345678);
+    // Still in PAUSE-DR, UPDATE-DR hasn't happened yet
+    assert_eq!(mock.register(0, 0x10).unwrap(), [0x78, 0x56, 0x34, 0x12]);
+
+    // The IR scan goes through UPDATE-DR before anything is captured
+    assert_eq!(probe.read_register32(0x10).unwrap(), 0xcafef00d);
+    assert_eq!(mock.register(0, 0x10).unwrap(), [0x0d, 0xf0, 0xfe, 0xca]);
+}
+
+#[test]
+fn state_path_follows_each_operation() {
+    use TapState::*;
+
+    let mut adapter = JtagAdapter::from_io(MockJtag::new(three_taps().unwrap()));
+    adapter.attach().unwrap();
+    adapter.reset().unwrap();
+    assert_eq!(adapter.last_state_path(), []);
+
+    adapter.set_record_state_path(true);
+    adapter.reset().unwrap();
+    assert_eq!(adapter.last_state_path(), [TestLogicReset, RunTestIdle]);
+
+    adapter.transfer_dr(&[0; 2], 16).unwrap();
+    let dr_scan = [
+        RunTestIdle,
+        SelectDrScan,
+        CaptureDr,
+        ShiftDr,
+        Exit1Dr,
+        UpdateDr,
+        RunTestIdle,
+    ];
+    assert_eq!(adapter.last_state_path(), dr_scan);
+
+    adapter.set_park_state(PauseIr).unwrap();
+    adapter.shift_ir(&[0xff; 2], 13).unwrap();
+    let ir_scan = [
+        RunTestIdle,
+        SelectDrScan,
+        SelectIrScan,
+        CaptureIr,
+        ShiftIr,
+        Exit1Ir,
+        PauseIr,
+    ];
+    assert_eq!(adapter.last_state_path(), ir_scan);
+
+    adapter.idle(10).unwrap();
+    assert_eq!(
+        adapter.last_state_path(),
+        [PauseIr, Exit2Ir, UpdateIr, RunTestIdle]
+    );
+
+    adapter.sequence().dr_write(&[0; 2], 16).run().unwrap();
+    assert_eq!(
+        adapter.last_state_path(),
+        [
+            RunTestIdle,
+            SelectDrScan,
+            CaptureDr,
+            ShiftDr,
+            Exit1Dr,
+            UpdateDr,
+            SelectDrScan,
+            SelectIrScan,
+            CaptureIr,
+            Exit1Ir,
+            PauseIr
+        ]
+    );
+}
+
+#[test]
+fn bypass_only_tap_selected_by_index() {
+    let (mut probe, mock) = attach(three_taps().unwrap());
+
+    probe.select_target_by_index(1).unwrap();
+    probe.write_register(0x2, &[0x55], 7).unwrap();
+    assert_eq!(mock.register(1, 0x2).unwrap(), [0x55]);
+    assert_eq!(probe.read_register(0x2, 7).unwrap(), [0x55]);
+}
+
+#[test]
+fn last_tap_with_odd_ir_capture_gets_the_rest_of_the_total() {
+    // The ARM DAP captures 0b0101, which parses as two 2-bit IRs
+    let mut taps = three_taps().unwrap();
+    taps[2] = MockTap::new(Some(0x4ba00477), 4)
+        .and_then(|tap| tap.with_ir_capture(0b0101))
+        .and_then(|tap| tap.with_register(0xa, 35))
+        .unwrap();
+    let (mut probe, mock) = attach(taps);
+
+    let chain = probe.scan_chain().unwrap();
+    let irlens: Vec<_> = chain.iter().map(|tap| tap.irlen).collect();
+    assert_eq!(irlens, [5, 4, 4]);
+
+    probe.select_target(0x4ba00477).unwrap();
+    probe
+        .write_register(0xa, &[0x21, 0x43, 0x65, 0x87, 0x05], 35)
+        .unwrap();
+    assert_eq!(
+        mock.register(2, 0xa).unwrap(),
+        [0x21, 0x43, 0x65, 0x87, 0x05]
+    );
+}
+
+#[test]
+fn odd_ir_capture_before_the_last_tap_fails_the_scan() {
+    let mut taps = three_taps().unwrap();
+    taps[1] = MockTap::new(None, 4)
+        .and_then(|tap| tap.with_ir_capture(0b0000))
+        .unwrap();
+    let (mut probe, _) = attach(taps);
+
+    assert!(matches!(
+        probe.scan_chain(),
+        Err(JtagError::InvalidChain(_))
+    ));
+}
+
+#[test]
+fn mock_tap_rejects_bad_lengths() {
+    assert!(matches!(
+        MockTap::new(None, 1),
+        Err(JtagError::InvalidInput(_))
+    ));
+    assert!(matches!(
+        MockTap::new(None, 4).and_then(|tap| tap.with_register(0x2, 0)),
+        Err(JtagError::InvalidInput(_))
+    ));
+    assert!(matches!(
+        MockTap::new(None, 4).and_then(|tap| tap.with_ir_capture(0x10)),
+        Err(JtagError::InvalidInput(_))
+    ));
+}
+
+/// `n` bits of a fixed pseudo-random pattern, LSB first
+fn pattern(n: usize, seed: u32) -> Vec<u8> {
+    let mut x = seed;
+    let mut bytes: Vec<u8> = (0..n)
+        .step_by(8)
+        .map(|_| {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (x >> 16) as u8
+        })
+        .collect();
+    let rest = n % 8;
+    if rest > 0 {
+        *bytes.last_mut().unwrap() &= (1 << rest) - 1;
+    }
+    bytes
+}
+
+#[test]
+fn transfers_of_1_to_70_bits_between_bypassed_taps() {
+    for n in 1..=70 {
+        let taps = vec![
+            MockTap::new(Some(0x1000563d), 5).unwrap(),
+            MockTap::new(None, 4)
+                .and_then(|tap| tap.with_register(0x2, n))
+                .unwrap(),
+            MockTap::new(Some(0x4ba00477), 4).unwrap(),
+        ];
+        let (mut probe, mock) = attach(taps);
+        let old = pattern(n, 1);
+        let new = pattern(n, 2);
+        mock.set_register(1, 0x2, &old);
+
+        probe.select_target_by_index(1).unwrap();
+        assert_eq!(
+            probe.write_register(0x2, &new, n).unwrap(),
+            old,
+            "{} bits",
+            n
+        );
+        assert_eq!(mock.register(1, 0x2).unwrap(), new, "{} bits", n);
+        assert_eq!(probe.read_register(0x2, n).unwrap(), new, "{} bits", n);
+    }
+}
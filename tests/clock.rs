@@ -0,0 +1,43 @@
+//! TCK programming on mock chips
+
+mod common;
+
+use common::{attach, three_taps};
+use ftdi_playground::{ftdi, FtdiProbe, JtagError, MockJtag};
+
+#[test]
+fn capped_clock_never_exceeds_the_cap() {
+    let (mut probe, _) = attach(three_taps().unwrap());
+
+    assert_eq!(probe.set_clock_capped(10_000_000).unwrap(), 10_000_000);
+    // 30 MHz / 5, the next divisor would give 7.5 MHz
+    assert_eq!(probe.set_clock_capped(7_000_000).unwrap(), 6_000_000);
+    assert_eq!(probe.clock_hz(), 6_000_000);
+    assert_eq!(probe.speed_khz(), 6_000);
+
+    // Anything above the fastest clock gets the fastest clock
+    assert_eq!(probe.set_clock_capped(100_000_000).unwrap(), 30_000_000);
+    let r = probe.set_clock(100_000_000);
+    assert!(
+        matches!(r, Err(JtagError::ClockOutOfRange { .. })),
+        "{:?}",
+        r
+    );
+
+    let r = probe.set_clock_capped(100);
+    assert!(
+        matches!(r, Err(JtagError::ClockOutOfRange { .. })),
+        "{:?}",
+        r
+    );
+}
+
+#[test]
+fn capped_clock_follows_the_chip() {
+    let mock = MockJtag::new(three_taps().unwrap()).with_chip_type(ftdi::ChipType::Ft2232D);
+    let mut probe = FtdiProbe::from_io(mock);
+    probe.attach().unwrap();
+
+    assert_eq!(probe.set_clock_capped(10_000_000).unwrap(), 6_000_000);
+    assert_eq!(probe.set_clock_capped(4_000_000).unwrap(), 3_000_000);
+}
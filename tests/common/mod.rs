@@ -0,0 +1,173 @@
+//! Shared fixtures for the tests running against `MockJtag`
+
+#![allow(dead_code)]
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use ftdi_playground::{ftdi, FtdiProbe, JtagError, MockJtag, MockTap, MpsseIo};
+
+type Callback = Box<dyn FnMut(&SharedMock)>;
+
+/// Callback run between the commands written to a `SharedMock`
+#[derive(Default)]
+struct BetweenCommands(Option<Callback>);
+
+impl fmt::Debug for BetweenCommands {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() { "Some(..)" } else { "None" })
+    }
+}
+
+/// `MockJtag` the test keeps a handle to, recording every byte written
+///
+/// Clones share the mock, so registers and the written stream can be
+/// inspected while a probe owns the device.
+#[derive(Clone, Debug)]
+pub struct SharedMock {
+    mock: Rc<RefCell<MockJtag>>,
+    written: Rc<RefCell<Vec<u8>>>,
+    read_limit: Rc<Cell<Option<usize>>>,
+    lost: Rc<Cell<usize>>,
+    between_commands: Rc<RefCell<BetweenCommands>>,
+}
+
+impl SharedMock {
+    pub fn new(taps: Vec<MockTap>) -> Self {
+        Self {
+            mock: Rc::new(RefCell::new(MockJtag::new(taps))),
+            written: Rc::new(RefCell::new(Vec::new())),
+            read_limit: Rc::new(Cell::new(None)),
+            lost: Rc::new(Cell::new(0)),
+            between_commands: Rc::new(RefCell::new(BetweenCommands::default())),
+        }
+    }
+
+    /// Value of the data register `ir` of TAP `tap`, LSB first
+    pub fn register(&self, tap: usize, ir: u32) -> Option<Vec<u8>> {
+        self.mock.borrow().register(tap, ir)
+    }
+
+    pub fn set_register(&self, tap: usize, ir: u32, value: &[u8]) {
+        self.mock.borrow_mut().set_register(tap, ir, value).unwrap();
+    }
+
+    /// Deliver only `bytes` more bytes of replies, then nothing
+    pub fn limit_reads(&self, bytes: usize) {
+        self.read_limit.set(Some(bytes));
+    }
+
+    /// Drop the next `bytes` bytes of replies, as if lost on the cable
+    pub fn lose_reads(&self, bytes: usize) {
+        self.lost.set(bytes);
+    }
+
+    /// Run `f` between any two MPSSE commands, to model what's wired to the chain
+    ///
+    /// Bytes are then fed to the mock one at a time, with `f` run after
+    /// each. Register changes `f` makes are captured by the next scan.
+    pub fn between_commands(&self, f: impl FnMut(&SharedMock) + 'static) {
+        self.between_commands.borrow_mut().0 = Some(Box::new(f));
+    }
+
+    /// Take the bytes written since the last call
+    pub fn take_written(&self) -> Vec<u8> {
+        self.written.borrow_mut().split_off(0)
+    }
+}
+
+impl Read for SharedMock {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = match self.read_limit.get() {
+            Some(limit) => buf.len().min(limit),
+            None => buf.len(),
+        };
+        let mut n = self.mock.borrow_mut().read(&mut buf[..len])?;
+        let lost = self.lost.get().min(n);
+        if lost > 0 {
+            buf.copy_within(lost..n, 0);
+            n -= lost;
+            self.lost.set(self.lost.get() - lost);
+        }
+        if let Some(limit) = self.read_limit.get() {
+            self.read_limit.set(Some(limit - n));
+        }
+        Ok(n)
+    }
+}
+
+impl Write for SharedMock {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut between_commands = self.between_commands.borrow_mut();
+        let n = match &mut between_commands.0 {
+            Some(f) => {
+                for byte in buf.chunks(1) {
+                    self.mock.borrow_mut().write_all(byte)?;
+                    f(self);
+                }
+                buf.len()
+            }
+            None => self.mock.borrow_mut().write(buf)?,
+        };
+        self.written.borrow_mut().extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.mock.borrow_mut().flush()
+    }
+}
+
+impl MpsseIo for SharedMock {
+    fn usb_reset(&mut self) -> Result<(), JtagError> {
+        self.mock.borrow_mut().usb_reset()
+    }
+
+    fn set_latency_timer(&mut self, ms: u8) -> Result<(), JtagError> {
+        self.mock.borrow_mut().set_latency_timer(ms)
+    }
+
+    fn enter_mpsse(&mut self, direction: u8) -> Result<(), JtagError> {
+        self.mock.borrow_mut().enter_mpsse(direction)
+    }
+
+    fn enter_bitbang(&mut self, direction: u8) -> Result<(), JtagError> {
+        self.mock.borrow_mut().enter_bitbang(direction)
+    }
+
+    fn reset_bitmode(&mut self) -> Result<(), JtagError> {
+        self.mock.borrow_mut().reset_bitmode()
+    }
+
+    fn purge(&mut self) -> Result<(), JtagError> {
+        self.mock.borrow_mut().purge()
+    }
+
+    fn chip_type(&self) -> ftdi::ChipType {
+        self.mock.borrow().chip_type()
+    }
+}
+
+/// Attached probe on a shared mock chain, with the written stream cleared
+pub fn attach(taps: Vec<MockTap>) -> (FtdiProbe<SharedMock>, SharedMock) {
+    let mock = SharedMock::new(taps);
+    let mut probe = FtdiProbe::from_io(mock.clone());
+    probe.attach().unwrap();
+    probe.flush().unwrap();
+    mock.take_written();
+    (probe, mock)
+}
+
+/// RISC-V DTM (IDCODE 0x1000563d) with `dtmcs` and `dmi`, a BYPASS-only
+/// TAP and an ARM DAP with `dpacc`, from the TDO end
+pub fn three_taps() -> Result<Vec<MockTap>, JtagError> {
+    Ok(vec![
+        MockTap::new(Some(0x1000563d), 5)?
+            .with_register(0x10, 32)?
+            .with_register(0x11, 41)?,
+        MockTap::new(None, 4)?.with_register(0x2, 7)?,
+        MockTap::new(Some(0x4ba00477), 4)?.with_register(0xa, 35)?,
+    ])
+}
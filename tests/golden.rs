@@ -0,0 +1,100 @@
+//! MPSSE streams of high-level operations against checked-in golden files
+//!
+//! After an intentional change to the command generation, run the tests
+//! with `UPDATE_GOLDEN=1` to rewrite the files and review their diff.
+
+mod common;
+
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+
+use common::{three_taps, SharedMock};
+use ftdi_playground::{FtdiProbe, JtagError};
+
+/// Records the stream of each operation under its name
+struct Recorder {
+    probe: FtdiProbe<SharedMock>,
+    mock: SharedMock,
+    stream: String,
+}
+
+impl Recorder {
+    fn new(mock: SharedMock) -> Self {
+        Self {
+            probe: FtdiProbe::from_io(mock.clone()),
+            mock,
+            stream: String::new(),
+        }
+    }
+
+    /// Run `op` and append the bytes it wrote, 16 per line
+    fn record<T, F>(&mut self, name: &str, op: F) -> T
+    where
+        F: FnOnce(&mut FtdiProbe<SharedMock>) -> Result<T, JtagError>,
+    {
+        let r = op(&mut self.probe).unwrap();
+        self.probe.flush().unwrap();
+
+        writeln!(self.stream, "# {}", name).unwrap();
+        for line in self.mock.take_written().chunks(16) {
+            let hex: Vec<_> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+            writeln!(self.stream, "{}", hex.join(" ")).unwrap();
+        }
+        r
+    }
+
+    /// Compare the recorded stream with `tests/golden/<name>`
+    fn check(&self, name: &str) {
+        let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", name]
+            .iter()
+            .collect();
+        if env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(&path, &self.stream).unwrap();
+            return;
+        }
+        let golden = fs::read_to_string(&path).unwrap();
+        if golden != self.stream {
+            let line = golden
+                .lines()
+                .zip(self.stream.lines())
+                .position(|(golden, stream)| golden != stream)
+                .unwrap_or_else(|| golden.lines().count().min(self.stream.lines().count()));
+            panic!(
+                "MPSSE stream differs from {} at line {}, rerun with UPDATE_GOLDEN=1 if intended",
+                path.display(),
+                line + 1
+            );
+        }
+    }
+}
+
+#[test]
+fn three_tap_session() {
+    let mock = SharedMock::new(three_taps().unwrap());
+    mock.set_register(0, 0x11, &[0x01, 0x02, 0x03, 0x04, 0x05, 0x01]);
+    let mut recorder = Recorder::new(mock);
+
+    recorder.record("attach", |probe| probe.attach());
+    recorder.record("scan_chain", |probe| probe.scan_chain());
+    recorder.record("select_target 0x1000563d", |probe| {
+        probe.select_target(0x1000563d)
+    });
+    recorder.record("read_register32 0x1", |probe| probe.read_register32(0x1));
+    recorder.record("read_register 0x11, 41 bits", |probe| {
+        probe.read_register(0x11, 41)
+    });
+    recorder.record("write_register32_void 0x10", |probe| {
+        probe.write_register32_void(0x10, 0x12345678)
+    });
+    recorder.record("select_target 0x4ba00477", |probe| {
+        probe.select_target(0x4ba00477)
+    });
+    recorder.record("exchange_register32 0x1", |probe| {
+        probe.exchange_register32(0x1, 0xffffffff)
+    });
+    recorder.record("reset", |probe| probe.reset());
+
+    recorder.check("three_taps.txt");
+}
@@ -0,0 +1,228 @@
+//! Exact MPSSE command streams sent for JTAG operations
+
+mod common;
+
+use common::SharedMock;
+use ftdi_playground::{JtagAdapter, JtagError, MockTap, ResetConfig, ResetPin};
+
+/// Attached adapter on a single TAP, with the written stream cleared
+fn adapter() -> (JtagAdapter<SharedMock>, SharedMock) {
+    let mock = SharedMock::new(vec![MockTap::new(Some(0x1000563d), 5).unwrap()]);
+    let mut adapter = JtagAdapter::from_io(mock.clone());
+    adapter.attach().unwrap();
+    adapter.flush().unwrap();
+    mock.take_written();
+    (adapter, mock)
+}
+
+#[test]
+fn reset_clocks_five_tms_high_then_one_low() {
+    let (mut adapter, mock) = adapter();
+
+    adapter.reset().unwrap();
+    adapter.flush().unwrap();
+    // One 6-bit TMS shift: 11111 into TEST-LOGIC-RESET, 0 to RUN-TEST/IDLE
+    assert_eq!(mock.take_written(), [0x4b, 5, 0b001_1111]);
+}
+
+#[test]
+fn reset_with_custom_clock_count() {
+    let (mut adapter, mock) = adapter();
+
+    adapter.set_reset_clocks(9);
+    adapter.reset().unwrap();
+    adapter.flush().unwrap();
+    // Ten bits split into shifts of 7 and 3
+    assert_eq!(mock.take_written(), [0x4b, 6, 0b111_1111, 0x4b, 2, 0b011]);
+}
+
+#[test]
+fn reset_is_flushed_unless_queued() {
+    let (mut adapter, mock) = adapter();
+
+    adapter.reset().unwrap();
+    assert_eq!(mock.take_written(), [0x4b, 5, 0b001_1111]);
+
+    adapter.set_queue_reset(true);
+    adapter.reset().unwrap();
+    assert!(mock.take_written().is_empty());
+    adapter.flush().unwrap();
+    assert_eq!(mock.take_written(), [0x4b, 5, 0b001_1111]);
+}
+
+#[test]
+fn queue_is_written_out_at_the_flush_threshold() {
+    let (mut adapter, mock) = adapter();
+    adapter.reset().unwrap();
+    mock.take_written();
+
+    adapter.shift_dr(&[0; 4], 32).unwrap();
+    assert!(mock.take_written().is_empty());
+    adapter.flush().unwrap();
+    mock.take_written();
+
+    adapter.set_flush_threshold(8);
+    adapter.shift_dr(&[0; 4], 32).unwrap();
+    assert!(!mock.take_written().is_empty());
+}
+
+#[test]
+fn auto_tune_sets_queue_and_read_sizes() {
+    let (mut adapter, _) = adapter();
+
+    let tuned = adapter.auto_tune().unwrap();
+    let chunk = tuned.flush_threshold;
+    assert!(chunk.is_power_of_two() && (512..=64 * 1024).contains(&chunk));
+    let read_size = tuned.min_read_size;
+    assert!(read_size.is_power_of_two() && (64..=chunk).contains(&read_size));
+    assert_eq!(adapter.flush_threshold(), chunk);
+    assert_eq!(adapter.min_read_size(), read_size);
+}
+
+#[test]
+fn auto_tune_only_shifts_through_the_loopback() {
+    let (mut adapter, mock) = adapter();
+
+    adapter.auto_tune().unwrap();
+    adapter.flush().unwrap();
+    let written = mock.take_written();
+    // Loopback on, byte shifts without TMS, loopback off
+    assert_eq!((written[0], written[written.len() - 1]), (0x84, 0x85));
+    let mut sizes = vec![];
+    let mut rest = &written[1..written.len() - 1];
+    while !rest.is_empty() {
+        assert_eq!(rest[0], 0x39);
+        let bytes = usize::from(u16::from_le_bytes([rest[1], rest[2]])) + 1;
+        sizes.push(bytes);
+        rest = &rest[3 + bytes..];
+    }
+    sizes.dedup();
+    assert_eq!(sizes, [1, 512, 4096, 16 * 1024]);
+    assert!(!adapter.is_loopback_enabled());
+}
+
+#[test]
+fn trst_without_a_pin_falls_back_to_tms_reset() {
+    let (mut adapter, mock) = adapter();
+
+    adapter.set_reset(Some(true), None).unwrap();
+    let written = mock.take_written();
+    // The TMS reset, then the unchanged GPIOs on ADBUS and ACBUS
+    assert_eq!(written[..3], [0x4b, 5, 0b001_1111]);
+    assert_eq!((written[3], written[6], written.len()), (0x80, 0x82, 9));
+
+    adapter
+        .set_reset_config(ResetConfig {
+            trst: Some(ResetPin {
+                pin: 4,
+                active_high: false,
+                open_drain: false,
+            }),
+            srst: None,
+        })
+        .unwrap();
+    adapter.set_reset(Some(true), None).unwrap();
+    let written = mock.take_written();
+    // Only GPIO 4 driven low
+    assert_eq!(written.len(), 6);
+    assert_eq!(written[0], 0x80);
+    assert_eq!((written[1] & 0x10, written[2] & 0x10), (0, 0x10));
+}
+
+#[test]
+fn adaptive_clock_makes_the_rtck_pin_an_input() {
+    let (mut adapter, mock) = adapter();
+    adapter
+        .set_reset_config(ResetConfig {
+            trst: None,
+            srst: Some(ResetPin {
+                pin: 9,
+                active_high: false,
+                open_drain: true,
+            }),
+        })
+        .unwrap();
+
+    let r = adapter.set_rtck_pin(3);
+    assert!(matches!(r, Err(JtagError::InvalidInput(_))), "{:?}", r);
+    let r = adapter.set_rtck_pin(9);
+    assert!(matches!(r, Err(JtagError::InvalidInput(_))), "{:?}", r);
+
+    adapter.set_rtck_pin(5).unwrap();
+    adapter.flush().unwrap();
+    mock.take_written();
+    adapter.set_adaptive_clock(true).unwrap();
+    adapter.flush().unwrap();
+    let written = mock.take_written();
+    assert_eq!((written[0], written[2] & 0x20), (0x80, 0));
+    assert_eq!(written[6..], [0x96]);
+
+    let r = adapter.gpio_set_direction(5, true);
+    assert!(matches!(r, Err(JtagError::InvalidInput(_))), "{:?}", r);
+    // GPIOL3 is free for other uses
+    adapter.gpio_set_direction(7, true).unwrap();
+}
+
+#[test]
+fn transfer_dr_of_200000_bytes() {
+    let (mut adapter, mock) = adapter();
+    adapter.reset().unwrap();
+    adapter.flush().unwrap();
+    mock.take_written();
+
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7 + i / 256) as u8).collect();
+    let r = adapter.transfer_dr(&data, data.len() * 8).unwrap();
+
+    // IDCODE after reset comes out first, then the data delayed by its 32 bits
+    assert_eq!(r.len(), data.len());
+    assert_eq!(r[..4], 0x1000563du32.to_le_bytes());
+    assert_eq!(r[4..], data[..data.len() - 4]);
+
+    // The 199999 full bytes go out in shifts of 65536, 65536, 65536 and 3391
+    let written = mock.take_written();
+    let chunk = |offset: usize| written[offset..offset + 3].to_vec();
+    assert_eq!(chunk(3), [0x39, 0xff, 0xff]);
+    assert_eq!(chunk(3 + 65539), [0x39, 0xff, 0xff]);
+    assert_eq!(chunk(3 + 2 * 65539), [0x39, 0xff, 0xff]);
+    assert_eq!(chunk(3 + 3 * 65539), [0x39, 0x3e, 0x0d]);
+}
+
+#[test]
+fn transfer_dr_of_8_bits() {
+    let (mut adapter, mock) = adapter();
+    adapter.reset().unwrap();
+    adapter.flush().unwrap();
+    mock.take_written();
+
+    let r = adapter.transfer_dr(&[0xa5], 8).unwrap();
+    assert_eq!(r, [0x3d]);
+    assert_eq!(
+        mock.take_written(),
+        [
+            // RUN-TEST/IDLE to SHIFT-DR
+            0x4b, 2, 0b001,
+            // Seven bits, then the last one on the way to EXIT1, UPDATE and IDLE
+            0x3b, 6, 0x25, 0x6b, 2, 0x83,
+        ]
+    );
+}
+
+#[test]
+fn transfer_dr_of_9_bits() {
+    let (mut adapter, mock) = adapter();
+    adapter.reset().unwrap();
+    adapter.flush().unwrap();
+    mock.take_written();
+
+    let r = adapter.transfer_dr(&[0xa5, 0x01], 9).unwrap();
+    assert_eq!(r, [0x3d, 0x00]);
+    assert_eq!(
+        mock.take_written(),
+        [
+            // RUN-TEST/IDLE to SHIFT-DR
+            0x4b, 2, 0b001,
+            // One full byte, then the ninth bit on the way to EXIT1, UPDATE and IDLE
+            0x39, 0, 0, 0xa5, 0x6b, 2, 0x83,
+        ]
+    );
+}
@@ -0,0 +1,87 @@
+//! `FtdiProbe` driven through probe-rs' `JtagAccess` on mock chains
+
+#![cfg(feature = "probe-rs")]
+
+mod common;
+
+use bitvec1::vec::BitVec;
+use common::three_taps;
+use ftdi_playground::{FtdiProbe, MockJtag};
+use probe_rs::config::ScanChainElement;
+use probe_rs::probe::{DebugProbe, JtagAccess, JtagSequence};
+
+const DTMCS: u32 = 0x0000_5071;
+
+fn bits(value: u32, len: usize) -> BitVec {
+    (0..len).map(|i| value >> i & 1 != 0).collect()
+}
+
+fn probe() -> FtdiProbe<MockJtag> {
+    let mut mock = MockJtag::new(three_taps().unwrap());
+    mock.set_register(0, 0x10, &DTMCS.to_le_bytes()).unwrap();
+    let mut probe = FtdiProbe::from_io(mock);
+    DebugProbe::attach(&mut probe).unwrap();
+    probe
+}
+
+fn sequence(tms: bool, capture: bool, len: usize) -> JtagSequence {
+    JtagSequence {
+        tdo_capture: capture,
+        tms,
+        data: bits(0, len),
+    }
+}
+
+#[test]
+fn scan_chain_reports_ir_lengths() {
+    let mut probe = probe();
+
+    let chain = JtagAccess::scan_chain(&mut probe).unwrap();
+    let irlens: Vec<_> = chain.iter().map(|tap| tap.ir_len()).collect();
+    assert_eq!(irlens, [5, 4, 4]);
+}
+
+#[test]
+fn reads_a_register_of_the_selected_target() {
+    let mut probe = probe();
+    JtagAccess::scan_chain(&mut probe).unwrap();
+    JtagAccess::select_target(&mut probe, 0).unwrap();
+
+    let dtmcs = JtagAccess::read_register(&mut probe, 0x10, 32).unwrap();
+    assert_eq!(dtmcs, bits(DTMCS, 32));
+}
+
+#[test]
+fn given_scan_chain_replaces_the_scanned_one() {
+    let mut probe = probe();
+    let chain = [5, 4, 4].map(|irlen| ScanChainElement {
+        name: None,
+        ir_len: Some(irlen),
+    });
+    JtagAccess::set_scan_chain(&mut probe, &chain).unwrap();
+    JtagAccess::select_target(&mut probe, 0).unwrap();
+
+    let dtmcs = JtagAccess::read_register(&mut probe, 0x10, 32).unwrap();
+    assert_eq!(dtmcs, bits(DTMCS, 32));
+    assert!(JtagAccess::select_target(&mut probe, 3).is_err());
+}
+
+#[test]
+fn raw_sequences_shift_the_idcode_and_keep_the_state_in_step() {
+    let mut probe = probe();
+    JtagAccess::tap_reset(&mut probe).unwrap();
+
+    // RUN-TEST/IDLE to SHIFT-DR, holding the IDCODEs loaded by the reset
+    JtagAccess::shift_raw_sequence(&mut probe, sequence(true, false, 1)).unwrap();
+    JtagAccess::shift_raw_sequence(&mut probe, sequence(false, false, 2)).unwrap();
+    let idcode = JtagAccess::shift_raw_sequence(&mut probe, sequence(false, true, 32)).unwrap();
+    assert_eq!(idcode, bits(0x1000563d, 32));
+    // EXIT1-DR, UPDATE-DR, RUN-TEST/IDLE
+    JtagAccess::shift_raw_sequence(&mut probe, sequence(true, false, 2)).unwrap();
+    JtagAccess::shift_raw_sequence(&mut probe, sequence(false, false, 1)).unwrap();
+
+    JtagAccess::scan_chain(&mut probe).unwrap();
+    JtagAccess::select_target(&mut probe, 0).unwrap();
+    let dtmcs = JtagAccess::read_register(&mut probe, 0x10, 32).unwrap();
+    assert_eq!(dtmcs, bits(DTMCS, 32));
+}
@@ -0,0 +1,252 @@
+//! RISC-V DMI access against a mock Debug Module behind the DTM
+
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use common::SharedMock;
+use ftdi_playground::{DmiBusyPolicy, FtdiProbe, JtagError, MockTap, RiscvDtm};
+
+/// Where the mock system bus memory starts
+const MEMORY_BASE: u32 = 0x8000_0000;
+
+/// `dtmcs` of a 0.13 DTM with 7 address bits and 1 idle cycle
+const DTMCS: u32 = 1 | 7 << 4 | 1 << 12;
+
+const DTMCS_DMIRESET: u32 = 1 << 16;
+
+/// `dmstatus` of a halted, authenticated 0.13 Debug Module
+const DMSTATUS: u32 = 0x0040_0382;
+
+const SBCS_SBBUSY: u32 = 1 << 21;
+const SBCS_SBREADONADDR: u32 = 1 << 20;
+const SBCS_SBAUTOINCREMENT: u32 = 1 << 16;
+const SBCS_SBREADONDATA: u32 = 1 << 15;
+const SBCS_SBERROR: u32 = 7 << 12;
+
+/// System bus half of a Debug Module, with memory at `MEMORY_BASE`
+#[derive(Debug, Default)]
+struct Dm {
+    memory: Vec<u32>,
+    sbcs: u32,
+    sbaddress: u32,
+    sbdata: u32,
+    /// `sbcs` reads still reporting `sbbusy`
+    busy: usize,
+    /// Bus reads done
+    reads: usize,
+    /// `abstractcs` reads still reporting `busy`
+    abstract_busy: usize,
+    abstractcs_reads: usize,
+    /// Operations still dropped with a busy `op`
+    op_busy: usize,
+    /// Only drop operations at this address
+    op_busy_addr: Option<u32>,
+    /// Busy `op` reported until `dtmcs.dmireset`, as in the spec
+    sticky_busy: bool,
+}
+
+impl Dm {
+    fn bus_read(&mut self) {
+        if self.sbcs & SBCS_SBERROR != 0 {
+            return;
+        }
+        let index = self.sbaddress.wrapping_sub(MEMORY_BASE) as usize / 4;
+        match self.memory.get(index) {
+            Some(&value) if self.sbaddress >= MEMORY_BASE => {
+                self.sbdata = value;
+                self.reads += 1;
+            }
+            // Bad address
+            _ => self.sbcs |= 2 << 12,
+        }
+        if self.sbcs & SBCS_SBAUTOINCREMENT != 0 {
+            self.sbaddress = self.sbaddress.wrapping_add(4);
+        }
+        self.busy = 1;
+    }
+
+    /// Execute a DMI operation, returning the data of the reply
+    fn access(&mut self, addr: u32, data: u32, op: u64) -> u32 {
+        match (op, addr) {
+            (1, 0x11) => DMSTATUS,
+            (1, 0x16) => {
+                self.abstractcs_reads += 1;
+                if self.abstract_busy > 0 {
+                    self.abstract_busy -= 1;
+                    1 << 12
+                } else {
+                    0
+                }
+            }
+            (1, 0x38) if self.busy > 0 => {
+                self.busy -= 1;
+                self.sbcs | SBCS_SBBUSY
+            }
+            (1, 0x38) => self.sbcs,
+            (1, 0x39) => self.sbaddress,
+            (1, 0x3c) => {
+                let value = self.sbdata;
+                if self.sbcs & SBCS_SBREADONDATA != 0 {
+                    self.bus_read();
+                }
+                value
+            }
+            (2, 0x38) => {
+                // sberror and sbbusyerror are write-1-to-clear
+                let sticky = self.sbcs & !data & SBCS_SBERROR;
+                self.sbcs = data & !SBCS_SBERROR & !(1 << 22) | sticky;
+                0
+            }
+            (2, 0x39) => {
+                self.sbaddress = data;
+                if self.sbcs & SBCS_SBREADONADDR != 0 {
+                    self.bus_read();
+                }
+                0
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// Execute a request latched into `dmi` and leave the reply there for the
+/// next scan to capture
+fn serve_dmi(jtag: &SharedMock, dm: &RefCell<Dm>) {
+    let mut raw = [0; 4];
+    raw.copy_from_slice(&jtag.register(0, 0x10).unwrap());
+    if u32::from_le_bytes(raw) & DTMCS_DMIRESET != 0 {
+        dm.borrow_mut().sticky_busy = false;
+        jtag.set_register(0, 0x10, &DTMCS.to_le_bytes());
+    }
+
+    let mut raw = [0; 8];
+    raw[..6].copy_from_slice(&jtag.register(0, 0x11).unwrap());
+    let dmi = u64::from_le_bytes(raw);
+    // Replies hold op 0 or the busy status 3, only reads and writes are requests
+    let op = dmi & 3;
+    if op != 1 && op != 2 {
+        return;
+    }
+    let addr = (dmi >> 34) as u32;
+    let mut dm = dm.borrow_mut();
+    let dropped = dm.op_busy > 0 && dm.op_busy_addr.is_none_or(|a| a == addr);
+    let reply = if dropped || dm.sticky_busy {
+        if dropped {
+            dm.op_busy -= 1;
+        }
+        dm.sticky_busy = true;
+        u64::from(addr) << 34 | 3
+    } else {
+        let data = dm.access(addr, (dmi >> 2) as u32, op);
+        u64::from(addr) << 34 | u64::from(data) << 2
+    };
+    jtag.set_register(0, 0x11, &reply.to_le_bytes()[..6]);
+}
+
+/// DTM with 7 address bits in front of a `Dm` holding `words` of memory
+fn dtm(words: usize) -> (RiscvDtm<SharedMock>, Rc<RefCell<Dm>>) {
+    let tap = MockTap::new(Some(0x1000563d), 5)
+        .unwrap()
+        .with_register(0x10, 32)
+        .unwrap()
+        .with_register(0x11, 41)
+        .unwrap();
+    let jtag = SharedMock::new(vec![tap]);
+    jtag.set_register(0, 0x10, &DTMCS.to_le_bytes());
+
+    let dm = Rc::new(RefCell::new(Dm {
+        memory: (0..words as u32)
+            .map(|i| 0x0101_0101 * i + 0x1000)
+            .collect(),
+        ..Dm::default()
+    }));
+    let served = dm.clone();
+    jtag.between_commands(move |jtag| serve_dmi(jtag, &served));
+    let mut probe = FtdiProbe::from_io(jtag);
+    probe.attach().unwrap();
+    probe.select_target(0x1000563d).unwrap();
+    (RiscvDtm::new(probe).unwrap(), dm)
+}
+
+#[test]
+fn block_read_autoincrements_from_one_address() {
+    let (mut dtm, dm) = dtm(16);
+
+    let words = dtm.read_memory_block32(MEMORY_BASE + 8, 6).unwrap();
+    assert_eq!(words, dm.borrow().memory[2..8]);
+    // Nothing fetched past the end, autoincrement off again
+    assert_eq!(dm.borrow().reads, 6);
+    assert_eq!(dm.borrow().sbcs & SBCS_SBAUTOINCREMENT, 0);
+
+    assert!(dtm.read_memory_block32(MEMORY_BASE, 0).unwrap().is_empty());
+    let r = dtm.read_memory_block32(MEMORY_BASE + 2, 1);
+    assert!(matches!(r, Err(JtagError::InvalidInput(_))), "{:?}", r);
+    let r = dtm.read_memory_block32(0xffff_fff0, 5);
+    assert!(matches!(r, Err(JtagError::InvalidInput(_))), "{:?}", r);
+}
+
+#[test]
+fn block_read_reports_the_failing_offset() {
+    let (mut dtm, dm) = dtm(16);
+
+    let r = dtm.read_memory_block32(MEMORY_BASE + 48, 8);
+    assert!(
+        matches!(
+            r,
+            Err(JtagError::SystemBusFailed {
+                offset: 16,
+                sberror: 2
+            })
+        ),
+        "{:?}",
+        r
+    );
+    // The error is cleared and autoincrement off again
+    assert_eq!(dm.borrow().sbcs & (SBCS_SBERROR | SBCS_SBAUTOINCREMENT), 0);
+
+    let words = dtm.read_memory_block32(MEMORY_BASE + 60, 1).unwrap();
+    assert_eq!(words, [dm.borrow().memory[15]]);
+}
+
+#[test]
+fn busy_policy_selects_the_busy_signal() {
+    let (mut dtm, dm) = dtm(0);
+
+    // A busy op is retried, abstractcs isn't looked at
+    dm.borrow_mut().abstract_busy = 3;
+    dm.borrow_mut().op_busy = 1;
+    assert_eq!(dtm.dmi_read(0x11).unwrap(), DMSTATUS);
+    assert_eq!(dm.borrow().abstractcs_reads, 0);
+
+    // abstractcs is polled until idle
+    dtm.set_busy_policy(DmiBusyPolicy::StatusRegister);
+    dm.borrow_mut().abstract_busy = 3;
+    assert_eq!(dtm.dmi_read(0x11).unwrap(), DMSTATUS);
+    assert_eq!(dm.borrow().abstractcs_reads, 4);
+    // A dropped read is cleared and issued again once abstractcs is idle
+    dm.borrow_mut().abstract_busy = 1;
+    dm.borrow_mut().op_busy = 1;
+    dm.borrow_mut().op_busy_addr = Some(0x11);
+    assert_eq!(dtm.dmi_read(0x11).unwrap(), DMSTATUS);
+    assert_eq!(dm.borrow().abstractcs_reads, 7);
+    dm.borrow_mut().op_busy_addr = None;
+    assert!(!dm.borrow().sticky_busy);
+    assert_eq!(dtm.dmi_read(0x11).unwrap(), DMSTATUS);
+    assert_eq!(dm.borrow().abstractcs_reads, 8);
+
+    dtm.set_busy_policy(DmiBusyPolicy::Both);
+    dm.borrow_mut().abstract_busy = 1;
+    dm.borrow_mut().op_busy = 1;
+    assert_eq!(dtm.dmi_read(0x11).unwrap(), DMSTATUS);
+    assert_eq!(dm.borrow().abstractcs_reads, 10);
+
+    dm.borrow_mut().abstract_busy = 100;
+    let r = dtm.dmi_read(0x11);
+    assert!(
+        matches!(r, Err(JtagError::DmiBusy { addr: 0x11 })),
+        "{:?}",
+        r
+    );
+}
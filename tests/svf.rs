@@ -0,0 +1,112 @@
+//! SVF playback against a mock chain
+
+mod common;
+
+use common::attach;
+use ftdi_playground::svf::{play_svf, SvfError};
+use ftdi_playground::MockTap;
+
+fn dtm() -> Vec<MockTap> {
+    vec![MockTap::new(Some(0x1000563d), 5).unwrap()]
+}
+
+#[test]
+fn scans_checking_tdo() {
+    let (mut probe, _) = attach(dtm());
+    let svf = "\
+        SIR 5 TDI (01);\n\
+        SDR 32 TDI (00000000) TDO (1000563d);\n\
+        SDR 32 TDI (00000000) TDO (1000563d) MASK (0fffffff);\n";
+    play_svf(&mut probe, svf.as_bytes()).unwrap();
+}
+
+#[test]
+fn reports_tdo_mismatch_line() {
+    let (mut probe, _) = attach(dtm());
+    let svf = "\
+        SIR 5 TDI (01);\n\
+        SDR 32 TDI (00000000) TDO (1000563d);\n\
+        SDR 32 TDI (00000000) TDO (1000563e);\n";
+    let r = play_svf(&mut probe, svf.as_bytes());
+    assert!(
+        matches!(r, Err(SvfError::TdoMismatch { line: 3, .. })),
+        "{:?}",
+        r
+    );
+}
+
+#[test]
+fn scan_after_drpause_captures_again() {
+    let (mut probe, _) = attach(dtm());
+    let svf = "\
+        SIR 5 TDI (01);\n\
+        ENDDR DRPAUSE;\n\
+        SDR 32 TDI (00000000) TDO (1000563d);\n\
+        SDR 32 TDI (00000000) TDO (1000563d);\n";
+    play_svf(&mut probe, svf.as_bytes()).unwrap();
+}
+
+#[test]
+fn scan_after_irpause_captures_again() {
+    let (mut probe, _) = attach(dtm());
+    let svf = "\
+        ENDIR IRPAUSE;\n\
+        SIR 5 TDI (1f) TDO (01);\n\
+        SIR 5 TDI (01) TDO (01);\n\
+        SDR 32 TDI (00000000) TDO (1000563d);\n";
+    play_svf(&mut probe, svf.as_bytes()).unwrap();
+}
+
+#[test]
+fn rejects_unstable_end_states() {
+    for svf in &["STATE DREXIT2;\n", "RUNTEST IREXIT2 10 TCK;\n"] {
+        let (mut probe, _) = attach(dtm());
+        let r = play_svf(&mut probe, svf.as_bytes());
+        assert!(
+            matches!(r, Err(SvfError::Syntax { line: 1, .. })),
+            "{:?}",
+            r
+        );
+    }
+}
+
+#[test]
+fn explicit_state_path_ends_in_its_last_state() {
+    let (mut probe, _) = attach(dtm());
+    let svf = "\
+        STATE RESET IDLE DRSELECT DRCAPTURE DREXIT1 DRPAUSE;\n\
+        SIR 5 TDI (01);\n\
+        SDR 32 TDI (00000000) TDO (1000563d);\n";
+    play_svf(&mut probe, svf.as_bytes()).unwrap();
+}
+
+#[test]
+fn rejects_invalid_numbers() {
+    let svfs = [
+        "RUNTEST -1 SEC;\n",
+        "RUNTEST NAN SEC;\n",
+        "RUNTEST INF SEC;\n",
+        "RUNTEST 1E300 SEC;\n",
+        "RUNTEST -5 TCK;\n",
+        "RUNTEST 1.5 TCK;\n",
+        "SDR 1E30 TDI (0);\n",
+        "FREQUENCY -1E6 HZ;\n",
+    ];
+    for svf in &svfs {
+        let (mut probe, _) = attach(dtm());
+        let r = play_svf(&mut probe, svf.as_bytes());
+        assert!(
+            matches!(r, Err(SvfError::Syntax { line: 1, .. })),
+            "{}: {:?}",
+            svf,
+            r
+        );
+    }
+}
+
+#[test]
+fn accepts_float_clock_counts() {
+    let (mut probe, _) = attach(dtm());
+    let svf = "RUNTEST IDLE 1.00E+02 TCK ENDSTATE IDLE;\n";
+    play_svf(&mut probe, svf.as_bytes()).unwrap();
+}